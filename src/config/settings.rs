@@ -0,0 +1,655 @@
+use std::{ collections::HashMap, fmt, path::Path, str::FromStr };
+
+use serde::Deserialize;
+
+use crate::scripts::{
+    auth::ServiceAccount,
+    sheets::{ GOOGLE_SHEETS_BASE_URL, GOOGLE_TOKEN_URL },
+};
+
+pub const LINNWORKS_AUTH_URL: &str = "https://api.linnworks.net/api/Auth/AuthorizeByApplication";
+
+/// Optional JSON file read before the environment, override its location with `APP_CONFIG`.
+const DEFAULT_CONFIG_FILE: &str = "config.json";
+
+/// LMDB map sizes must be a multiple of the OS page size.
+const PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read config file {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("cannot parse config file {path}: {source}")]
+    Parse {
+        path: String,
+        source: serde_json::Error,
+    },
+    #[error("invalid value for {name}: {value:?}")]
+    Env {
+        name: &'static str,
+        value: String,
+    },
+    #[error("invalid configuration: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+}
+
+/// Everything the service needs to start, built once in `main` and shared with
+/// the handlers through `web::Data<AppConfig>`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub db: DbConfig,
+    pub sheets: SheetsConfig,
+    pub outbox: OutboxConfig,
+    pub jobs: JobsConfig,
+    pub http: HttpConfig,
+    pub linnworks: LinnworksConfig,
+    pub marketplace_rules: MarketplaceRulesConfig,
+    pub matching: MatchingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DbConfig {
+    pub path: String,
+    /// Bytes, a multiple of the 4 KiB page size
+    pub map_size: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SheetsConfig {
+    pub spreadsheet_id: String,
+    /// Spreadsheet holding the reconciliation tab, `spreadsheet_id` when unset
+    pub reconciliation_spreadsheet_id: Option<String>,
+    /// Tab with one row per returned order (`Sheet1`)
+    pub orders_tab: String,
+    /// Tab with the reconciliation columns (`Sheet2`)
+    pub reconciliation_tab: String,
+    pub base_url: String,
+    pub token_url: String,
+    /// Key file, unused when the key is in the environment (`GOOGLE_SERVICE_ACCOUNT_JSON`)
+    pub service_account_path: String,
+    /// Serve the spreadsheet from memory instead of calling Google
+    pub fake: bool,
+    /// JSON file seeding the fake spreadsheet
+    pub fake_seed: Option<String>,
+    /// Marketplace names accepted on import, the ones the marketplace matchers recognise
+    /// when empty
+    pub marketplaces: Vec<String>,
+}
+
+/// Delivery of queued sheet writes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutboxConfig {
+    pub poll_interval_secs: u64,
+    pub batch_size: usize,
+    /// Failed deliveries before an entry is dead-lettered
+    pub max_attempts: u32,
+    /// Delay after the first failure, doubled on every further one
+    pub base_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+/// Outbound calls to Google and Linnworks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    /// Retries after the first attempt of a request
+    pub max_retries: u32,
+    /// Backoff ceiling of the first retry, doubled on every further one
+    pub base_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Longest `Retry-After` waited for before retrying anyway
+    pub max_retry_after_secs: u64,
+    /// Request budgets per API, spread evenly over the minute
+    pub sheets_per_minute: u32,
+    pub oauth_per_minute: u32,
+    pub linnworks_per_minute: u32,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout_secs: 30,
+            connect_timeout_secs: 10,
+            max_retries: 4,
+            base_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            max_retry_after_secs: 120,
+            sheets_per_minute: 60,
+            oauth_per_minute: 30,
+            linnworks_per_minute: 150,
+        }
+    }
+}
+
+/// Linnworks application credentials, see `AuthorizeByApplication`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct LinnworksConfig {
+    pub app_id: String,
+    pub app_secret: String,
+    /// Installation token of the application
+    pub api_token: String,
+    pub auth_url: String,
+    /// Session lifetime assumed when the auth response has no `TTL`
+    pub session_ttl_secs: i64,
+}
+
+impl fmt::Debug for LinnworksConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LinnworksConfig")
+            .field("app_id", &self.app_id)
+            .field("auth_url", &self.auth_url)
+            .field("session_ttl_secs", &self.session_ttl_secs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for LinnworksConfig {
+    fn default() -> Self {
+        LinnworksConfig {
+            app_id: String::new(),
+            app_secret: String::new(),
+            api_token: String::new(),
+            auth_url: LINNWORKS_AUTH_URL.to_string(),
+            session_ttl_secs: 30 * 60,
+        }
+    }
+}
+
+impl LinnworksConfig {
+    pub fn is_configured(&self) -> bool {
+        [&self.app_id, &self.app_secret, &self.api_token].iter().all(|v| !v.trim().is_empty())
+    }
+}
+
+/// Marketplace detection rules, the built-in Debenhams, Secret Sales and Matalan
+/// matchers when no file is given.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MarketplaceRulesConfig {
+    /// JSON rules file, see `marketplace_rules.example.json`
+    pub path: Option<String>,
+    /// How often the file is checked for changes, 0 to load it only at startup
+    pub reload_interval_secs: u64,
+}
+
+impl Default for MarketplaceRulesConfig {
+    fn default() -> Self {
+        MarketplaceRulesConfig { path: None, reload_interval_secs: 30 }
+    }
+}
+
+/// Scoring of returned SKUs against Linnworks order lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MatchingConfig {
+    /// Matches scoring at least this (0-100) are accepted, weaker ones wait for review
+    pub auto_accept_confidence: u8,
+    /// Most single-character edits a SKU may be away from a line and still be a candidate,
+    /// short SKUs are allowed fewer (one per four characters)
+    pub max_edit_distance: usize,
+    /// Returned SKU to the SKU Linnworks knows it by
+    pub sku_aliases: HashMap<String, String>,
+}
+
+impl Default for MatchingConfig {
+    fn default() -> Self {
+        MatchingConfig { auto_accept_confidence: 90, max_edit_distance: 2, sku_aliases: HashMap::new() }
+    }
+}
+
+/// Background jobs run by the scheduler, an interval of 0 leaves a job to manual runs.
+/// The outbox flush runs every `outbox.poll_interval_secs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    pub sheet_import_interval_secs: u64,
+    /// Off until Linnworks credentials are configured
+    pub linnworks_reconcile_interval_secs: u64,
+    /// Orders looked up per reconcile run
+    pub reconcile_batch_size: usize,
+    /// Runs kept per job
+    pub history_limit: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            sheet_import_interval_secs: 5 * 60,
+            linnworks_reconcile_interval_secs: 0,
+            reconcile_batch_size: 25,
+            history_limit: 50,
+        }
+    }
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        OutboxConfig {
+            poll_interval_secs: 2,
+            batch_size: 50,
+            max_attempts: 8,
+            base_backoff_secs: 5,
+            max_backoff_secs: 15 * 60,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { host: "127.0.0.1".to_string(), port: 8080 }
+    }
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        DbConfig { path: "./lmdb_data".to_string(), map_size: 1024 * 1024 * 1024 }
+    }
+}
+
+impl Default for SheetsConfig {
+    fn default() -> Self {
+        SheetsConfig {
+            spreadsheet_id: "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas".to_string(),
+            reconciliation_spreadsheet_id: None,
+            orders_tab: "Sheet1".to_string(),
+            reconciliation_tab: "Sheet2".to_string(),
+            base_url: GOOGLE_SHEETS_BASE_URL.to_string(),
+            token_url: GOOGLE_TOKEN_URL.to_string(),
+            service_account_path: "./src/service_account.json".to_string(),
+            fake: false,
+            fake_seed: None,
+            marketplaces: Vec::new(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Defaults, then the JSON config file, then environment variables (`.env` included).
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenvy::dotenv().ok();
+        let mut config = match std::env::var("APP_CONFIG") {
+            Ok(path) => AppConfig::from_file(&path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                AppConfig::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            Err(_) => AppConfig::default(),
+        };
+        config.apply_env(&|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_string(),
+            source,
+        })?;
+        serde_json::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_string(),
+            source,
+        })
+    }
+
+    /// Override settings from `env`, a variable lookup so tests need not touch the process environment.
+    fn apply_env(&mut self, env: &dyn Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        env_override(env, "SERVER_HOST", &mut self.server.host)?;
+        env_override(env, "SERVER_PORT", &mut self.server.port)?;
+        env_override(env, "DB_PATH", &mut self.db.path)?;
+        env_override(env, "DB_MAP_SIZE", &mut self.db.map_size)?;
+        env_override(env, "SPREADSHEET_ID", &mut self.sheets.spreadsheet_id)?;
+        env_override_opt(
+            env,
+            "RECONCILIATION_SPREADSHEET_ID",
+            &mut self.sheets.reconciliation_spreadsheet_id
+        );
+        env_override(env, "SHEETS_ORDERS_TAB", &mut self.sheets.orders_tab)?;
+        env_override(env, "SHEETS_RECONCILIATION_TAB", &mut self.sheets.reconciliation_tab)?;
+        env_override(env, "SHEETS_BASE_URL", &mut self.sheets.base_url)?;
+        env_override(env, "GOOGLE_TOKEN_URL", &mut self.sheets.token_url)?;
+        env_override(env, "SERVICE_ACCOUNT_PATH", &mut self.sheets.service_account_path)?;
+        if let Some(value) = env("SHEETS_FAKE") {
+            self.sheets.fake = match value.trim().to_lowercase().as_str() {
+                "1" | "true" | "yes" => true,
+                "0" | "false" | "no" | "" => false,
+                _ => {
+                    return Err(ConfigError::Env { name: "SHEETS_FAKE", value });
+                }
+            };
+        }
+        env_override_opt(env, "SHEETS_FAKE_SEED", &mut self.sheets.fake_seed);
+        if let Some(value) = env("SHEETS_MARKETPLACES") {
+            self.sheets.marketplaces = value
+                .split(',')
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty())
+                .collect();
+        }
+        env_override(env, "OUTBOX_POLL_INTERVAL_SECS", &mut self.outbox.poll_interval_secs)?;
+        env_override(env, "OUTBOX_BATCH_SIZE", &mut self.outbox.batch_size)?;
+        env_override(env, "OUTBOX_MAX_ATTEMPTS", &mut self.outbox.max_attempts)?;
+        env_override(env, "OUTBOX_BASE_BACKOFF_SECS", &mut self.outbox.base_backoff_secs)?;
+        env_override(env, "OUTBOX_MAX_BACKOFF_SECS", &mut self.outbox.max_backoff_secs)?;
+        env_override(env, "JOBS_SHEET_IMPORT_INTERVAL_SECS", &mut self.jobs.sheet_import_interval_secs)?;
+        env_override(
+            env,
+            "JOBS_LINNWORKS_RECONCILE_INTERVAL_SECS",
+            &mut self.jobs.linnworks_reconcile_interval_secs
+        )?;
+        env_override(env, "JOBS_RECONCILE_BATCH_SIZE", &mut self.jobs.reconcile_batch_size)?;
+        env_override(env, "JOBS_HISTORY_LIMIT", &mut self.jobs.history_limit)?;
+        env_override(env, "HTTP_TIMEOUT_SECS", &mut self.http.timeout_secs)?;
+        env_override(env, "HTTP_CONNECT_TIMEOUT_SECS", &mut self.http.connect_timeout_secs)?;
+        env_override(env, "HTTP_MAX_RETRIES", &mut self.http.max_retries)?;
+        env_override(env, "HTTP_BASE_BACKOFF_MS", &mut self.http.base_backoff_ms)?;
+        env_override(env, "HTTP_MAX_BACKOFF_MS", &mut self.http.max_backoff_ms)?;
+        env_override(env, "HTTP_MAX_RETRY_AFTER_SECS", &mut self.http.max_retry_after_secs)?;
+        env_override(env, "HTTP_SHEETS_PER_MINUTE", &mut self.http.sheets_per_minute)?;
+        env_override(env, "HTTP_OAUTH_PER_MINUTE", &mut self.http.oauth_per_minute)?;
+        env_override(env, "HTTP_LINNWORKS_PER_MINUTE", &mut self.http.linnworks_per_minute)?;
+        env_override(env, "LINNWORKS_APP_ID", &mut self.linnworks.app_id)?;
+        env_override(env, "LINNWORKS_APP_SECRET", &mut self.linnworks.app_secret)?;
+        env_override(env, "LINNWORKS_API_TOKEN", &mut self.linnworks.api_token)?;
+        env_override(env, "LINNWORKS_AUTH_URL", &mut self.linnworks.auth_url)?;
+        env_override(env, "LINNWORKS_SESSION_TTL_SECS", &mut self.linnworks.session_ttl_secs)?;
+        env_override(env, "MATCHING_AUTO_ACCEPT_CONFIDENCE", &mut self.matching.auto_accept_confidence)?;
+        env_override(env, "MATCHING_MAX_EDIT_DISTANCE", &mut self.matching.max_edit_distance)?;
+        env_override_opt(env, "MARKETPLACE_RULES_PATH", &mut self.marketplace_rules.path);
+        env_override(
+            env,
+            "MARKETPLACE_RULES_RELOAD_INTERVAL_SECS",
+            &mut self.marketplace_rules.reload_interval_secs
+        )?;
+        Ok(())
+    }
+
+    /// Report every problem at once rather than failing on the first.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.server.host.trim().is_empty() {
+            problems.push("server.host is empty".to_string());
+        }
+        if self.server.port == 0 {
+            problems.push("server.port must be non-zero".to_string());
+        }
+        if self.db.path.trim().is_empty() {
+            problems.push("db.path is empty".to_string());
+        }
+        if self.db.map_size < PAGE_SIZE || !self.db.map_size.is_multiple_of(PAGE_SIZE) {
+            problems.push(
+                format!(
+                    "db.map_size must be a positive multiple of {} bytes, got {}",
+                    PAGE_SIZE,
+                    self.db.map_size
+                )
+            );
+        }
+
+        let sheets = &self.sheets;
+        for (name, id) in [
+            ("sheets.spreadsheet_id", Some(&sheets.spreadsheet_id)),
+            ("sheets.reconciliation_spreadsheet_id", sheets.reconciliation_spreadsheet_id.as_ref()),
+        ] {
+            if let Some(id) = id && (id.trim().is_empty() || id.contains(['/', '?', '#', ' '])) {
+                problems.push(format!("{} is not a spreadsheet id: {:?}", name, id));
+            }
+        }
+        for (name, tab) in [
+            ("sheets.orders_tab", &sheets.orders_tab),
+            ("sheets.reconciliation_tab", &sheets.reconciliation_tab),
+        ] {
+            if tab.trim().is_empty() || tab.contains('!') {
+                problems.push(format!("{} is not a tab name: {:?}", name, tab));
+            }
+        }
+        if !sheets.fake {
+            for (name, url) in [
+                ("sheets.base_url", &sheets.base_url),
+                ("sheets.token_url", &sheets.token_url),
+            ] {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    problems.push(format!("{} must be an http(s) URL: {:?}", name, url));
+                }
+            }
+            if !ServiceAccount::in_env() && !Path::new(&sheets.service_account_path).is_file() {
+                problems.push(
+                    format!(
+                        "sheets.service_account_path {:?} does not exist (set SHEETS_FAKE=1 to run without Google)",
+                        sheets.service_account_path
+                    )
+                );
+            }
+        }
+        if let Some(seed) = &sheets.fake_seed && sheets.fake && !Path::new(seed).is_file() {
+            problems.push(format!("sheets.fake_seed {:?} does not exist", seed));
+        }
+
+        let outbox = &self.outbox;
+        if outbox.poll_interval_secs == 0 || outbox.batch_size == 0 || outbox.max_attempts == 0 {
+            problems.push(
+                "outbox.poll_interval_secs, batch_size and max_attempts must be non-zero".to_string()
+            );
+        }
+        if outbox.base_backoff_secs == 0 || outbox.base_backoff_secs > outbox.max_backoff_secs {
+            problems.push(
+                "outbox.base_backoff_secs must be non-zero and at most max_backoff_secs".to_string()
+            );
+        }
+        if self.jobs.reconcile_batch_size == 0 || self.jobs.history_limit == 0 {
+            problems.push(
+                "jobs.reconcile_batch_size and history_limit must be non-zero".to_string()
+            );
+        }
+
+        let http = &self.http;
+        if http.timeout_secs == 0 || http.connect_timeout_secs == 0 {
+            problems.push("http.timeout_secs and connect_timeout_secs must be non-zero".to_string());
+        }
+        if http.base_backoff_ms > http.max_backoff_ms {
+            problems.push("http.base_backoff_ms must be at most max_backoff_ms".to_string());
+        }
+        if http.sheets_per_minute == 0 || http.oauth_per_minute == 0 || http.linnworks_per_minute == 0 {
+            problems.push("http request budgets must be non-zero".to_string());
+        }
+
+        let linnworks = &self.linnworks;
+        if !linnworks.auth_url.starts_with("http://") && !linnworks.auth_url.starts_with("https://") {
+            problems.push(format!("linnworks.auth_url must be an http(s) URL: {:?}", linnworks.auth_url));
+        }
+        if linnworks.session_ttl_secs <= 0 {
+            problems.push("linnworks.session_ttl_secs must be positive".to_string());
+        }
+        if self.jobs.linnworks_reconcile_interval_secs > 0 && !linnworks.is_configured() {
+            problems.push(
+                "jobs.linnworks_reconcile_interval_secs needs LINNWORKS_APP_ID, LINNWORKS_APP_SECRET and LINNWORKS_API_TOKEN".to_string()
+            );
+        }
+
+        if self.matching.auto_accept_confidence > 100 {
+            problems.push("matching.auto_accept_confidence must be at most 100".to_string());
+        }
+        for (alias, sku) in &self.matching.sku_aliases {
+            if alias.trim().is_empty() || sku.trim().is_empty() {
+                problems.push(format!("matching.sku_aliases has an empty entry: {:?} -> {:?}", alias, sku));
+            }
+        }
+        if let Some(path) = &self.marketplace_rules.path && !Path::new(path).is_file() {
+            problems.push(format!("marketplace_rules.path {:?} does not exist", path));
+        }
+
+        if problems.is_empty() { Ok(()) } else { Err(ConfigError::Invalid(problems)) }
+    }
+}
+
+impl SheetsConfig {
+    /// `Sheet1!A:Z`, the full width of the orders tab.
+    pub fn orders_range(&self) -> String {
+        a1_range(&self.orders_tab, "A:Z")
+    }
+
+    /// Spreadsheet holding the reconciliation tab.
+    pub fn reconciliation_spreadsheet(&self) -> &str {
+        self.reconciliation_spreadsheet_id.as_deref().unwrap_or(&self.spreadsheet_id)
+    }
+
+    /// `Sheet2!A:Z`, the full width of the reconciliation tab.
+    pub fn reconciliation_range(&self) -> String {
+        a1_range(&self.reconciliation_tab, "A:Z")
+    }
+}
+
+/// Tab names with anything but letters, digits and underscores must be quoted in A1 notation.
+fn a1_range(tab: &str, cells: &str) -> String {
+    if tab.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}!{}", tab, cells)
+    } else {
+        format!("'{}'!{}", tab.replace('\'', "''"), cells)
+    }
+}
+
+fn env_override<T: FromStr>(
+    env: &dyn Fn(&str) -> Option<String>,
+    name: &'static str,
+    target: &mut T
+) -> Result<(), ConfigError> {
+    if let Some(value) = env(name) {
+        *target = value.trim().parse().map_err(|_| ConfigError::Env { name, value })?;
+    }
+    Ok(())
+}
+
+/// An empty value clears the setting.
+fn env_override_opt(
+    env: &dyn Fn(&str) -> Option<String>,
+    name: &'static str,
+    target: &mut Option<String>
+) {
+    if let Some(value) = env(name) {
+        let value = value.trim();
+        *target = if value.is_empty() { None } else { Some(value.to_string()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn env_of(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn config_file(dir: &tempfile::TempDir, json: &str) -> String {
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, json).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn file_overrides_defaults_and_env_overrides_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_file(
+            &dir,
+            r#"{ "server": { "port": 9000 }, "sheets": { "orders_tab": "Returns", "fake": true } }"#
+        );
+
+        let mut config = AppConfig::from_file(&path).unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.sheets.orders_tab, "Returns");
+        assert!(config.sheets.fake);
+        // anything the file leaves out keeps its default
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.sheets.reconciliation_tab, "Sheet2");
+
+        let env = env_of(&[
+            ("SERVER_PORT", " 9100 "),
+            ("SHEETS_FAKE", "no"),
+            ("RECONCILIATION_SPREADSHEET_ID", "recon-sheet"),
+        ]);
+        config.apply_env(&env).unwrap();
+        assert_eq!(config.server.port, 9100);
+        assert!(!config.sheets.fake);
+        assert_eq!(config.sheets.orders_tab, "Returns");
+        assert_eq!(config.sheets.reconciliation_spreadsheet_id.as_deref(), Some("recon-sheet"));
+
+        // an empty value clears an optional setting
+        config.apply_env(&env_of(&[("RECONCILIATION_SPREADSHEET_ID", "")])).unwrap();
+        assert_eq!(config.sheets.reconciliation_spreadsheet_id, None);
+
+        // a comma-separated list, every marketplace accepted by default
+        assert!(config.sheets.marketplaces.is_empty());
+        config.apply_env(&env_of(&[("SHEETS_MARKETPLACES", "Debenhams, Matalan,")])).unwrap();
+        assert_eq!(config.sheets.marketplaces, ["Debenhams", "Matalan"]);
+    }
+
+    #[test]
+    fn unparseable_env_values_are_rejected() {
+        let mut config = AppConfig::default();
+        let err = config.apply_env(&env_of(&[("SERVER_PORT", "eighty")])).unwrap_err();
+        assert!(matches!(err, ConfigError::Env { name: "SERVER_PORT", .. }), "{}", err);
+        let err = config.apply_env(&env_of(&[("SHEETS_FAKE", "maybe")])).unwrap_err();
+        assert!(matches!(err, ConfigError::Env { name: "SHEETS_FAKE", .. }), "{}", err);
+    }
+
+    #[test]
+    fn bad_config_files_name_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = config_file(&dir, "{ not json");
+        assert!(matches!(AppConfig::from_file(&path), Err(ConfigError::Parse { path: p, .. }) if p == path));
+        let missing = dir.path().join("missing.json");
+        assert!(matches!(AppConfig::from_file(missing.to_str().unwrap()), Err(ConfigError::Read { .. })));
+    }
+
+    #[test]
+    fn validate_reports_every_problem_at_once() {
+        let mut config = AppConfig::default();
+        config.server.port = 0;
+        config.db.map_size = 5000;
+        config.sheets.spreadsheet_id = "https://docs.google.com/x".to_string();
+        config.sheets.orders_tab = "Sheet1!A:Z".to_string();
+        config.sheets.service_account_path = "/nonexistent/service_account.json".to_string();
+
+        let Err(ConfigError::Invalid(problems)) = config.validate() else {
+            panic!("expected the configuration to be rejected");
+        };
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        for field in ["server.port", "db.map_size", "sheets.spreadsheet_id", "sheets.orders_tab", "service_account_path"] {
+            assert!(problems.iter().any(|p| p.contains(field)), "{} not reported in {:?}", field, problems);
+        }
+
+        // the fake needs no credentials
+        let mut fake = AppConfig::default();
+        fake.sheets.fake = true;
+        fake.sheets.service_account_path = "/nonexistent/service_account.json".to_string();
+        assert!(fake.validate().is_ok());
+    }
+
+    #[test]
+    fn tab_names_are_quoted_when_needed() {
+        assert_eq!(a1_range("Sheet1", "A:Z"), "Sheet1!A:Z");
+        assert_eq!(a1_range("Bob's returns", "A:Z"), "'Bob''s returns'!A:Z");
+    }
+}
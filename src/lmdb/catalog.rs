@@ -0,0 +1,335 @@
+use std::error::Error;
+
+use heed::RwTxn;
+
+use crate::{
+    lmdb::{ index::KEY_SEPARATOR, utils::DB },
+    schema::catalog::{
+        compact_sku,
+        CatalogEntry,
+        CatalogError,
+        CatalogImportReport,
+        CatalogImportRow,
+        InvalidCatalogEntry,
+        RejectedCatalogLine,
+        SkuAlias,
+    },
+};
+
+/// `""` for aliases used everywhere, otherwise the lowercased marketplace name.
+fn marketplace_key(marketplace: Option<&str>) -> String {
+    marketplace.map(|m| m.trim().to_lowercase()).unwrap_or_default()
+}
+
+fn alias_key(marketplace: Option<&str>, sku: &str) -> String {
+    format!("{}{}{}", marketplace_key(marketplace), KEY_SEPARATOR, compact_sku(sku))
+}
+
+/// Every alias index key of `entry`, its own SKU included.
+fn alias_keys(entry: &CatalogEntry) -> Vec<(String, Option<&SkuAlias>)> {
+    std::iter
+        ::once((alias_key(None, &entry.sku), None))
+        .chain(entry.aliases.iter().map(|a| (alias_key(a.marketplace.as_deref(), &a.sku), Some(a))))
+        .collect()
+}
+
+/// Trim the entry and drop blank marketplaces, rejecting empty SKUs.
+fn clean_entry(mut entry: CatalogEntry) -> Result<CatalogEntry, InvalidCatalogEntry> {
+    entry.sku = entry.sku.trim().to_string();
+    if compact_sku(&entry.sku).is_empty() {
+        return Err(InvalidCatalogEntry("sku is empty".to_string()));
+    }
+    entry.title = entry.title.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    for alias in &mut entry.aliases {
+        alias.sku = alias.sku.trim().to_string();
+        alias.marketplace = alias.marketplace
+            .as_ref()
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty());
+        if compact_sku(&alias.sku).is_empty() {
+            return Err(InvalidCatalogEntry(format!("alias of {} is empty", entry.sku)));
+        }
+    }
+    Ok(entry)
+}
+
+pub trait DBCatalog {
+    /// Every entry, ordered by SKU.
+    fn catalog_entries(&self) -> Result<Vec<CatalogEntry>, Box<dyn Error>>;
+    fn catalog_entry(&self, sku: &str) -> Result<Option<CatalogEntry>, Box<dyn Error>>;
+    /// Add a new entry, failing if the SKU or one of its aliases is taken.
+    fn insert_catalog_entry(&self, entry: CatalogEntry) -> Result<CatalogEntry, Box<dyn Error>>;
+    /// Replace the entry for `sku`, `None` when there is none.
+    fn replace_catalog_entry(
+        &self,
+        sku: &str,
+        entry: CatalogEntry
+    ) -> Result<Option<CatalogEntry>, Box<dyn Error>>;
+    fn delete_catalog_entry(&self, sku: &str) -> Result<Option<CatalogEntry>, Box<dyn Error>>;
+    /// Apply parsed CSV lines in one transaction. A line that can't be applied is rejected
+    /// without affecting the others.
+    fn import_catalog(&self, rows: Vec<CatalogImportRow>) -> Result<CatalogImportReport, Box<dyn Error>>;
+    /// The entry `sku` belongs to on `marketplace`, as its own SKU or an alias.
+    fn resolve_sku(&self, marketplace: &str, sku: &str) -> Result<Option<CatalogEntry>, Box<dyn Error>>;
+}
+
+impl DB {
+    /// Store `entry` in place of `previous`, moving its alias index keys along.
+    fn write_catalog_entry(
+        &self,
+        txn: &mut RwTxn,
+        mut entry: CatalogEntry,
+        previous: Option<&CatalogEntry>
+    ) -> Result<CatalogEntry, Box<dyn Error>> {
+        let key = compact_sku(&entry.sku);
+        let previous_key = previous.map(|p| compact_sku(&p.sku));
+        // renamed onto the SKU of another entry
+        if
+            previous_key.as_ref().is_some_and(|pk| *pk != key) &&
+            self.catalog_db.get(txn, &key)?.is_some()
+        {
+            return Err(CatalogError::Exists { sku: entry.sku.clone() }.into());
+        }
+        for (alias, source) in alias_keys(&entry) {
+            let Some(owner) = self.sku_alias_index.get(txn, &alias)? else {
+                continue;
+            };
+            if owner != key && Some(owner) != previous_key.as_deref() {
+                let owner_sku = self.catalog_db
+                    .get(txn, owner)?
+                    .map(|e| e.sku)
+                    .unwrap_or_else(|| owner.to_string());
+                return Err(match source {
+                    None => CatalogError::Exists { sku: entry.sku.clone() },
+                    Some(a) => CatalogError::AliasTaken {
+                        alias: a.sku.clone(),
+                        marketplace: a.marketplace.clone(),
+                        sku: owner_sku,
+                    },
+                }.into());
+            }
+        }
+        if let Some(previous) = previous {
+            for (alias, _) in alias_keys(previous) {
+                self.sku_alias_index.delete(txn, &alias)?;
+            }
+            self.catalog_db.delete(txn, &compact_sku(&previous.sku))?;
+        }
+        for (alias, _) in alias_keys(&entry) {
+            self.sku_alias_index.put(txn, &alias, &key)?;
+        }
+        entry.updated_at = chrono::Utc::now().to_rfc3339();
+        self.catalog_db.put(txn, &key, &entry)?;
+        Ok(entry)
+    }
+}
+
+impl DBCatalog for DB {
+    fn catalog_entries(&self) -> Result<Vec<CatalogEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let mut entries = Vec::new();
+        for result in self.catalog_db.iter(&txn)? {
+            entries.push(result?.1);
+        }
+        Ok(entries)
+    }
+
+    fn catalog_entry(&self, sku: &str) -> Result<Option<CatalogEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        Ok(self.catalog_db.get(&txn, &compact_sku(sku))?)
+    }
+
+    fn insert_catalog_entry(&self, entry: CatalogEntry) -> Result<CatalogEntry, Box<dyn Error>> {
+        let entry = clean_entry(entry)?;
+        let mut txn = self.env.write_txn()?;
+        if self.catalog_db.get(&txn, &compact_sku(&entry.sku))?.is_some() {
+            return Err(CatalogError::Exists { sku: entry.sku }.into());
+        }
+        let entry = self.write_catalog_entry(&mut txn, entry, None)?;
+        txn.commit()?;
+        Ok(entry)
+    }
+
+    fn replace_catalog_entry(
+        &self,
+        sku: &str,
+        entry: CatalogEntry
+    ) -> Result<Option<CatalogEntry>, Box<dyn Error>> {
+        let entry = clean_entry(entry)?;
+        let mut txn = self.env.write_txn()?;
+        let Some(previous) = self.catalog_db.get(&txn, &compact_sku(sku))? else {
+            return Ok(None);
+        };
+        let entry = self.write_catalog_entry(&mut txn, entry, Some(&previous))?;
+        txn.commit()?;
+        Ok(Some(entry))
+    }
+
+    fn delete_catalog_entry(&self, sku: &str) -> Result<Option<CatalogEntry>, Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        let Some(entry) = self.catalog_db.get(&txn, &compact_sku(sku))? else {
+            return Ok(None);
+        };
+        for (alias, _) in alias_keys(&entry) {
+            self.sku_alias_index.delete(&mut txn, &alias)?;
+        }
+        self.catalog_db.delete(&mut txn, &compact_sku(sku))?;
+        txn.commit()?;
+        Ok(Some(entry))
+    }
+
+    fn import_catalog(&self, rows: Vec<CatalogImportRow>) -> Result<CatalogImportReport, Box<dyn Error>> {
+        let mut report = CatalogImportReport::default();
+        let mut txn = self.env.write_txn()?;
+        for row in rows {
+            let previous = self.catalog_db.get(&txn, &compact_sku(&row.sku))?;
+            let mut entry = previous.clone().unwrap_or_else(|| CatalogEntry {
+                sku: row.sku.clone(),
+                title: None,
+                aliases: Vec::new(),
+                updated_at: String::new(),
+            });
+            if row.title.is_some() {
+                entry.title = row.title;
+            }
+            let mut alias_added = false;
+            if let Some(alias) = row.alias {
+                let key = alias_key(alias.marketplace.as_deref(), &alias.sku);
+                if !alias_keys(&entry).iter().any(|(k, _)| *k == key) {
+                    entry.aliases.push(alias);
+                    alias_added = true;
+                }
+            }
+            if previous.as_ref() == Some(&entry) {
+                continue;
+            }
+            let result = clean_entry(entry)
+                .map_err(Box::<dyn Error>::from)
+                .and_then(|entry| self.write_catalog_entry(&mut txn, entry, previous.as_ref()));
+            match result {
+                Ok(_) => {
+                    if previous.is_some() {
+                        report.updated += 1;
+                    } else {
+                        report.created += 1;
+                    }
+                    if alias_added {
+                        report.aliases_added += 1;
+                    }
+                }
+                Err(e) => report.rejected.push(RejectedCatalogLine { line: row.line, reason: e.to_string() }),
+            }
+        }
+        txn.commit()?;
+        Ok(report)
+    }
+
+    fn resolve_sku(&self, marketplace: &str, sku: &str) -> Result<Option<CatalogEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        for key in [alias_key(Some(marketplace), sku), alias_key(None, sku)] {
+            if let Some(owner) = self.sku_alias_index.get(&txn, &key)? {
+                return Ok(self.catalog_db.get(&txn, owner)?);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ lmdb::utils::tests::temp_db, schema::catalog::parse_catalog_csv };
+
+    fn entry(sku: &str, aliases: &[(Option<&str>, &str)]) -> CatalogEntry {
+        CatalogEntry {
+            sku: sku.to_string(),
+            title: None,
+            aliases: aliases
+                .iter()
+                .map(|(marketplace, sku)| SkuAlias {
+                    marketplace: marketplace.map(str::to_string),
+                    sku: sku.to_string(),
+                })
+                .collect(),
+            updated_at: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn rename_onto_an_existing_sku_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        db.insert_catalog_entry(entry("TSHIRT-RED", &[(Some("Debenhams"), "DEB-1")])).unwrap();
+        db.insert_catalog_entry(entry("JEANS", &[(Some("Matalan"), "MAT-7")])).unwrap();
+
+        let err = db.replace_catalog_entry("TSHIRT-RED", entry("jeans", &[])).unwrap_err();
+        assert_eq!(err.downcast_ref::<CatalogError>(), Some(&CatalogError::Exists { sku: "jeans".to_string() }));
+
+        // both entries and every alias are untouched
+        assert_eq!(db.resolve_sku("Debenhams", "DEB-1").unwrap().unwrap().sku, "TSHIRT-RED");
+        assert_eq!(db.resolve_sku("Matalan", "MAT-7").unwrap().unwrap().sku, "JEANS");
+        assert_eq!(db.catalog_entries().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rename_moves_the_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        db.insert_catalog_entry(entry("TSHIRT-RED", &[(Some("Debenhams"), "DEB-1")])).unwrap();
+
+        db.replace_catalog_entry("tshirt red", entry("TSHIRT-CRIMSON", &[(Some("Debenhams"), "DEB-1")]))
+            .unwrap()
+            .unwrap();
+        assert!(db.catalog_entry("TSHIRT-RED").unwrap().is_none());
+        assert!(db.resolve_sku("Debenhams", "TSHIRT-RED").unwrap().is_none());
+        assert_eq!(db.resolve_sku("Debenhams", "deb 1").unwrap().unwrap().sku, "TSHIRT-CRIMSON");
+        // a marketplace alias doesn't apply elsewhere
+        assert!(db.resolve_sku("Matalan", "DEB-1").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_marketplace_alias_beats_a_global_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        db.insert_catalog_entry(entry("TSHIRT-RED", &[(None, "TEE-1")])).unwrap();
+        db.insert_catalog_entry(entry("TSHIRT-BLUE", &[(Some("Debenhams"), "TEE-1")])).unwrap();
+
+        assert_eq!(db.resolve_sku("debenhams ", "tee 1").unwrap().unwrap().sku, "TSHIRT-BLUE");
+        assert_eq!(db.resolve_sku("Matalan", "TEE-1").unwrap().unwrap().sku, "TSHIRT-RED");
+        assert_eq!(db.resolve_sku("Debenhams", "TSHIRT-RED").unwrap().unwrap().sku, "TSHIRT-RED");
+        assert!(db.resolve_sku("Debenhams", "TEE-2").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn importing_the_same_csv_twice_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let csv = "sku,marketplace,alias,title\n\
+            TSHIRT-RED,Debenhams,DEB-1,Red tee\n\
+            TSHIRT-RED,,TEE-1,\n\
+            JEANS,,,\n";
+        let (rows, _) = parse_catalog_csv(csv).unwrap();
+
+        let report = db.import_catalog(rows.clone()).unwrap();
+        assert_eq!((report.created, report.updated, report.aliases_added), (2, 1, 2));
+        let entries = db.catalog_entries().unwrap();
+
+        assert_eq!(db.import_catalog(rows).unwrap(), CatalogImportReport::default());
+        assert_eq!(db.catalog_entries().unwrap(), entries);
+    }
+
+    #[tokio::test]
+    async fn an_import_line_taking_another_entrys_alias_is_rejected_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        db.insert_catalog_entry(entry("JEANS", &[(Some("Matalan"), "MAT-7")])).unwrap();
+        let (rows, _) = parse_catalog_csv("sku,marketplace,alias\nTSHIRT-RED,Matalan,MAT-7\nSOCKS,,\n").unwrap();
+
+        let report = db.import_catalog(rows).unwrap();
+        assert_eq!(report.created, 1);
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].line, 2);
+        assert!(db.catalog_entry("TSHIRT-RED").unwrap().is_none());
+        assert_eq!(db.resolve_sku("Matalan", "MAT-7").unwrap().unwrap().sku, "JEANS");
+    }
+}
@@ -0,0 +1,199 @@
+use std::error::Error;
+
+use heed::RwTxn;
+
+use crate::{
+    lmdb::{ index::KEY_SEPARATOR, utils::DB },
+    schema::{
+        history::{ diff_orders, ChangeAction, ChangeContext, FieldChange, HistoryEntry },
+        order::{ Order, RestoreError },
+        status::validate_transition,
+    },
+};
+
+const HISTORY_SEQ_KEY: &str = "history_seq";
+
+#[allow(dead_code)]
+pub trait DBHistory {
+    fn history(&self, id: String) -> Result<Vec<HistoryEntry>, Box<dyn Error>>;
+}
+
+fn history_key(id: &str, seq: u64) -> String {
+    format!("{}{}{:020}", id, KEY_SEPARATOR, seq)
+}
+
+impl DBHistory for DB {
+    fn history(&self, id: String) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        // deleted orders are no longer in the order_id index, their primary id still works
+        let id = self.resolve_id(&txn, &id)?.unwrap_or(id);
+        let prefix = format!("{}{}", id, KEY_SEPARATOR);
+        let mut entries = Vec::new();
+        for result in self.history_db.prefix_iter(&txn, &prefix)? {
+            let (_, entry) = result?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+impl DB {
+    /// Create or update an order and record what changed. Writes that change
+    /// nothing are skipped so re-imports don't bump `updated_at` or grow the history.
+    pub fn save_order(
+        &self,
+        txn: &mut RwTxn,
+        mut order: Order,
+        ctx: &ChangeContext
+    ) -> Result<Order, Box<dyn Error>> {
+        let previous = self.order_db.get(txn, &order.id)?;
+        if let Some(previous) = &previous {
+            validate_transition(&order.order_id, previous.status, order.status)?;
+            order.created_at = previous.created_at.clone();
+        }
+        let changes = diff_orders(previous.as_ref(), Some(&order));
+        if changes.is_empty() && let Some(previous) = previous {
+            return Ok(previous);
+        }
+
+        order.updated_at = chrono::Utc::now().to_rfc3339();
+        self.write_order(txn, &order)?;
+        let action = if previous.is_some() { ChangeAction::Update } else { ChangeAction::Create };
+        self.append_history(txn, &order, action, changes, ctx)?;
+        Ok(order)
+    }
+
+    /// Take an order out of the live set with all its index entries and sync state, and
+    /// record it. A soft delete keeps the record in `deleted_db` so it can be restored,
+    /// a hard one drops it, also when it was soft-deleted before.
+    pub fn discard_order(
+        &self,
+        txn: &mut RwTxn,
+        id: &str,
+        hard: bool,
+        ctx: &ChangeContext
+    ) -> Result<Option<Order>, Box<dyn Error>> {
+        let (order, changes) = match self.remove_order(txn, id)? {
+            Some(order) => {
+                let changes = diff_orders(Some(&order), None);
+                (order, changes)
+            }
+            // already soft-deleted, its fields were recorded back then
+            None if hard =>
+                match self.deleted_db.get(txn, &id.to_string())? {
+                    Some(order) => (order, Vec::new()),
+                    None => {
+                        return Ok(None);
+                    }
+                }
+            None => {
+                return Ok(None);
+            }
+        };
+        if hard {
+            self.deleted_db.delete(txn, &order.id)?;
+        } else {
+            self.deleted_db.put(txn, &order.id, &order)?;
+        }
+        self.forget_sync_state(txn, &order.id)?;
+        let action = if hard { ChangeAction::Purge } else { ChangeAction::Delete };
+        self.append_history(txn, &order, action, changes, ctx)?;
+        Ok(Some(order))
+    }
+
+    /// Bring a soft-deleted order back into the live set. `None` if there is no such order.
+    pub fn restore_order(
+        &self,
+        txn: &mut RwTxn,
+        id: &str,
+        ctx: &ChangeContext
+    ) -> Result<Option<Order>, Box<dyn Error>> {
+        let Some(mut order) = self.deleted_db.get(txn, &id.to_string())? else {
+            return Ok(None);
+        };
+        if let Some(other) = self.order_id_index.get(txn, &order.order_id)? {
+            return Err(
+                (RestoreError { order_id: order.order_id.clone(), id: other.to_string() }).into()
+            );
+        }
+        self.deleted_db.delete(txn, &order.id)?;
+        // its old row was cleared and may be reused, the next sync finds the new one
+        order.row_number = None;
+        order.updated_at = chrono::Utc::now().to_rfc3339();
+        self.write_order(txn, &order)?;
+        let changes = diff_orders(None, Some(&order));
+        self.append_history(txn, &order, ChangeAction::Restore, changes, ctx)?;
+        Ok(Some(order))
+    }
+
+    fn append_history(
+        &self,
+        txn: &mut RwTxn,
+        order: &Order,
+        action: ChangeAction,
+        changes: Vec<FieldChange>,
+        ctx: &ChangeContext
+    ) -> Result<(), Box<dyn Error>> {
+        let seq = self.meta_db
+            .get(txn, HISTORY_SEQ_KEY)?
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0) + 1;
+        self.meta_db.put(txn, HISTORY_SEQ_KEY, &seq.to_string())?;
+
+        let entry = HistoryEntry {
+            seq,
+            id: order.id.clone(),
+            order_id: order.order_id.clone(),
+            action,
+            claimed_actor: ctx.claimed_actor.clone(),
+            source: ctx.source,
+            at: chrono::Utc::now().to_rfc3339(),
+            changes,
+        };
+        self.history_db.put(txn, &history_key(&order.id, seq), &entry)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::{ utils::tests::temp_db, versioned::tests::expected_v1 },
+        schema::{ history::ChangeSource, status::OrderStatus },
+    };
+
+    #[tokio::test]
+    async fn saves_that_change_nothing_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let ctx = ChangeContext::sheet_sync();
+        let mut txn = db.env.write_txn().unwrap();
+        let created = db.save_order(&mut txn, expected_v1(), &ctx).unwrap();
+
+        // a re-import of the same row keeps the record and the history as they are
+        let again = Order { updated_at: "2030-01-01T00:00:00+00:00".to_string(), ..expected_v1() };
+        assert_eq!(db.save_order(&mut txn, again, &ctx).unwrap(), created);
+
+        let changed = Order {
+            status: Some(OrderStatus::Confirmed),
+            created_at: "2030-01-01T00:00:00+00:00".to_string(),
+            ..expected_v1()
+        };
+        let updated = db.save_order(&mut txn, changed, &ChangeContext::linnworks_reconcile()).unwrap();
+        assert_eq!(updated.created_at, created.created_at);
+        txn.commit().unwrap();
+
+        let history = db.history(created.id.clone()).unwrap();
+        let actions: Vec<ChangeAction> = history.iter().map(|e| e.action).collect();
+        assert_eq!(actions, [ChangeAction::Create, ChangeAction::Update]);
+        assert_eq!(history[1].source, ChangeSource::LinnworksReconcile);
+        assert_eq!(history[1].changes, vec![FieldChange {
+            field: "status".to_string(),
+            before: Some("matched".to_string()),
+            after: Some("confirmed".to_string()),
+        }]);
+        // by order_id as well
+        assert_eq!(db.history(created.order_id.clone()).unwrap(), history);
+    }
+}
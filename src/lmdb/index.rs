@@ -0,0 +1,120 @@
+use std::error::Error;
+
+use heed::{ RoTxn, RwTxn };
+
+use crate::{ lmdb::utils::DB, schema::order::{ normalize_date, Order } };
+
+/// Separator used in composite index keys, never present in ids or marketplace names.
+pub const KEY_SEPARATOR: char = '\u{1f}';
+
+pub fn marketplace_key(marketplace: &str, id: &str) -> String {
+    format!("{}{}{}", field_value(marketplace), KEY_SEPARATOR, id)
+}
+
+pub fn field_key(field: &str, value: &str, id: &str) -> String {
+    format!("{}{}{}{}{}", field, KEY_SEPARATOR, value, KEY_SEPARATOR, id)
+}
+
+/// Normalised form used for equality lookups in the field index.
+pub fn field_value(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// `(field, value)` pairs kept in the field index, used for filtering and sorting.
+/// Missing values are indexed as "" so sorting still visits every order.
+fn indexed_fields(order: &Order) -> Vec<(&'static str, String)> {
+    vec![
+        ("status", order.status.map(|v| v.as_str()).unwrap_or_default().to_string()),
+        ("match_type", order.match_type.map(|v| v.as_str()).unwrap_or_default().to_string()),
+        (
+            "manual_confirmation",
+            order.manual_confirmation.map(|v| v.as_str()).unwrap_or_default().to_string(),
+        ),
+        ("sku", field_value(order.returned_sku.as_deref().unwrap_or_default())),
+        ("boolean", order.boolean.to_string()),
+        ("date", normalize_date(&order.date).unwrap_or_default()),
+        ("created_at", order.created_at.clone()),
+        ("row_number", order.row_number.map(|n| format!("{:020}", n)).unwrap_or_default()),
+        ("order_id", order.order_id.clone())
+    ]
+}
+
+impl DB {
+    /// Resolve an `id` or an `order_id` to the primary key.
+    pub fn resolve_id(&self, txn: &RoTxn, key: &str) -> Result<Option<String>, Box<dyn Error>> {
+        if self.order_db.get(txn, &key.to_string())?.is_some() {
+            return Ok(Some(key.to_string()));
+        }
+        Ok(self.order_id_index.get(txn, key)?.map(|id| id.to_string()))
+    }
+
+    /// Store an order in the primary database and bring every index in line with it.
+    pub fn write_order(&self, txn: &mut RwTxn, order: &Order) -> Result<(), Box<dyn Error>> {
+        if let Some(previous) = self.order_db.get(txn, &order.id)? {
+            self.unindex_order(txn, &previous)?;
+        }
+        self.order_db.put(txn, &order.id, order)?;
+        self.index_order(txn, order)?;
+        Ok(())
+    }
+
+    /// Remove an order and all of its index entries.
+    pub fn remove_order(&self, txn: &mut RwTxn, id: &str) -> Result<Option<Order>, Box<dyn Error>> {
+        let previous = self.order_db.get(txn, &id.to_string())?;
+        if let Some(previous) = &previous {
+            self.unindex_order(txn, previous)?;
+            self.order_db.delete(txn, &previous.id)?;
+        }
+        Ok(previous)
+    }
+
+    fn index_order(&self, txn: &mut RwTxn, order: &Order) -> Result<(), Box<dyn Error>> {
+        if !order.order_id.is_empty() {
+            self.order_id_index.put(txn, &order.order_id, &order.id)?;
+        }
+        if let Some(row_number) = order.row_number {
+            self.row_number_index.put(txn, &(row_number as u64), &order.id)?;
+        }
+        self.marketplace_index.put(txn, &marketplace_key(&order.marketplace, &order.id), &())?;
+        for (field, value) in indexed_fields(order) {
+            self.field_index.put(txn, &field_key(field, &value, &order.id), &())?;
+        }
+        Ok(())
+    }
+
+    /// Drop every secondary index and rebuild it from the primary database.
+    pub fn reindex_all(&self, txn: &mut RwTxn) -> Result<(), Box<dyn Error>> {
+        self.order_id_index.clear(txn)?;
+        self.row_number_index.clear(txn)?;
+        self.marketplace_index.clear(txn)?;
+        self.field_index.clear(txn)?;
+        let mut orders = Vec::new();
+        for result in self.order_db.iter(txn)? {
+            let (_, order) = result?;
+            orders.push(order);
+        }
+        for order in &orders {
+            self.index_order(txn, order)?;
+        }
+        Ok(())
+    }
+
+    /// Only entries that still point at this order are removed, a row number or
+    /// order id may already have been taken over by another record.
+    fn unindex_order(&self, txn: &mut RwTxn, order: &Order) -> Result<(), Box<dyn Error>> {
+        if self.order_id_index.get(txn, &order.order_id)? == Some(order.id.as_str()) {
+            self.order_id_index.delete(txn, &order.order_id)?;
+        }
+        if let Some(row_number) = order.row_number {
+            let row_key = row_number as u64;
+            if self.row_number_index.get(txn, &row_key)? == Some(order.id.as_str()) {
+                self.row_number_index.delete(txn, &row_key)?;
+            }
+        }
+        self.marketplace_index.delete(txn, &marketplace_key(&order.marketplace, &order.id))?;
+        for (field, value) in indexed_fields(order) {
+            self.field_index.delete(txn, &field_key(field, &value, &order.id))?;
+        }
+        Ok(())
+    }
+}
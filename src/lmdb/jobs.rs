@@ -0,0 +1,130 @@
+use std::error::Error;
+
+use crate::{
+    lmdb::{ index::KEY_SEPARATOR, utils::DB },
+    schema::jobs::{ JobKind, JobRun },
+};
+
+const JOB_RUN_SEQ_KEY: &str = "job_run_seq";
+
+fn job_run_key(job: JobKind, seq: u64) -> String {
+    format!("{}{}{:020}", job.as_str(), KEY_SEPARATOR, seq)
+}
+
+fn job_cursor_key(job: JobKind) -> String {
+    format!("job_cursor:{}", job.as_str())
+}
+
+#[allow(dead_code)]
+pub trait DBJobs {
+    /// Store a finished run, numbering it, and keep only the latest `keep` runs of its job.
+    fn record_job_run(&self, run: JobRun, keep: usize) -> Result<JobRun, Box<dyn Error>>;
+    fn last_job_run(&self, job: JobKind) -> Result<Option<JobRun>, Box<dyn Error>>;
+    /// Recorded runs of a job, oldest first.
+    fn job_runs(&self, job: JobKind) -> Result<Vec<JobRun>, Box<dyn Error>>;
+    /// Where a job working through orders in batches left off.
+    fn job_cursor(&self, job: JobKind) -> Result<Option<String>, Box<dyn Error>>;
+    fn set_job_cursor(&self, job: JobKind, cursor: Option<&str>) -> Result<(), Box<dyn Error>>;
+}
+
+impl DBJobs for DB {
+    fn record_job_run(&self, mut run: JobRun, keep: usize) -> Result<JobRun, Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        run.seq = self.meta_db
+            .get(&txn, JOB_RUN_SEQ_KEY)?
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0) + 1;
+        self.meta_db.put(&mut txn, JOB_RUN_SEQ_KEY, &run.seq.to_string())?;
+        self.job_run_db.put(&mut txn, &job_run_key(run.job, run.seq), &run)?;
+
+        let prefix = format!("{}{}", run.job.as_str(), KEY_SEPARATOR);
+        let keys: Vec<String> = self.job_run_db
+            .prefix_iter(&txn, &prefix)?
+            .map(|r| r.map(|(k, _)| k.to_string()))
+            .collect::<Result<_, _>>()?;
+        for key in keys.iter().take(keys.len().saturating_sub(keep)) {
+            self.job_run_db.delete(&mut txn, key)?;
+        }
+        txn.commit()?;
+        Ok(run)
+    }
+
+    fn last_job_run(&self, job: JobKind) -> Result<Option<JobRun>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let prefix = format!("{}{}", job.as_str(), KEY_SEPARATOR);
+        match self.job_run_db.rev_prefix_iter(&txn, &prefix)?.next() {
+            Some(result) => Ok(Some(result?.1)),
+            None => Ok(None),
+        }
+    }
+
+    fn job_runs(&self, job: JobKind) -> Result<Vec<JobRun>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let prefix = format!("{}{}", job.as_str(), KEY_SEPARATOR);
+        let mut runs = Vec::new();
+        for result in self.job_run_db.prefix_iter(&txn, &prefix)? {
+            let (_, run) = result?;
+            runs.push(run);
+        }
+        Ok(runs)
+    }
+
+    fn job_cursor(&self, job: JobKind) -> Result<Option<String>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        Ok(self.meta_db.get(&txn, &job_cursor_key(job))?.map(|v| v.to_string()))
+    }
+
+    fn set_job_cursor(&self, job: JobKind, cursor: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        match cursor {
+            Some(cursor) => self.meta_db.put(&mut txn, &job_cursor_key(job), cursor)?,
+            None => {
+                self.meta_db.delete(&mut txn, &job_cursor_key(job))?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::utils::tests::temp_db,
+        schema::jobs::{ JobOutcome, JobTrigger },
+    };
+
+    fn run(job: JobKind, detail: &str) -> JobRun {
+        JobRun {
+            seq: 0,
+            job,
+            trigger: JobTrigger::Schedule,
+            started_at: "2024-03-04T09:00:00+00:00".to_string(),
+            finished_at: "2024-03-04T09:00:05+00:00".to_string(),
+            outcome: JobOutcome::Succeeded,
+            detail: detail.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_runs_of_each_job_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        db.record_job_run(run(JobKind::OutboxFlush, "flush"), 2).unwrap();
+        for detail in ["1", "2", "3"] {
+            db.record_job_run(run(JobKind::SheetImport, detail), 2).unwrap();
+        }
+
+        let kept: Vec<(u64, String)> = db
+            .job_runs(JobKind::SheetImport)
+            .unwrap()
+            .into_iter()
+            .map(|run| (run.seq, run.detail))
+            .collect();
+        assert_eq!(kept, [(3, "2".to_string()), (4, "3".to_string())]);
+        assert_eq!(db.last_job_run(JobKind::SheetImport).unwrap().map(|run| run.seq), Some(4));
+        // other jobs keep their own history
+        assert_eq!(db.job_runs(JobKind::OutboxFlush).unwrap().len(), 1);
+    }
+}
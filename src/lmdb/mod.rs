@@ -1,2 +1,10 @@
 pub mod order;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod index;
+pub mod versioned;
+pub mod history;
+pub mod query;
+pub mod sync;
+pub mod outbox;
+pub mod jobs;
+pub mod catalog;
@@ -1,165 +1,45 @@
 #[allow(unused_imports)]
 use std::error::Error;
-use std::fs;
 
 use crate::{
     lmdb::utils::DB,
-    schema::order::Order,
-    scripts::{ order::fetch_sheet_data, utils::get_or_generate_token },
+    schema::{ history::ChangeContext, order::Order, outbox::SheetOp },
 };
 #[allow(dead_code)]
 pub trait DBOrder {
-    async fn insert(&self, order: Order) -> Result<(), Box<dyn Error>>;
-    async fn insert_all(&self) -> Result<(), Box<dyn Error>>;
+    fn insert(&self, order: Order, ctx: &ChangeContext) -> Result<(), Box<dyn Error>>;
     fn get_single(&self, id: String) -> Result<Option<Order>, Box<dyn Error>>;
     fn get(&self) -> Result<Option<Vec<Order>>, Box<dyn Error>>;
-    fn put(&self, order: Order) -> Result<(), Box<dyn Error>>;
-    fn delete(&self, id: String) -> Result<(), Box<dyn Error>>;
-}
-#[derive(serde::Deserialize)]
-struct ServiceAccount {
-    client_email: String,
-    private_key: String,
+    fn put(&self, order: Order, ctx: &ChangeContext) -> Result<(), Box<dyn Error>>;
+    /// Soft delete keeps the order for `restore`, `hard` removes it for good. Either way its
+    /// sheet row is cleared. `None` if there is no such order. Live orders are found by
+    /// `id` or `order_id`, soft-deleted ones by `id` only.
+    fn delete(
+        &self,
+        id: String,
+        hard: bool,
+        ctx: &ChangeContext
+    ) -> Result<Option<Order>, Box<dyn Error>>;
+    /// `id` is the primary id, an `order_id` may have been reused since the delete.
+    fn restore(&self, id: String, ctx: &ChangeContext) -> Result<Option<Order>, Box<dyn Error>>;
+    fn deleted(&self) -> Result<Vec<Order>, Box<dyn Error>>;
 }
 
 impl DBOrder for DB {
-    async fn insert(&self, order: Order) -> Result<(), Box<dyn Error>> {
+    fn insert(&self, order: Order, ctx: &ChangeContext) -> Result<(), Box<dyn Error>> {
         println!("Inserting order: {:?}", &order);
-        let file_content = fs::read_to_string("./src/service_account.json")?;
-        println!("Service Account JSON: {}", file_content.len());
-        let sa: ServiceAccount = serde_json::from_str(&file_content)?;
-        let access_token = get_or_generate_token(&sa.client_email, &sa.private_key).await.unwrap();
-        let sheet1_value = fetch_sheet_data(
-            &access_token,
-            "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas",
-            "Sheet1"
-        ).await.unwrap(); // Now `Value`
-        // Fetch sheet2 row
-        let sheet2_value = fetch_sheet_data(
-            &access_token,
-            "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas",
-            "Sheet2"
-        ).await.unwrap();
-
-        // Convert JSON Value → Vec<String>
-        let sheet1_row: Vec<String> = sheet1_value["values"][0] // first row
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|v| v.as_str().unwrap_or_default().to_string())
-            .collect();
-
-        let sheet2_row: Vec<String> = sheet2_value["values"][0]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|v| v.as_str().unwrap_or_default().to_string())
-            .collect();
-        println!("Sheet1 Row: {:?}", &sheet1_row);
-        println!("Sheet2 Row: {:?}", &sheet2_row);
-        // Call your function
-        let i = 0; // for nothing
-        let order: Order = Order::from_sheets(i, &sheet1_row, Some(&sheet2_row)).await.unwrap();
-        println!("Order created from sheets: {:?}", &order);
         let mut txn = self.env.write_txn()?;
-        self.order_db.put(&mut txn, &order.id, &order)?;
-        self.order_db.put(&mut txn, &order.row_number.unwrap().to_string(), &order)?;
-        txn.commit()?;
-        Ok(())
-    }
-
-    async fn insert_all(&self) -> Result<(), Box<dyn Error>> {
-        let file_content = fs::read_to_string("./src/service_account.json")?;
-        let sa: ServiceAccount = serde_json::from_str(&file_content)?;
-        let access_token = get_or_generate_token(&sa.client_email, &sa.private_key).await?;
-
-        // Saara Sheet1 data lo
-        let sheet1_value = fetch_sheet_data(
-            &access_token,
-            "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas",
-            "Sheet1!A:Z" // full range
-        ).await?;
-
-        // Saara Sheet2 data lo
-        let sheet2_value = fetch_sheet_data(
-            &access_token,
-            "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas",
-            "Sheet2!A:Z"
-        ).await?;
-
-        // Vec<Vec<String>> me convert karo
-        let sheet1_rows: Vec<Vec<String>> = sheet1_value["values"]
-            .as_array()
-            .unwrap_or(&vec![])
-            .iter()
-            .map(|row|
-                row
-                    .as_array()
-                    .unwrap_or(&vec![])
-                    .iter()
-                    .map(|v| v.as_str().unwrap_or_default().to_string())
-                    .collect()
-            )
-            .collect();
-
-        let sheet2_rows: Vec<Vec<String>> = sheet2_value["values"]
-            .as_array()
-            .unwrap_or(&vec![])
-            .iter()
-            .map(|row|
-                row
-                    .as_array()
-                    .unwrap_or(&vec![])
-                    .iter()
-                    .map(|v| v.as_str().unwrap_or_default().to_string())
-                    .collect()
-            )
-            .collect();
-
-        println!("Total Sheet1 Rows: {}", sheet1_rows.len());
-        println!("Total Sheet2 Rows: {}", sheet2_rows.len());
-
-        let mut txn = self.env.write_txn()?;
-
-        // Row by row process karo (skip headers)
-        for i in 1..sheet1_rows.len() {
-            let sheet1_row = &sheet1_rows[i];
-            let sheet2_row = sheet2_rows.get(i); // same index ka row2
-            println!("Processing row {}: {:?}", i, sheet1_row);
-            println!("Sheet2 Row: {:?}", sheet2_row);
-            let order_opt = Order::from_sheets(
-                i,
-                sheet1_row,
-                sheet2_row.map(|r| r.as_slice())
-            ).await;
-            println!("Order from row {}: {:?}", i, &order_opt);
-            if let Some(order) = order_opt {
-                let order_id = order.order_id.clone();
-                let exists = self.order_db.get(&txn, &order_id)?;
-                if exists.is_none() {
-                    println!("Inserting new order: {}", order_id);
-                    // convert row_number to string for key
-                    let row_number = order.row_number.map(|v| v.to_string()).unwrap_or_default();
-                    println!("Inserting order from row {}: {:?}", i, &order);
-                    self.order_db.put(&mut txn, &order_id, &order)?;
-                    self.order_db.put(&mut txn, &row_number, &order)?;
-                } else {
-                    println!("Order already exists, updating: {}", order_id);
-                    self.order_db.put(&mut txn, &order_id, &order)?;
-                }
-            }
-        }
-
+        let order = self.save_order(&mut txn, order, ctx)?;
+        self.enqueue_sheet_op(&mut txn, SheetOp::AppendOrder { id: order.id })?;
         txn.commit()?;
         Ok(())
     }
 
     fn get_single(&self, id: String) -> Result<Option<Order>, Box<dyn Error>> {
         let txn = self.env.read_txn()?;
-        if let Some(order) = self.order_db.get(&txn, &id)? {
-            Ok(Some(order))
-        } else {
-            Ok(None)
+        match self.resolve_id(&txn, &id)? {
+            Some(id) => Ok(self.order_db.get(&txn, &id)?),
+            None => Ok(None),
         }
     }
 
@@ -177,19 +57,170 @@ impl DBOrder for DB {
         }
     }
 
-    fn put(&self, order: Order) -> Result<(), Box<dyn Error>> {
+    fn put(&self, mut order: Order, ctx: &ChangeContext) -> Result<(), Box<dyn Error>> {
         let mut txn = self.env.write_txn()?;
-        let row_number = order.row_number.map(|v| v.to_string()).unwrap_or_default();
-        self.order_db.put(&mut txn, &order.order_id, &order)?;
-        self.order_db.put(&mut txn, &row_number, &order)?;
+        if self.order_db.get(&txn, &order.id)?.is_none() {
+            // callers often only know the order_id, update the record it already points to
+            if let Some(existing_id) = self.order_id_index.get(&txn, &order.order_id)? {
+                order.id = existing_id.to_string();
+            }
+        }
+        let order = self.save_order(&mut txn, order, ctx)?;
+        self.enqueue_sheet_op(&mut txn, SheetOp::SyncOrder { id: order.id, order_id: order.order_id })?;
         txn.commit()?;
         Ok(())
     }
 
-    fn delete(&self, id: String) -> Result<(), Box<dyn Error>> {
+    fn delete(
+        &self,
+        id: String,
+        hard: bool,
+        ctx: &ChangeContext
+    ) -> Result<Option<Order>, Box<dyn Error>> {
         let mut txn = self.env.write_txn()?;
-        self.order_db.delete(&mut txn, &id)?;
+        let id = match self.resolve_id(&txn, &id)? {
+            Some(id) => id,
+            // purging a soft-deleted order, `discard_order` looks it up
+            None if hard => id,
+            None => {
+                return Ok(None);
+            }
+        };
+        let removed = self.discard_order(&mut txn, &id, hard, ctx)?;
+        if let Some(order) = &removed {
+            let op = SheetOp::ClearRow { id: order.id.clone(), order_id: order.order_id.clone() };
+            self.enqueue_sheet_op(&mut txn, op)?;
+        }
         txn.commit()?;
-        Ok(())
+        Ok(removed)
+    }
+
+    fn restore(&self, id: String, ctx: &ChangeContext) -> Result<Option<Order>, Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        let restored = self.restore_order(&mut txn, &id, ctx)?;
+        if let Some(order) = &restored {
+            self.enqueue_sheet_op(&mut txn, SheetOp::AppendOrder { id: order.id.clone() })?;
+        }
+        txn.commit()?;
+        Ok(restored)
+    }
+
+    fn deleted(&self) -> Result<Vec<Order>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let mut orders = Vec::new();
+        for result in self.deleted_db.iter(&txn)? {
+            let (_, order) = result?;
+            orders.push(order);
+        }
+        Ok(orders)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::{ outbox::DBOutbox, utils::tests::temp_db, versioned::tests::expected_v1 },
+        schema::{ history::ChangeSource, order::RestoreError },
+    };
+
+    fn ctx() -> ChangeContext {
+        ChangeContext { claimed_actor: "test".to_string(), source: ChangeSource::Http }
+    }
+
+    /// Queued sheet ops, the outbox emptied on the way.
+    fn drain_outbox(db: &DB) -> Vec<SheetOp> {
+        let entries = db.pending().unwrap();
+        for entry in &entries {
+            db.complete_entry(entry.seq).unwrap();
+        }
+        entries.into_iter().map(|entry| entry.op).collect()
+    }
+
+    fn index_sizes(db: &DB) -> (u64, u64, u64) {
+        let txn = db.env.read_txn().unwrap();
+        (
+            db.order_id_index.len(&txn).unwrap(),
+            db.marketplace_index.len(&txn).unwrap(),
+            db.field_index.len(&txn).unwrap(),
+        )
+    }
+
+    async fn db_with_order(dir: &tempfile::TempDir) -> (DB, Order) {
+        let db = temp_db(dir).await;
+        let order = expected_v1();
+        db.insert(order.clone(), &ctx()).unwrap();
+        drain_outbox(&db);
+        (db, order)
+    }
+
+    #[tokio::test]
+    async fn soft_delete_hides_the_order_and_clears_its_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, order) = db_with_order(&dir).await;
+
+        let deleted = db.delete(order.order_id.clone(), false, &ctx()).unwrap().unwrap();
+        assert_eq!(deleted.id, order.id);
+        assert_eq!(db.get_single(order.id.clone()).unwrap(), None);
+        assert_eq!(db.get_single(order.order_id.clone()).unwrap(), None);
+        assert_eq!(index_sizes(&db), (0, 0, 0));
+        assert_eq!(db.deleted().unwrap(), [deleted]);
+        assert_eq!(drain_outbox(&db), [SheetOp::ClearRow { id: order.id.clone(), order_id: order.order_id.clone() }]);
+        // not live any more, so a second soft delete finds nothing
+        assert_eq!(db.delete(order.id.clone(), false, &ctx()).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn restore_brings_the_order_back_and_appends_a_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, order) = db_with_order(&dir).await;
+        let indexed = index_sizes(&db);
+        db.delete(order.id.clone(), false, &ctx()).unwrap();
+        drain_outbox(&db);
+
+        // deleted orders are restored by their primary id only
+        assert_eq!(db.restore(order.order_id.clone(), &ctx()).unwrap(), None);
+        let restored = db.restore(order.id.clone(), &ctx()).unwrap().unwrap();
+        assert_eq!(restored.row_number, None);
+        assert_eq!(db.get_single(order.order_id.clone()).unwrap(), Some(restored));
+        assert_eq!(index_sizes(&db), indexed);
+        assert!(db.deleted().unwrap().is_empty());
+        assert_eq!(drain_outbox(&db), [SheetOp::AppendOrder { id: order.id.clone() }]);
+    }
+
+    #[tokio::test]
+    async fn restore_refuses_an_order_id_taken_in_the_meantime() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, order) = db_with_order(&dir).await;
+        db.delete(order.id.clone(), false, &ctx()).unwrap();
+        let reused = Order { id: "another-return".to_string(), ..expected_v1() };
+        db.insert(reused, &ctx()).unwrap();
+        drain_outbox(&db);
+
+        let err = db.restore(order.id.clone(), &ctx()).unwrap_err();
+        let err = err.downcast_ref::<RestoreError>().unwrap();
+        assert_eq!((err.order_id.as_str(), err.id.as_str()), ("104522", "another-return"));
+        // nothing changed
+        assert_eq!(db.deleted().unwrap().len(), 1);
+        assert!(drain_outbox(&db).is_empty());
+    }
+
+    #[tokio::test]
+    async fn hard_delete_removes_live_and_soft_deleted_orders() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, order) = db_with_order(&dir).await;
+
+        db.delete(order.order_id.clone(), true, &ctx()).unwrap().unwrap();
+        assert_eq!(db.get_single(order.id.clone()).unwrap(), None);
+        assert!(db.deleted().unwrap().is_empty());
+        assert_eq!(db.restore(order.id.clone(), &ctx()).unwrap(), None);
+        assert_eq!(drain_outbox(&db).len(), 1);
+
+        // purging an order that was soft-deleted before
+        db.insert(order.clone(), &ctx()).unwrap();
+        db.delete(order.id.clone(), false, &ctx()).unwrap();
+        assert_eq!(db.delete(order.order_id.clone(), true, &ctx()).unwrap(), None);
+        assert!(db.delete(order.id.clone(), true, &ctx()).unwrap().is_some());
+        assert!(db.deleted().unwrap().is_empty());
     }
 }
@@ -0,0 +1,251 @@
+use std::{ collections::{ HashMap, HashSet }, error::Error };
+
+use heed::RwTxn;
+
+use crate::{ lmdb::utils::DB, schema::outbox::{ OutboxEntry, SheetOp } };
+
+const OUTBOX_SEQ_KEY: &str = "outbox_seq";
+
+#[allow(dead_code)]
+pub trait DBOutbox {
+    /// Entries due at `now` (unix seconds), oldest first. An entry is held back while an
+    /// earlier entry for the same order is still waiting for its retry or dead-lettered.
+    fn due_entries(&self, now: i64, limit: usize) -> Result<Vec<OutboxEntry>, Box<dyn Error>>;
+    fn pending(&self) -> Result<Vec<OutboxEntry>, Box<dyn Error>>;
+    fn complete_entry(&self, seq: u64) -> Result<(), Box<dyn Error>>;
+    /// Record a failed delivery. Retried at `retry_at`, or moved to the dead letters when
+    /// `retry_at` is `None`. Returns whether the entry was dead-lettered.
+    fn fail_entry(
+        &self,
+        seq: u64,
+        error: String,
+        retry_at: Option<i64>
+    ) -> Result<bool, Box<dyn Error>>;
+    fn dead_letters(&self) -> Result<Vec<OutboxEntry>, Box<dyn Error>>;
+    /// Put a dead letter back in the outbox with a fresh attempt count.
+    fn retry_dead_letter(&self, seq: u64) -> Result<Option<OutboxEntry>, Box<dyn Error>>;
+    fn discard_dead_letter(&self, seq: u64) -> Result<Option<OutboxEntry>, Box<dyn Error>>;
+}
+
+impl DB {
+    /// Queue a sheet write in the caller's transaction, so it commits or rolls back
+    /// together with the order change it belongs to.
+    pub fn enqueue_sheet_op(&self, txn: &mut RwTxn, op: SheetOp) -> Result<u64, Box<dyn Error>> {
+        let seq = self.meta_db
+            .get(txn, OUTBOX_SEQ_KEY)?
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0) + 1;
+        self.meta_db.put(txn, OUTBOX_SEQ_KEY, &seq.to_string())?;
+        let entry = OutboxEntry {
+            seq,
+            op,
+            attempts: 0,
+            next_attempt_at: 0,
+            last_error: None,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        self.outbox_db.put(txn, &seq, &entry)?;
+        Ok(seq)
+    }
+}
+
+impl DBOutbox for DB {
+    fn due_entries(&self, now: i64, limit: usize) -> Result<Vec<OutboxEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        // lowest dead-lettered seq per order, nothing queued after it may overtake it
+        let mut dead: HashMap<String, u64> = HashMap::new();
+        for result in self.dead_letter_db.iter(&txn)? {
+            let (seq, entry) = result?;
+            dead.entry(entry.op.order_key().to_string())
+                .and_modify(|lowest| {
+                    *lowest = (*lowest).min(seq);
+                })
+                .or_insert(seq);
+        }
+
+        let mut waiting = HashSet::new();
+        let mut entries = Vec::new();
+        for result in self.outbox_db.iter(&txn)? {
+            let (seq, entry) = result?;
+            let key = entry.op.order_key();
+            if waiting.contains(key) || dead.get(key).is_some_and(|lowest| *lowest < seq) {
+                continue;
+            }
+            if entry.next_attempt_at > now {
+                waiting.insert(key.to_string());
+                continue;
+            }
+            entries.push(entry);
+            if entries.len() >= limit {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn pending(&self) -> Result<Vec<OutboxEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let mut entries = Vec::new();
+        for result in self.outbox_db.iter(&txn)? {
+            let (_, entry) = result?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn complete_entry(&self, seq: u64) -> Result<(), Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        self.outbox_db.delete(&mut txn, &seq)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn fail_entry(
+        &self,
+        seq: u64,
+        error: String,
+        retry_at: Option<i64>
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        let Some(mut entry) = self.outbox_db.get(&txn, &seq)? else {
+            return Ok(false);
+        };
+        entry.attempts += 1;
+        entry.last_error = Some(error);
+        let dead = match retry_at {
+            Some(at) => {
+                entry.next_attempt_at = at;
+                self.outbox_db.put(&mut txn, &seq, &entry)?;
+                false
+            }
+            None => {
+                self.outbox_db.delete(&mut txn, &seq)?;
+                self.dead_letter_db.put(&mut txn, &seq, &entry)?;
+                true
+            }
+        };
+        txn.commit()?;
+        Ok(dead)
+    }
+
+    fn dead_letters(&self) -> Result<Vec<OutboxEntry>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let mut entries = Vec::new();
+        for result in self.dead_letter_db.iter(&txn)? {
+            let (_, entry) = result?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn retry_dead_letter(&self, seq: u64) -> Result<Option<OutboxEntry>, Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        let Some(mut entry) = self.dead_letter_db.get(&txn, &seq)? else {
+            return Ok(None);
+        };
+        self.dead_letter_db.delete(&mut txn, &seq)?;
+        // keeps its seq, so it is delivered before later writes to the same order
+        entry.attempts = 0;
+        entry.next_attempt_at = 0;
+        self.outbox_db.put(&mut txn, &seq, &entry)?;
+        txn.commit()?;
+        Ok(Some(entry))
+    }
+
+    fn discard_dead_letter(&self, seq: u64) -> Result<Option<OutboxEntry>, Box<dyn Error>> {
+        let mut txn = self.env.write_txn()?;
+        let entry = self.dead_letter_db.get(&txn, &seq)?;
+        if entry.is_some() {
+            self.dead_letter_db.delete(&mut txn, &seq)?;
+        }
+        txn.commit()?;
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lmdb::utils::tests::temp_db;
+
+    fn sync(id: &str) -> SheetOp {
+        SheetOp::SyncOrder { id: id.to_string(), order_id: format!("order-{}", id) }
+    }
+
+    fn enqueue(db: &DB, ops: Vec<SheetOp>) {
+        let mut txn = db.env.write_txn().unwrap();
+        for op in ops {
+            db.enqueue_sheet_op(&mut txn, op).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    fn seqs(entries: &[OutboxEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.seq).collect()
+    }
+
+    #[tokio::test]
+    async fn entries_wait_for_earlier_ones_of_the_same_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        enqueue(&db, vec![
+            SheetOp::AppendOrder { id: "a".to_string() },
+            sync("a"),
+            SheetOp::AppendOrder { id: "b".to_string() },
+            sync("b"),
+            sync("c")
+        ]);
+        assert_eq!(seqs(&db.due_entries(0, 10).unwrap()), [1, 2, 3, 4, 5]);
+        assert_eq!(seqs(&db.due_entries(0, 2).unwrap()), [1, 2]);
+
+        // the append of "a" waits for its retry, its update may not overtake it
+        assert!(!db.fail_entry(1, "timeout".to_string(), Some(100)).unwrap());
+        assert_eq!(seqs(&db.due_entries(50, 10).unwrap()), [3, 4, 5]);
+        assert_eq!(seqs(&db.due_entries(100, 10).unwrap()), [1, 2, 3, 4, 5]);
+
+        // a dead letter holds back everything queued after it for the same order
+        assert!(db.fail_entry(3, "gone".to_string(), None).unwrap());
+        assert_eq!(seqs(&db.due_entries(100, 10).unwrap()), [1, 2, 5]);
+        db.discard_dead_letter(3).unwrap();
+        assert_eq!(seqs(&db.due_entries(100, 10).unwrap()), [1, 2, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn failures_retry_until_dead_lettered() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        enqueue(&db, vec![sync("a")]);
+
+        assert!(!db.fail_entry(1, "first".to_string(), Some(10)).unwrap());
+        let [entry] = db.pending().unwrap().try_into().unwrap();
+        assert_eq!((entry.attempts, entry.next_attempt_at), (1, 10));
+        assert_eq!(entry.last_error.as_deref(), Some("first"));
+
+        assert!(db.fail_entry(1, "second".to_string(), None).unwrap());
+        assert!(db.pending().unwrap().is_empty());
+        let [dead] = db.dead_letters().unwrap().try_into().unwrap();
+        assert_eq!((dead.seq, dead.attempts), (1, 2));
+        assert_eq!(dead.last_error.as_deref(), Some("second"));
+        // already gone
+        assert!(!db.fail_entry(1, "third".to_string(), None).unwrap());
+    }
+
+    #[tokio::test]
+    async fn dead_letters_can_be_retried_or_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        enqueue(&db, vec![sync("a"), sync("b")]);
+        db.fail_entry(1, "gone".to_string(), None).unwrap();
+        db.fail_entry(2, "gone".to_string(), None).unwrap();
+
+        let retried = db.retry_dead_letter(1).unwrap().unwrap();
+        assert_eq!((retried.seq, retried.attempts, retried.next_attempt_at), (1, 0, 0));
+        assert_eq!(seqs(&db.pending().unwrap()), [1]);
+        assert_eq!(seqs(&db.due_entries(0, 10).unwrap()), [1]);
+
+        assert_eq!(db.discard_dead_letter(2).unwrap().map(|e| e.seq), Some(2));
+        assert!(db.dead_letters().unwrap().is_empty());
+        assert_eq!(db.retry_dead_letter(2).unwrap(), None);
+        assert_eq!(db.discard_dead_letter(2).unwrap(), None);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{ collections::HashSet, error::Error, ops::Bound, str::FromStr };
+
+use heed::RoTxn;
+
+use crate::{
+    lmdb::{ index::{ field_value, KEY_SEPARATOR }, utils::DB },
+    schema::{
+        order::{ normalize_date, Order },
+        status::{ ManualConfirmation, MatchType, OrderStatus },
+        order_query::{
+            OrderPage,
+            OrderQuery,
+            QueryError,
+            SortDirection,
+            DEFAULT_PAGE_SIZE,
+            MAX_PAGE_SIZE,
+        },
+    },
+};
+
+#[allow(dead_code)]
+pub trait DBOrderQuery {
+    fn query(&self, query: &OrderQuery) -> Result<OrderPage, Box<dyn Error>>;
+    /// Orders without a match type that are still waiting to be matched,
+    /// in id order starting after `after`.
+    fn unmatched_orders(&self, after: Option<&str>, limit: usize) -> Result<Vec<Order>, Box<dyn Error>>;
+}
+
+/// Id part of a field index key, always the last segment.
+fn id_from_key(key: &str) -> &str {
+    key.rsplit(KEY_SEPARATOR).next().unwrap_or_default()
+}
+
+impl DB {
+    fn ids_with_prefix(&self, txn: &RoTxn, prefix: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+        let mut ids = HashSet::new();
+        for result in self.field_index.prefix_iter(txn, prefix)? {
+            let (key, _) = result?;
+            ids.insert(id_from_key(key).to_string());
+        }
+        Ok(ids)
+    }
+
+    fn ids_for_marketplace(
+        &self,
+        txn: &RoTxn,
+        marketplace: &str
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        let prefix = format!("{}{}", field_value(marketplace), KEY_SEPARATOR);
+        let mut ids = HashSet::new();
+        for result in self.marketplace_index.prefix_iter(txn, &prefix)? {
+            let (key, _) = result?;
+            ids.insert(id_from_key(key).to_string());
+        }
+        Ok(ids)
+    }
+
+    fn ids_in_date_range(
+        &self,
+        txn: &RoTxn,
+        from: Option<&str>,
+        to: Option<&str>
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        let prefix = format!("date{}", KEY_SEPARATOR);
+        let mut ids = HashSet::new();
+        for result in self.field_index.prefix_iter(txn, &prefix)? {
+            let (key, _) = result?;
+            let date = key[prefix.len()..].split(KEY_SEPARATOR).next().unwrap_or_default();
+            if date.is_empty() {
+                continue;
+            }
+            if from.is_some_and(|from| date < from) {
+                continue;
+            }
+            if to.is_some_and(|to| date > to) {
+                break;
+            }
+            ids.insert(id_from_key(key).to_string());
+        }
+        Ok(ids)
+    }
+
+    /// Ids matching every filter in `query`, `None` when nothing is filtered.
+    fn matching_ids(
+        &self,
+        txn: &RoTxn,
+        query: &OrderQuery
+    ) -> Result<Option<HashSet<String>>, Box<dyn Error>> {
+        let mut sets: Vec<HashSet<String>> = Vec::new();
+        if let Some(marketplace) = &query.marketplace {
+            sets.push(self.ids_for_marketplace(txn, marketplace)?);
+        }
+        let status = query.status.as_deref().map(OrderStatus::from_str).transpose();
+        let match_type = query.match_type.as_deref().map(MatchType::from_str).transpose();
+        let manual_confirmation = query.manual_confirmation
+            .as_deref()
+            .map(ManualConfirmation::from_str)
+            .transpose();
+        let equality = [
+            ("status", status.map_err(QueryError::from)?.map(|v| v.as_str().to_string())),
+            ("match_type", match_type.map_err(QueryError::from)?.map(|v| v.as_str().to_string())),
+            (
+                "manual_confirmation",
+                manual_confirmation.map_err(QueryError::from)?.map(|v| v.as_str().to_string()),
+            ),
+            ("sku", query.sku.clone()),
+            ("boolean", query.boolean.map(|b| b.to_string())),
+        ];
+        for (field, value) in equality {
+            if let Some(value) = value {
+                let prefix = format!(
+                    "{}{}{}{}",
+                    field,
+                    KEY_SEPARATOR,
+                    field_value(&value),
+                    KEY_SEPARATOR
+                );
+                sets.push(self.ids_with_prefix(txn, &prefix)?);
+            }
+        }
+        if query.date_from.is_some() || query.date_to.is_some() {
+            let from = query.date_from.as_deref().map(|d| normalize_date(d).ok_or(d));
+            let to = query.date_to.as_deref().map(|d| normalize_date(d).ok_or(d));
+            let from = from.transpose().map_err(|d| QueryError::Date(d.to_string()))?;
+            let to = to.transpose().map_err(|d| QueryError::Date(d.to_string()))?;
+            sets.push(self.ids_in_date_range(txn, from.as_deref(), to.as_deref())?);
+        }
+
+        // intersect starting from the smallest set
+        sets.sort_by_key(|set| set.len());
+        let mut sets = sets.into_iter();
+        let Some(mut ids) = sets.next() else {
+            return Ok(None);
+        };
+        for set in sets {
+            ids.retain(|id| set.contains(id));
+        }
+        Ok(Some(ids))
+    }
+}
+
+impl DBOrderQuery for DB {
+    /// Walks the field index of the sort column from the cursor on, loading only the
+    /// orders on the requested page. The cursor is the hex-encoded index key of the
+    /// last item. `total` comes from the filtered id set, or the order count when
+    /// nothing is filtered, so it never needs a walk of its own.
+    fn query(&self, query: &OrderQuery) -> Result<OrderPage, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let sort = query.sort.unwrap_or_default();
+        let direction = query.direction.unwrap_or_default();
+        let prefix = format!("{}{}", sort.field(), KEY_SEPARATOR);
+        let cursor = match &query.cursor {
+            Some(cursor) => {
+                let bytes = hex::decode(cursor).map_err(|_| QueryError::Cursor)?;
+                let key = String::from_utf8(bytes).map_err(|_| QueryError::Cursor)?;
+                // a cursor from another sort column would start the walk anywhere
+                if !key.starts_with(&prefix) {
+                    return Err(QueryError::Cursor.into());
+                }
+                Some(key)
+            }
+            None => None,
+        };
+        let candidates = self.matching_ids(&txn, query)?;
+        let total = match &candidates {
+            Some(ids) => ids.len(),
+            None => self.order_db.len(&txn)? as usize,
+        };
+
+        let keys: Box<dyn Iterator<Item = heed::Result<(&str, ())>>> = match
+            (direction, cursor.as_deref())
+        {
+            (SortDirection::Asc, None) => Box::new(self.field_index.prefix_iter(&txn, &prefix)?),
+            (SortDirection::Desc, None) => {
+                Box::new(self.field_index.rev_prefix_iter(&txn, &prefix)?)
+            }
+            (SortDirection::Asc, Some(cursor)) => {
+                let range = (Bound::Excluded(cursor), Bound::Unbounded);
+                Box::new(self.field_index.range(&txn, &range)?)
+            }
+            (SortDirection::Desc, Some(cursor)) => {
+                let range = (Bound::Included(prefix.as_str()), Bound::Excluded(cursor));
+                Box::new(self.field_index.rev_range(&txn, &range)?)
+            }
+        };
+
+        let mut items = Vec::new();
+        let mut last_key: Option<String> = None;
+        let mut next_cursor = None;
+        for result in keys {
+            let (key, _) = result?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let id = id_from_key(key);
+            if candidates.as_ref().is_some_and(|ids| !ids.contains(id)) {
+                continue;
+            }
+            if items.len() == limit {
+                // there is at least one more match, the page needs a cursor
+                next_cursor = last_key.map(hex::encode);
+                break;
+            }
+            if let Some(order) = self.order_db.get(&txn, &id.to_string())? {
+                items.push(order);
+                last_key = Some(key.to_string());
+            }
+        }
+
+        Ok(OrderPage { items, total, next_cursor })
+    }
+
+    fn unmatched_orders(&self, after: Option<&str>, limit: usize) -> Result<Vec<Order>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let prefix = format!("match_type{}{}", KEY_SEPARATOR, KEY_SEPARATOR);
+        let mut orders = Vec::new();
+        for result in self.field_index.prefix_iter(&txn, &prefix)? {
+            let (key, _) = result?;
+            let id = id_from_key(key);
+            if after.is_some_and(|after| id <= after) {
+                continue;
+            }
+            let Some(order) = self.order_db.get(&txn, &id.to_string())? else {
+                continue;
+            };
+            if order.status.is_none_or(|s| s == OrderStatus::Received) {
+                orders.push(order);
+                if orders.len() == limit {
+                    break;
+                }
+            }
+        }
+        Ok(orders)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::{ utils::tests::temp_db, versioned::tests::expected_v1 },
+        schema::{ history::ChangeContext, order::Order, order_query::OrderSort },
+    };
+
+    /// Five orders, rows 1..=5, returned on consecutive days, alternating marketplaces.
+    async fn seeded_db(dir: &tempfile::TempDir) -> DB {
+        let db = temp_db(dir).await;
+        let mut txn = db.env.write_txn().unwrap();
+        for n in 1..=5 {
+            let order = Order {
+                id: format!("id-{}", n),
+                order_id: format!("ORD-{}", n),
+                row_number: Some(n),
+                date: format!("2024-03-0{}", n),
+                marketplace: (if n % 2 == 0 { "Debenhams" } else { "Next" }).to_string(),
+                ..expected_v1()
+            };
+            db.save_order(&mut txn, order, &ChangeContext::sheet_sync()).unwrap();
+        }
+        txn.commit().unwrap();
+        db
+    }
+
+    fn ids(page: &OrderPage) -> Vec<&str> {
+        page.items.iter().map(|order| order.id.as_str()).collect()
+    }
+
+    /// Follow `next_cursor` until the last page, collecting every id on the way.
+    fn walk(db: &DB, mut query: OrderQuery) -> Vec<String> {
+        let mut seen = Vec::new();
+        loop {
+            let page = db.query(&query).unwrap();
+            assert_eq!(page.total, 5);
+            seen.extend(page.items.iter().map(|order| order.id.clone()));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return seen,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cursor_pages_continue_where_the_last_one_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = seeded_db(&dir).await;
+
+        let asc = OrderQuery { limit: Some(2), ..OrderQuery::default() };
+        assert_eq!(walk(&db, asc), ["id-1", "id-2", "id-3", "id-4", "id-5"]);
+
+        let desc = OrderQuery {
+            limit: Some(2),
+            sort: Some(OrderSort::Date),
+            direction: Some(SortDirection::Desc),
+            ..OrderQuery::default()
+        };
+        assert_eq!(walk(&db, desc), ["id-5", "id-4", "id-3", "id-2", "id-1"]);
+
+        // an exactly full last page has no cursor
+        let page = db.query(&OrderQuery { limit: Some(5), ..OrderQuery::default() }).unwrap();
+        assert_eq!(page.items.len(), 5);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn date_range_is_inclusive_on_both_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = seeded_db(&dir).await;
+
+        let query = OrderQuery {
+            date_from: Some("2024-03-02".to_string()),
+            date_to: Some("2024-03-04".to_string()),
+            ..OrderQuery::default()
+        };
+        let page = db.query(&query).unwrap();
+        assert_eq!(ids(&page), ["id-2", "id-3", "id-4"]);
+        assert_eq!(page.total, 3);
+
+        let query = OrderQuery { date_from: Some("not a date".to_string()), ..OrderQuery::default() };
+        let error = db.query(&query).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(QueryError::Date(_))));
+    }
+
+    #[tokio::test]
+    async fn marketplace_filter_ignores_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = seeded_db(&dir).await;
+
+        for marketplace in ["Debenhams", "debenhams", " DEBENHAMS "] {
+            let query = OrderQuery { marketplace: Some(marketplace.to_string()), ..OrderQuery::default() };
+            let page = db.query(&query).unwrap();
+            assert_eq!(ids(&page), ["id-2", "id-4"], "{:?}", marketplace);
+            assert_eq!(page.total, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_cursors_are_query_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = seeded_db(&dir).await;
+
+        let first = db.query(&OrderQuery { limit: Some(1), ..OrderQuery::default() }).unwrap();
+        let from_other_sort = OrderQuery {
+            sort: Some(OrderSort::Date),
+            cursor: first.next_cursor,
+            ..OrderQuery::default()
+        };
+        for query in [
+            OrderQuery { cursor: Some("zz".to_string()), ..OrderQuery::default() },
+            OrderQuery { cursor: Some(hex::encode([0xff, 0xfe])), ..OrderQuery::default() },
+            from_other_sort,
+        ] {
+            let error = db.query(&query).unwrap_err();
+            assert!(matches!(error.downcast_ref(), Some(QueryError::Cursor)), "{:?}", query);
+        }
+    }
+}
@@ -0,0 +1,1522 @@
+use std::{ collections::{ HashMap, HashSet }, error::Error };
+
+use heed::{ RoTxn, RwTxn };
+use sha2::{ Digest, Sha256 };
+
+use crate::{
+    config::settings::SheetsConfig,
+    lmdb::{ index::KEY_SEPARATOR, utils::DB },
+    schema::{
+        history::{ diff_orders, ChangeContext, FieldChange },
+        order::{ Order, RowError },
+        status::{ validate_transition, TransitionError },
+        sync::{
+            OrderDiff,
+            RejectedRow,
+            RowState,
+            SheetRowDiff,
+            SyncBaseline,
+            SyncConflict,
+            SyncPreview,
+            SyncReport,
+            SyncSide,
+        },
+    },
+    scripts::{
+        columns::{
+            merged_columns,
+            Column,
+            ColumnError,
+            HeaderMap,
+            MAX_COLUMNS,
+            ROW_ID_HEADER,
+            SHEET1_COLUMNS,
+            SHEET2_COLUMNS,
+        },
+        marketplaces::MatcherRegistry,
+        sheets::{ rows_from_value, SheetsClient },
+    },
+};
+
+#[allow(dead_code)]
+pub trait DBSync {
+    /// Three-way sync of Sheet1, then of the reconciliation tab, against the DB using the
+    /// baseline stored at the last sync. `only_order_id` limits the run to the rows of one order.
+    async fn sync_sheet<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        matchers: &MatcherRegistry,
+        only_order_id: Option<&str>
+    ) -> Result<SyncReport, Box<dyn Error>>;
+    /// What a full `sync_sheet` would do, without writing to the DB or the sheet.
+    async fn preview_sync<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        matchers: &MatcherRegistry
+    ) -> Result<SyncPreview, Box<dyn Error>>;
+    fn conflicts(&self) -> Result<Vec<SyncConflict>, Box<dyn Error>>;
+    /// Settle a conflict by copying one side over the other. `None` if there is no such conflict.
+    async fn resolve_conflict<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        id: &str,
+        field: &str,
+        keep: SyncSide,
+        ctx: &ChangeContext
+    ) -> Result<Option<SyncConflict>, Box<dyn Error>>;
+}
+
+/// What to do with one sheet row, worked out before anything is written.
+struct RowPlan {
+    row_number: usize,
+    from_sheet: Order,
+    /// Primary id of the order the row belongs to, `None` for rows the DB doesn't know
+    existing_id: Option<String>,
+    pulls: Vec<(Column, String)>,
+    pushes: Vec<(Column, String)>,
+    /// Fields where both sides already agree
+    settled: Vec<(Column, String)>,
+    conflicts: Vec<SyncConflict>,
+    /// The row has no id cell yet and gets the order's id written to it
+    stamp_id: bool,
+    hash: String,
+}
+
+/// What to do with the reconciliation row of one order.
+struct ReconciliationPlan {
+    id: String,
+    /// `None` when the tab has no row for the order yet
+    row_number: Option<usize>,
+    /// Row to add when there is none
+    append: Option<Vec<String>>,
+    /// Hash of the existing row
+    hash: Option<String>,
+    pulls: Vec<(Column, String)>,
+    pushes: Vec<(Column, String)>,
+    settled: Vec<(Column, String)>,
+    /// Mirrored cells that differ from the order, written but never part of the baseline
+    mirrors: Vec<(Column, String)>,
+    conflicts: Vec<SyncConflict>,
+}
+
+/// Outcome of comparing the merged columns of one row with the order.
+#[derive(Default)]
+struct Merge {
+    pulls: Vec<(Column, String)>,
+    pushes: Vec<(Column, String)>,
+    settled: Vec<(Column, String)>,
+    conflicts: Vec<SyncConflict>,
+}
+
+/// `row_state_db` prefixes, one per tab.
+const ORDERS_TAB: &str = "orders";
+const RECONCILIATION_TAB: &str = "reconciliation";
+
+/// Marketplace names a row may carry: the configured ones, or every marketplace the
+/// matchers recognise when none are configured.
+fn accepted_marketplaces(sheet: &SheetsConfig, matchers: &MatcherRegistry) -> Vec<String> {
+    if sheet.marketplaces.is_empty() { matchers.names() } else { sheet.marketplaces.clone() }
+}
+
+fn conflict_key(id: &str, field: &str) -> String {
+    format!("{}{}{}", id, KEY_SEPARATOR, field)
+}
+
+fn row_state_key(tab: &str, id: &str) -> String {
+    format!("{}{}{}", tab, KEY_SEPARATOR, id)
+}
+
+/// Hash of a row's content, rows are told apart by the id of their order instead of their
+/// position. Trailing empty cells don't count, the API leaves them out of responses anyway.
+fn row_hash(row: &[String]) -> String {
+    let used = row
+        .iter()
+        .rposition(|cell| !cell.is_empty())
+        .map_or(0, |i| i + 1);
+    let mut hasher = Sha256::new();
+    for cell in &row[..used] {
+        hasher.update(cell.as_bytes());
+        hasher.update([0x1f]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Three-way merge of `columns` between a sheet row and `existing`, against the values
+/// recorded at the last sync.
+fn merge_row(
+    existing: &Order,
+    baseline: &SyncBaseline,
+    columns: &[Column],
+    sheet_cell: impl Fn(Column) -> String,
+    row_number: usize
+) -> Merge {
+    let mut merge = Merge::default();
+    for &column in columns {
+        let sheet_value = sheet_cell(column);
+        let db_value = existing.sheet_value(column);
+        if sheet_value == db_value {
+            merge.settled.push((column, sheet_value));
+            continue;
+        }
+        let base = baseline.get(column.name()).cloned();
+        // a never-synced order is assumed unchanged in the DB, so the sheet wins as before
+        let reference = base.clone().unwrap_or_else(|| db_value.clone());
+        if db_value == reference {
+            merge.pulls.push((column, sheet_value));
+        } else if sheet_value == reference {
+            merge.pushes.push((column, db_value));
+        } else {
+            merge.conflicts.push(SyncConflict {
+                id: existing.id.clone(),
+                order_id: existing.order_id.clone(),
+                field: column.name().to_string(),
+                row_number: Some(row_number),
+                base,
+                sheet: sheet_value,
+                db: db_value,
+                detected_at: chrono::Utc::now().to_rfc3339(),
+            });
+        }
+    }
+    merge
+}
+
+/// The cells a sync would write to one row, `current` reads what the row holds now.
+fn sheet_row_diff(
+    tab: &str,
+    row_number: Option<usize>,
+    order_id: &str,
+    cells: &[(Column, String)],
+    current: impl Fn(Column) -> Option<String>
+) -> Option<SheetRowDiff> {
+    let changes: Vec<FieldChange> = cells
+        .iter()
+        .map(|(column, value)| FieldChange {
+            field: column.name().to_string(),
+            before: current(*column).filter(|v| !v.is_empty()),
+            after: Some(value.clone()).filter(|v| !v.is_empty()),
+        })
+        .filter(|change| change.before != change.after)
+        .collect();
+    if changes.is_empty() {
+        return None;
+    }
+    Some(SheetRowDiff {
+        tab: tab.to_string(),
+        row_number,
+        order_id: order_id.to_string(),
+        changes,
+    })
+}
+
+/// Put the id column header right after the last used column and hide it. `rows` is
+/// updated to match so the caller can map the header again.
+async fn add_row_id_column<S: SheetsClient>(
+    sheets: &S,
+    sheet: &SheetsConfig,
+    rows: &mut [Vec<String>]
+) -> Result<(), Box<dyn Error>> {
+    let position = rows
+        .iter()
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0);
+    if position >= MAX_COLUMNS {
+        return Err(format!("no free column left in A:Z for the {} column", ROW_ID_HEADER).into());
+    }
+    let mut cells = vec![None; position];
+    cells.push(Some(ROW_ID_HEADER.to_string()));
+    sheets.update_row(&sheet.spreadsheet_id, &sheet.orders_range(), 0, cells).await?;
+    if let Err(e) = sheets.hide_column(&sheet.spreadsheet_id, &sheet.orders_tab, position).await {
+        println!("⚠️ Could not hide the {} column: {}", ROW_ID_HEADER, e);
+    }
+    println!("Added {} column at index {}", ROW_ID_HEADER, position);
+    let header = &mut rows[0];
+    header.resize(position, String::new());
+    header.push(ROW_ID_HEADER.to_string());
+    Ok(())
+}
+
+impl DB {
+    /// Drop the baseline, row fingerprints and open conflicts of an order that leaves
+    /// the live set.
+    pub fn forget_sync_state(&self, txn: &mut RwTxn, id: &str) -> Result<(), Box<dyn Error>> {
+        self.sync_db.delete(txn, id)?;
+        for tab in [ORDERS_TAB, RECONCILIATION_TAB] {
+            self.row_state_db.delete(txn, &row_state_key(tab, id))?;
+        }
+        let prefix = format!("{}{}", id, KEY_SEPARATOR);
+        let keys: Vec<String> = self.conflict_db
+            .prefix_iter(txn, &prefix)?
+            .map(|r| r.map(|(k, _)| k.to_string()))
+            .collect::<Result<_, _>>()?;
+        for key in keys {
+            self.conflict_db.delete(txn, &key)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a row hashes the same as at the last sync and its order wasn't written since.
+    /// `row_number` is where the order has to be recorded, for tabs the order keeps its row of.
+    fn row_unchanged(
+        &self,
+        txn: &RoTxn,
+        tab: &str,
+        id: &str,
+        hash: &str,
+        row_number: Option<usize>
+    ) -> Result<bool, Box<dyn Error>> {
+        let Some(state) = self.row_state_db.get(txn, &row_state_key(tab, id))? else {
+            return Ok(false);
+        };
+        if state.hash != hash {
+            return Ok(false);
+        }
+        let order = self.order_db.get(txn, &id.to_string())?;
+        Ok(
+            order.is_some_and(|order| {
+                order.updated_at == state.order_updated_at &&
+                    row_number.is_none_or(|n| order.row_number == Some(n))
+            })
+        )
+    }
+
+    /// Drop the fingerprints of `tab` rows whose order no longer has one, returns how many.
+    fn remove_row_states(
+        &self,
+        txn: &mut RwTxn,
+        tab: &str,
+        present: &HashSet<String>
+    ) -> Result<usize, Box<dyn Error>> {
+        let prefix = row_state_key(tab, "");
+        let gone: Vec<String> = self.row_state_db
+            .prefix_iter(txn, &prefix)?
+            .map(|r| r.map(|(k, _)| k.to_string()))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|key| !present.contains(&key[prefix.len()..]))
+            .collect();
+        for key in &gone {
+            self.row_state_db.delete(txn, key)?;
+        }
+        Ok(gone.len())
+    }
+
+    fn plan_row(
+        &self,
+        txn: &RoTxn,
+        header: &HeaderMap,
+        row_number: usize,
+        row: &[String],
+        from_sheet: Order
+    ) -> Result<RowPlan, Box<dyn Error>> {
+        let mut plan = RowPlan {
+            row_number,
+            from_sheet,
+            existing_id: None,
+            pulls: Vec::new(),
+            pushes: Vec::new(),
+            settled: Vec::new(),
+            conflicts: Vec::new(),
+            stamp_id: header.cell(row, Column::RowId).unwrap_or_default().is_empty(),
+            hash: row_hash(row),
+        };
+        // `from_sheet.id` is the row's id cell when it has one
+        let existing = if plan.stamp_id {
+            match self.order_id_index.get(txn, &plan.from_sheet.order_id)? {
+                Some(id) => self.order_db.get(txn, &id.to_string())?,
+                None => None,
+            }
+        } else {
+            self.order_db.get(txn, &plan.from_sheet.id)?
+        };
+        let Some(existing) = existing else {
+            // new order, whatever the sheet says is the starting point
+            plan.settled = header
+                .owned_columns()
+                .into_iter()
+                .map(|c| (c, header.cell(row, c).unwrap_or_default().to_string()))
+                .collect();
+            return Ok(plan);
+        };
+        let baseline = self.sync_db.get(txn, &existing.id)?.unwrap_or_default();
+        let merge = merge_row(
+            &existing,
+            &baseline,
+            &header.owned_columns(),
+            |column| header.cell(row, column).unwrap_or_default().to_string(),
+            row_number
+        );
+        plan.pulls = merge.pulls;
+        plan.pushes = merge.pushes;
+        plan.settled = merge.settled;
+        plan.conflicts = merge.conflicts;
+        plan.existing_id = Some(existing.id);
+        Ok(plan)
+    }
+
+    /// Store the conflicts just found for one order. They reflect the latest run only, so
+    /// open conflicts on `columns` that are not found again drop out.
+    fn replace_conflicts(
+        &self,
+        txn: &mut RwTxn,
+        id: &str,
+        columns: &[Column],
+        conflicts: Vec<SyncConflict>
+    ) -> Result<(), Box<dyn Error>> {
+        let prefix = format!("{}{}", id, KEY_SEPARATOR);
+        let previous: HashMap<String, SyncConflict> = self.conflict_db
+            .prefix_iter(txn, &prefix)?
+            .map(|r| r.map(|(k, v)| (k.to_string(), v)))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|(_, c)| Column::from_name(&c.field).is_some_and(|c| columns.contains(&c)))
+            .collect();
+        for key in previous.keys() {
+            self.conflict_db.delete(txn, key)?;
+        }
+        for mut conflict in conflicts {
+            let key = conflict_key(&conflict.id, &conflict.field);
+            match previous.get(&key) {
+                Some(known) if known.sheet == conflict.sheet && known.db == conflict.db => {
+                    conflict.detected_at = known.detected_at.clone();
+                }
+                _ => {
+                    println!(
+                        "⚠️ Sync conflict on {} {}: sheet {:?}, db {:?}",
+                        conflict.order_id,
+                        conflict.field,
+                        conflict.sheet,
+                        conflict.db
+                    );
+                }
+            }
+            self.conflict_db.put(txn, &key, &conflict)?;
+        }
+        Ok(())
+    }
+
+    /// `save_order` for a synced row. A status change the order may not make, as when it
+    /// moved on since the row was planned, rejects the row instead of the whole sync.
+    fn save_synced_order(
+        &self,
+        txn: &mut RwTxn,
+        order: Order,
+        ctx: &ChangeContext,
+        tab: &str,
+        row_number: usize,
+        report: &mut SyncReport
+    ) -> Result<Option<Order>, Box<dyn Error>> {
+        match self.save_order(txn, order, ctx) {
+            Ok(order) => Ok(Some(order)),
+            Err(e) =>
+                match e.downcast::<TransitionError>() {
+                    Ok(e) => {
+                        println!("⚠️ {} row {} rejected: {}", tab, row_number + 1, e);
+                        report.rejected.push(RejectedRow {
+                            tab: tab.to_string(),
+                            row_number: row_number + 1,
+                            order_id: Some(e.order_id),
+                            errors: vec![RowError::InvalidTransition { from: e.from, to: e.to }],
+                        });
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+        }
+    }
+
+    /// Plan the rows of the orders tab. Also returns the ids present in the tab.
+    /// Rows that aren't valid orders, or from none of `marketplaces`, end up in `report.rejected`.
+    fn plan_orders_tab(
+        &self,
+        header: &HeaderMap,
+        rows: &[Vec<String>],
+        sheet: &SheetsConfig,
+        marketplaces: &[String],
+        only_order_id: Option<&str>,
+        report: &mut SyncReport
+    ) -> Result<(Vec<RowPlan>, HashSet<String>), Box<dyn Error>> {
+        let mut plans = Vec::new();
+        // ids already written to some row, claimed before rows without one are matched
+        let mut seen: HashSet<String> = rows
+            .iter()
+            .skip(1)
+            .filter_map(|row| header.cell(row, Column::RowId))
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string())
+            .collect();
+        let txn = self.env.read_txn()?;
+        let mut stamped = HashSet::new();
+        // rows of deleted orders stay until the outbox clears them, they must not
+        // come back as new orders in the meantime
+        let mut deleted_ids = HashSet::new();
+        let mut deleted_order_ids = HashSet::new();
+        for result in self.deleted_db.iter(&txn)? {
+            let (id, order) = result?;
+            deleted_ids.insert(id);
+            deleted_order_ids.insert(order.order_id);
+        }
+        for (i, row) in rows.iter().enumerate().skip(1) {
+            if row.iter().all(|cell| cell.trim().is_empty()) {
+                continue;
+            }
+            let from_sheet = match Order::from_sheets(i, header, row, marketplaces) {
+                Ok(order) => order,
+                Err(errors) => {
+                    let order_id = header.cell(row, Column::OrderId).unwrap_or_default().trim();
+                    if only_order_id.is_none_or(|only| only == order_id) {
+                        report.rejected.push(RejectedRow {
+                            tab: sheet.orders_tab.clone(),
+                            row_number: i + 1,
+                            order_id: Some(order_id.to_string()).filter(|id| !id.is_empty()),
+                            errors,
+                        });
+                    }
+                    continue;
+                }
+            };
+            if only_order_id.is_some_and(|only| only != from_sheet.order_id) {
+                continue;
+            }
+            let deleted = if header.cell(row, Column::RowId).unwrap_or_default().is_empty() {
+                deleted_order_ids.contains(&from_sheet.order_id) &&
+                    self.order_id_index.get(&txn, &from_sheet.order_id)?.is_none()
+            } else {
+                deleted_ids.contains(&from_sheet.id)
+            };
+            if deleted {
+                println!(
+                    "⚠️ Row {} belongs to deleted order {}, skipped until it is restored",
+                    i,
+                    from_sheet.order_id
+                );
+                continue;
+            }
+            let has_id = !header.cell(row, Column::RowId).unwrap_or_default().is_empty();
+            if
+                has_id &&
+                only_order_id.is_none() &&
+                self.row_unchanged(&txn, ORDERS_TAB, &from_sheet.id, &row_hash(row), Some(i))?
+            {
+                if !stamped.insert(from_sheet.id.clone()) {
+                    println!(
+                        "⚠️ Order {} appears more than once in the sheet, row {} skipped",
+                        from_sheet.order_id,
+                        i
+                    );
+                } else {
+                    report.unchanged += 1;
+                }
+                continue;
+            }
+            let plan = self.plan_row(&txn, header, i, row, from_sheet)?;
+            let id = plan.existing_id.as_ref().unwrap_or(&plan.from_sheet.id);
+            let duplicate = if plan.stamp_id {
+                seen.contains(id) || !stamped.insert(plan.from_sheet.order_id.clone())
+            } else {
+                // two rows carrying the same id, e.g. a row copied by hand
+                !stamped.insert(id.clone())
+            };
+            if duplicate {
+                println!(
+                    "⚠️ Order {} appears more than once in the sheet, row {} skipped",
+                    plan.from_sheet.order_id,
+                    i
+                );
+                continue;
+            }
+            seen.insert(id.clone());
+            plans.push(plan);
+        }
+        Ok((plans, seen))
+    }
+
+    /// Rows and header of the reconciliation tab, `None` when the tab has no header yet.
+    async fn fetch_reconciliation<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig
+    ) -> Result<Option<(HeaderMap, Vec<Vec<String>>)>, Box<dyn Error>> {
+        let value = sheets.fetch(
+            sheet.reconciliation_spreadsheet(),
+            &sheet.reconciliation_range()
+        ).await?;
+        let rows = rows_from_value(&value);
+        let header = match
+            HeaderMap::resolve(SHEET2_COLUMNS, rows.first().map(|r| r.as_slice()).unwrap_or_default())
+        {
+            Ok(header) => header,
+            Err(ColumnError::NoHeader) => {
+                println!(
+                    "⚠️ {} has no header row, reconciliation fields not synced",
+                    sheet.reconciliation_tab
+                );
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        Ok(Some((header, rows)))
+    }
+
+    /// Plan the reconciliation row of every order. Also returns the ids of the orders that
+    /// have a row.
+    fn plan_reconciliation(
+        &self,
+        header: &HeaderMap,
+        rows: &[Vec<String>],
+        sheet: &SheetsConfig,
+        only_order_id: Option<&str>,
+        report: &mut SyncReport
+    ) -> Result<(Vec<ReconciliationPlan>, HashSet<String>), Box<dyn Error>> {
+        let mut by_order_id: HashMap<&str, usize> = HashMap::new();
+        for (i, row) in rows.iter().enumerate().skip(1) {
+            let order_id = header.cell(row, Column::OrderId).unwrap_or_default();
+            if order_id.is_empty() {
+                continue;
+            }
+            if by_order_id.contains_key(order_id) {
+                println!(
+                    "⚠️ Order {} appears more than once in {}, row {} skipped",
+                    order_id,
+                    sheet.reconciliation_tab,
+                    i
+                );
+                continue;
+            }
+            by_order_id.insert(order_id, i);
+        }
+
+        let mut plans = Vec::new();
+        // orders that have a row, the fingerprints of all others are stale
+        let mut present = HashSet::new();
+        let txn = self.env.read_txn()?;
+        let ids: Vec<String> = match only_order_id {
+            Some(order_id) =>
+                self.order_id_index
+                    .get(&txn, order_id)?
+                    .map(|id| vec![id.to_string()])
+                    .unwrap_or_default(),
+            None =>
+                self.order_db
+                    .iter(&txn)?
+                    .map(|r| r.map(|(id, _)| id))
+                    .collect::<Result<_, _>>()?,
+        };
+        for id in ids {
+            let Some(order) = self.order_db.get(&txn, &id)? else {
+                continue;
+            };
+            let mut plan = ReconciliationPlan {
+                id,
+                row_number: by_order_id.get(order.order_id.as_str()).copied(),
+                append: None,
+                hash: None,
+                pulls: Vec::new(),
+                pushes: Vec::new(),
+                settled: Vec::new(),
+                mirrors: Vec::new(),
+                conflicts: Vec::new(),
+            };
+            let Some(i) = plan.row_number else {
+                plan.append = Some(order.to_sheet2_row(header));
+                plan.settled = header
+                    .owned_columns()
+                    .into_iter()
+                    .map(|c| (c, order.sheet_value(c)))
+                    .collect();
+                plans.push(plan);
+                continue;
+            };
+            let row = &rows[i];
+            present.insert(plan.id.clone());
+            let hash = row_hash(row);
+            if
+                only_order_id.is_none() &&
+                self.row_unchanged(&txn, RECONCILIATION_TAB, &plan.id, &hash, None)?
+            {
+                report.unchanged += 1;
+                continue;
+            }
+            plan.hash = Some(hash);
+            let cell = |column| header.cell(row, column).unwrap_or_default().to_string();
+            let baseline = self.sync_db.get(&txn, &plan.id)?.unwrap_or_default();
+            let mut merge = merge_row(&order, &baseline, &header.owned_columns(), cell, i);
+            // a status typed into the sheet still has to be a step the order may take
+            if let Some(index) = merge.pulls.iter().position(|(c, _)| *c == Column::Status) {
+                let mut candidate = order.clone();
+                candidate.set_sheet_value(Column::Status, &merge.pulls[index].1);
+                if validate_transition(&order.order_id, order.status, candidate.status).is_err() {
+                    let (column, sheet_value) = merge.pulls.remove(index);
+                    merge.conflicts.push(SyncConflict {
+                        id: order.id.clone(),
+                        order_id: order.order_id.clone(),
+                        field: column.name().to_string(),
+                        row_number: Some(i),
+                        base: baseline.get(column.name()).cloned(),
+                        sheet: sheet_value,
+                        db: order.sheet_value(column),
+                        detected_at: chrono::Utc::now().to_rfc3339(),
+                    });
+                }
+            }
+            plan.pulls = merge.pulls;
+            plan.pushes = merge.pushes;
+            plan.settled = merge.settled;
+            plan.conflicts = merge.conflicts;
+            plan.mirrors = header
+                .mirrored_columns()
+                .into_iter()
+                .map(|c| (c, order.sheet_value(c)))
+                .filter(|(c, value)| cell(*c) != *value)
+                .collect();
+            plans.push(plan);
+        }
+        Ok((plans, present))
+    }
+
+    /// Second pass of a sync: merge the reconciliation tab, joined to the orders by order
+    /// id. Orders without a row there get one appended.
+    async fn sync_reconciliation<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        only_order_id: Option<&str>,
+        report: &mut SyncReport
+    ) -> Result<(), Box<dyn Error>> {
+        let Some((header, rows)) = self.fetch_reconciliation(sheets, sheet).await? else {
+            return Ok(());
+        };
+        let spreadsheet_id = sheet.reconciliation_spreadsheet();
+        let range = sheet.reconciliation_range();
+
+        // 1. plan every order against a consistent snapshot
+        let (plans, present) = self.plan_reconciliation(&header, &rows, sheet, only_order_id, report)?;
+
+        // 2. write DB-side values, new rows go out in one append
+        let new_rows: Vec<Vec<String>> = plans
+            .iter()
+            .filter_map(|p| p.append.clone())
+            .collect();
+        let mut appended = true;
+        if !new_rows.is_empty() {
+            let count = new_rows.len();
+            match sheets.append(spreadsheet_id, &range, new_rows).await {
+                Ok(_) => {
+                    report.appended += count;
+                }
+                Err(e) => {
+                    println!("❌ Appending {} reconciliation rows failed: {}", count, e);
+                    report.failed_rows += count;
+                    appended = false;
+                }
+            }
+        }
+        let mut pushed_ids = HashSet::new();
+        for plan in &plans {
+            let Some(row_number) = plan.row_number else {
+                continue;
+            };
+            if plan.pushes.is_empty() && plan.mirrors.is_empty() {
+                continue;
+            }
+            let cells: Vec<(Column, String)> = plan.pushes
+                .iter()
+                .chain(plan.mirrors.iter())
+                .cloned()
+                .collect();
+            let result = sheets.update_row(
+                spreadsheet_id,
+                &range,
+                row_number,
+                header.partial_row(&cells)
+            ).await;
+            match result {
+                Ok(_) => {
+                    report.pushed += plan.pushes.len();
+                    pushed_ids.insert(plan.id.clone());
+                }
+                Err(e) => {
+                    println!("❌ Reconciliation update failed for row {}: {}", row_number, e);
+                    report.failed_rows += 1;
+                }
+            }
+        }
+
+        // 3. apply sheet-side edits, baselines and conflicts in one transaction
+        let ctx = ChangeContext::sheet_sync();
+        let columns = merged_columns(SHEET2_COLUMNS);
+        let mut txn = self.env.write_txn()?;
+        for plan in plans {
+            if plan.append.is_some() && !appended {
+                continue;
+            }
+            let Some(mut order) = self.order_db.get(&txn, &plan.id)? else {
+                continue;
+            };
+            for (column, value) in &plan.pulls {
+                order.set_sheet_value(*column, value);
+            }
+            // appended rows have nothing to pull, the order stays as it is
+            let order = match plan.row_number {
+                Some(i) if !plan.pulls.is_empty() => {
+                    let tab = &sheet.reconciliation_tab;
+                    let Some(order) = self.save_synced_order(&mut txn, order, &ctx, tab, i, report)? else {
+                        continue;
+                    };
+                    order
+                }
+                _ => order,
+            };
+            report.pulled += plan.pulls.len();
+            // rows written to are fingerprinted on the next run, once their new content is read
+            if let Some(hash) = &plan.hash {
+                report.changed += 1;
+                if plan.pushes.is_empty() && plan.mirrors.is_empty() {
+                    let state = RowState { hash: hash.clone(), order_updated_at: order.updated_at };
+                    self.row_state_db.put(
+                        &mut txn,
+                        &row_state_key(RECONCILIATION_TAB, &plan.id),
+                        &state
+                    )?;
+                }
+            }
+
+            let mut baseline = self.sync_db.get(&txn, &plan.id)?.unwrap_or_default();
+            let pushed = pushed_ids.contains(&plan.id);
+            let synced = plan.settled
+                .iter()
+                .chain(plan.pulls.iter())
+                .chain(plan.pushes.iter().filter(|_| pushed));
+            for (column, value) in synced {
+                baseline.insert(column.name().to_string(), value.clone());
+            }
+            self.sync_db.put(&mut txn, &plan.id, &baseline)?;
+            report.conflicts += plan.conflicts.len();
+            self.replace_conflicts(&mut txn, &plan.id, &columns, plan.conflicts)?;
+        }
+        if only_order_id.is_none() {
+            report.removed += self.remove_row_states(&mut txn, RECONCILIATION_TAB, &present)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+impl DBSync for DB {
+    async fn sync_sheet<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        matchers: &MatcherRegistry,
+        only_order_id: Option<&str>
+    ) -> Result<SyncReport, Box<dyn Error>> {
+        let value = sheets.fetch(&sheet.spreadsheet_id, &sheet.orders_range()).await?;
+        let mut rows = rows_from_value(&value);
+        let mut header = HeaderMap::resolve(
+            SHEET1_COLUMNS,
+            rows.first().map(|r| r.as_slice()).unwrap_or_default()
+        )?;
+        if header.position(Column::RowId).is_none() {
+            add_row_id_column(sheets, sheet, &mut rows).await?;
+            header = HeaderMap::resolve(SHEET1_COLUMNS, &rows[0])?;
+        }
+
+        // 1. plan every row against a consistent snapshot
+        let mut report = SyncReport::default();
+        let marketplaces = accepted_marketplaces(sheet, matchers);
+        let (plans, seen) = self.plan_orders_tab(
+            &header,
+            &rows,
+            sheet,
+            &marketplaces,
+            only_order_id,
+            &mut report
+        )?;
+
+        // 2. push DB-side edits, a failed row keeps its old baseline and is retried next time
+        let mut pushed_rows = HashSet::new();
+        for plan in plans.iter().filter(|p| !p.pushes.is_empty() || p.stamp_id) {
+            let mut cells = plan.pushes.clone();
+            if plan.stamp_id {
+                let id = plan.existing_id.clone().unwrap_or_else(|| plan.from_sheet.id.clone());
+                cells.push((Column::RowId, id));
+            }
+            let cells = header.partial_row(&cells);
+            let result = sheets.update_row(
+                &sheet.spreadsheet_id,
+                &sheet.orders_range(),
+                plan.row_number,
+                cells
+            ).await;
+            match result {
+                Ok(_) => {
+                    report.pushed += plan.pushes.len();
+                    pushed_rows.insert(plan.row_number);
+                }
+                Err(e) => {
+                    println!("❌ Sheet update failed for row {}: {}", plan.row_number, e);
+                    report.failed_rows += 1;
+                }
+            }
+        }
+
+        // 3. apply sheet-side edits, baselines and conflicts in one transaction
+        let ctx = ChangeContext::sheet_sync();
+        let columns = merged_columns(SHEET1_COLUMNS);
+        let mut txn = self.env.write_txn()?;
+        for plan in plans {
+            // rows written to are fingerprinted on the next run, once their new content is read
+            let settled_row = !plan.stamp_id && plan.pushes.is_empty();
+            let Some(id) = plan.existing_id else {
+                let baseline: SyncBaseline = plan.settled
+                    .into_iter()
+                    .map(|(c, value)| (c.name().to_string(), value))
+                    .collect();
+                let saved = self.save_synced_order(
+                    &mut txn,
+                    plan.from_sheet,
+                    &ctx,
+                    &sheet.orders_tab,
+                    plan.row_number,
+                    &mut report
+                )?;
+                let Some(order) = saved else {
+                    continue;
+                };
+                self.sync_db.put(&mut txn, &order.id, &baseline)?;
+                if settled_row {
+                    let state = RowState { hash: plan.hash, order_updated_at: order.updated_at };
+                    self.row_state_db.put(&mut txn, &row_state_key(ORDERS_TAB, &order.id), &state)?;
+                }
+                report.added += 1;
+                continue;
+            };
+            let Some(mut order) = self.order_db.get(&txn, &id)? else {
+                continue;
+            };
+            for (column, value) in &plan.pulls {
+                order.set_sheet_value(*column, value);
+            }
+            // refund flags and position belong to the sheet
+            order.boolean = plan.from_sheet.boolean;
+            order.row_number = plan.from_sheet.row_number;
+            let saved = self.save_synced_order(
+                &mut txn,
+                order,
+                &ctx,
+                &sheet.orders_tab,
+                plan.row_number,
+                &mut report
+            )?;
+            let Some(order) = saved else {
+                continue;
+            };
+            report.pulled += plan.pulls.len();
+            report.changed += 1;
+            if settled_row {
+                let state = RowState { hash: plan.hash, order_updated_at: order.updated_at };
+                self.row_state_db.put(&mut txn, &row_state_key(ORDERS_TAB, &id), &state)?;
+            }
+
+            let mut baseline = self.sync_db.get(&txn, &id)?.unwrap_or_default();
+            let pushed = pushed_rows.contains(&plan.row_number);
+            let synced = plan.settled
+                .iter()
+                .chain(plan.pulls.iter())
+                .chain(plan.pushes.iter().filter(|_| pushed));
+            for (column, value) in synced {
+                baseline.insert(column.name().to_string(), value.clone());
+            }
+            self.sync_db.put(&mut txn, &id, &baseline)?;
+            report.conflicts += plan.conflicts.len();
+            self.replace_conflicts(&mut txn, &id, &columns, plan.conflicts)?;
+        }
+        if only_order_id.is_none() {
+            report.removed += self.remove_row_states(&mut txn, ORDERS_TAB, &seen)?;
+        }
+        txn.commit()?;
+
+        self.sync_reconciliation(sheets, sheet, only_order_id, &mut report).await?;
+        println!("Sheet sync: {:?}", report);
+        Ok(report)
+    }
+
+    async fn preview_sync<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        matchers: &MatcherRegistry
+    ) -> Result<SyncPreview, Box<dyn Error>> {
+        let value = sheets.fetch(&sheet.spreadsheet_id, &sheet.orders_range()).await?;
+        let rows = rows_from_value(&value);
+        // without an id column every row shows up with its id stamped
+        let header = HeaderMap::resolve(
+            SHEET1_COLUMNS,
+            rows.first().map(|r| r.as_slice()).unwrap_or_default()
+        )?;
+        let mut report = SyncReport::default();
+        let mut preview = SyncPreview::default();
+        // orders before and after the sync, both tabs applied
+        let mut touched: Vec<(Order, Order)> = Vec::new();
+
+        let marketplaces = accepted_marketplaces(sheet, matchers);
+        let (plans, _) = self.plan_orders_tab(&header, &rows, sheet, &marketplaces, None, &mut report)?;
+        {
+            let txn = self.env.read_txn()?;
+            for plan in plans {
+                let row = &rows[plan.row_number];
+                let mut cells = plan.pushes.clone();
+                if plan.stamp_id {
+                    let id = plan.existing_id.clone().unwrap_or_else(|| plan.from_sheet.id.clone());
+                    cells.push((Column::RowId, id));
+                }
+                preview.sheet.extend(
+                    sheet_row_diff(
+                        &sheet.orders_tab,
+                        Some(plan.row_number),
+                        &plan.from_sheet.order_id,
+                        &cells,
+                        |column| header.cell(row, column).map(|v| v.to_string())
+                    )
+                );
+                preview.conflicts.extend(plan.conflicts);
+                let Some(id) = plan.existing_id else {
+                    preview.created.push(plan.from_sheet);
+                    continue;
+                };
+                let Some(order) = self.order_db.get(&txn, &id)? else {
+                    continue;
+                };
+                let mut after = order.clone();
+                for (column, value) in &plan.pulls {
+                    after.set_sheet_value(*column, value);
+                }
+                after.boolean = plan.from_sheet.boolean;
+                after.row_number = plan.from_sheet.row_number;
+                touched.push((order, after));
+            }
+        }
+
+        if let Some((header, rows)) = self.fetch_reconciliation(sheets, sheet).await? {
+            let (plans, _) = self.plan_reconciliation(&header, &rows, sheet, None, &mut report)?;
+            let txn = self.env.read_txn()?;
+            for plan in plans {
+                let index = match touched.iter().position(|(order, _)| order.id == plan.id) {
+                    Some(index) => index,
+                    None => {
+                        let Some(order) = self.order_db.get(&txn, &plan.id)? else {
+                            continue;
+                        };
+                        touched.push((order.clone(), order));
+                        touched.len() - 1
+                    }
+                };
+                let after = &mut touched[index].1;
+                for (column, value) in &plan.pulls {
+                    after.set_sheet_value(*column, value);
+                }
+                let order_id = touched[index].0.order_id.clone();
+                let diff = match plan.row_number {
+                    Some(i) => {
+                        let cells: Vec<(Column, String)> = plan.pushes
+                            .iter()
+                            .chain(plan.mirrors.iter())
+                            .cloned()
+                            .collect();
+                        sheet_row_diff(&sheet.reconciliation_tab, Some(i), &order_id, &cells, |column| {
+                            header.cell(&rows[i], column).map(|v| v.to_string())
+                        })
+                    }
+                    None =>
+                        sheet_row_diff(&sheet.reconciliation_tab, None, &order_id, &plan.settled, |_| None),
+                };
+                preview.sheet.extend(diff);
+                preview.conflicts.extend(plan.conflicts);
+            }
+        }
+
+        for (before, after) in touched {
+            let changes = diff_orders(Some(&before), Some(&after));
+            if !changes.is_empty() {
+                preview.updated.push(OrderDiff { id: before.id, order_id: before.order_id, changes });
+            }
+        }
+        preview.unchanged = report.unchanged;
+        preview.rejected = report.rejected;
+        Ok(preview)
+    }
+
+    fn conflicts(&self) -> Result<Vec<SyncConflict>, Box<dyn Error>> {
+        let txn = self.env.read_txn()?;
+        let mut conflicts = Vec::new();
+        for result in self.conflict_db.iter(&txn)? {
+            let (_, conflict) = result?;
+            conflicts.push(conflict);
+        }
+        Ok(conflicts)
+    }
+
+    async fn resolve_conflict<S: SheetsClient>(
+        &self,
+        sheets: &S,
+        sheet: &SheetsConfig,
+        id: &str,
+        field: &str,
+        keep: SyncSide,
+        ctx: &ChangeContext
+    ) -> Result<Option<SyncConflict>, Box<dyn Error>> {
+        let key = conflict_key(id, field);
+        let conflict = {
+            let txn = self.env.read_txn()?;
+            match self.conflict_db.get(&txn, &key)? {
+                Some(conflict) => conflict,
+                None => {
+                    return Ok(None);
+                }
+            }
+        };
+        let column = Column::from_name(field).ok_or_else(|| format!("unknown sheet field {}", field))?;
+        let value = match keep {
+            SyncSide::Sheet => conflict.sheet.clone(),
+            SyncSide::Db => conflict.db.clone(),
+        };
+
+        if keep == SyncSide::Db {
+            let reconciliation = merged_columns(SHEET2_COLUMNS).contains(&column);
+            let (spreadsheet_id, range, specs) = if reconciliation {
+                (sheet.reconciliation_spreadsheet(), sheet.reconciliation_range(), SHEET2_COLUMNS)
+            } else {
+                (sheet.spreadsheet_id.as_str(), sheet.orders_range(), SHEET1_COLUMNS)
+            };
+            // rows move, look the row up again right before writing to it
+            let fetched = sheets.fetch(spreadsheet_id, &range).await?;
+            let rows = rows_from_value(&fetched);
+            let header = HeaderMap::resolve(
+                specs,
+                rows.first().map(|r| r.as_slice()).unwrap_or_default()
+            )?;
+            let row_number = (
+                if reconciliation {
+                    header.find_order_row(&rows, &conflict.order_id)
+                } else {
+                    header.find_row(&rows, id, &conflict.order_id)
+                }
+            ).ok_or_else(|| format!("order {} has no row in the sheet", conflict.order_id))?;
+            sheets.update_row(
+                spreadsheet_id,
+                &range,
+                row_number,
+                header.partial_row(&[(column, value.clone())])
+            ).await?;
+        }
+
+        let mut txn = self.env.write_txn()?;
+        if keep == SyncSide::Sheet && let Some(mut order) = self.order_db.get(&txn, &id.to_string())? {
+            order.set_sheet_value(column, &value);
+            self.save_order(&mut txn, order, ctx)?;
+        }
+        let mut baseline = self.sync_db.get(&txn, id)?.unwrap_or_default();
+        baseline.insert(field.to_string(), value);
+        self.sync_db.put(&mut txn, id, &baseline)?;
+        self.conflict_db.delete(&mut txn, &key)?;
+        txn.commit()?;
+        Ok(Some(conflict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::{ order::DBOrder, utils::tests::temp_db, versioned::tests::{ expected_v1, expected_v2 } },
+        schema::status::OrderStatus,
+        scripts::{ fake_sheets::{ tests::client, FakeSheets }, sheets::GoogleSheetsClient },
+    };
+
+    const COLUMNS: &[Column] = &[Column::ReturnedSku, Column::Marketplace];
+
+    fn baseline(sku: &str) -> SyncBaseline {
+        SyncBaseline::from([
+            ("returned_sku".to_string(), sku.to_string()),
+            ("marketplace".to_string(), "Debenhams".to_string()),
+        ])
+    }
+
+    /// Merge `expected_v1` (returned SKU `TSHIRT-RED-M`) with a row whose SKU cell is `sheet_sku`.
+    fn merge_with(baseline: &SyncBaseline, sheet_sku: &str) -> Merge {
+        let sheet_cell = |column| match column {
+            Column::ReturnedSku => sheet_sku.to_string(),
+            _ => "Debenhams".to_string(),
+        };
+        merge_row(&expected_v1(), baseline, COLUMNS, sheet_cell, 14)
+    }
+
+    fn sku(value: &str) -> Vec<(Column, String)> {
+        vec![(Column::ReturnedSku, value.to_string())]
+    }
+
+    #[test]
+    fn a_sheet_only_edit_is_pulled() {
+        let merge = merge_with(&baseline("TSHIRT-RED-M"), "TSHIRT-RED-L");
+        assert_eq!(merge.pulls, sku("TSHIRT-RED-L"));
+        assert!(merge.pushes.is_empty() && merge.conflicts.is_empty());
+        assert_eq!(merge.settled, [(Column::Marketplace, "Debenhams".to_string())]);
+
+        // without a baseline the DB counts as unchanged
+        let merge = merge_with(&SyncBaseline::new(), "TSHIRT-RED-L");
+        assert_eq!(merge.pulls, sku("TSHIRT-RED-L"));
+    }
+
+    #[test]
+    fn a_db_only_edit_is_pushed() {
+        let merge = merge_with(&baseline("TSHIRT-RED-S"), "TSHIRT-RED-S");
+        assert_eq!(merge.pushes, sku("TSHIRT-RED-M"));
+        assert!(merge.pulls.is_empty() && merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn the_same_edit_on_both_sides_is_settled() {
+        let merge = merge_with(&baseline("TSHIRT-RED-S"), "TSHIRT-RED-M");
+        assert!(merge.pulls.is_empty() && merge.pushes.is_empty() && merge.conflicts.is_empty());
+        assert_eq!(merge.settled.len(), 2);
+    }
+
+    #[test]
+    fn different_edits_on_both_sides_conflict() {
+        let merge = merge_with(&baseline("TSHIRT-RED-S"), "TSHIRT-RED-L");
+        assert!(merge.pulls.is_empty() && merge.pushes.is_empty());
+        let [conflict] = merge.conflicts.as_slice() else {
+            panic!("expected one conflict, got {:?}", merge.conflicts);
+        };
+        assert_eq!(conflict.field, "returned_sku");
+        assert_eq!(conflict.row_number, Some(14));
+        assert_eq!(conflict.base.as_deref(), Some("TSHIRT-RED-S"));
+        assert_eq!((conflict.sheet.as_str(), conflict.db.as_str()), ("TSHIRT-RED-L", "TSHIRT-RED-M"));
+    }
+
+    fn conflict(field: &str, sheet: &str, detected_at: &str) -> SyncConflict {
+        SyncConflict {
+            id: expected_v1().id,
+            order_id: expected_v1().order_id,
+            field: field.to_string(),
+            row_number: Some(1),
+            base: Some("TSHIRT-RED-S".to_string()),
+            sheet: sheet.to_string(),
+            db: "TSHIRT-RED-M".to_string(),
+            detected_at: detected_at.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn conflicts_reflect_the_latest_run_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let id = expected_v1().id;
+        let sheet1 = merged_columns(SHEET1_COLUMNS);
+        let mut txn = db.env.write_txn().unwrap();
+        db.replace_conflicts(&mut txn, &id, &sheet1, vec![
+            conflict("returned_sku", "TSHIRT-RED-L", "first run"),
+            conflict("date", "2024-03-09", "first run")
+        ]).unwrap();
+        db.replace_conflicts(&mut txn, &id, &merged_columns(SHEET2_COLUMNS), vec![
+            conflict("matched_sku", "TSHIRT-RED-M", "first run")
+        ]).unwrap();
+        txn.commit().unwrap();
+
+        // found again unchanged: keeps its detection time; not found again: dropped, but
+        // only among the columns of the tab being synced
+        let mut txn = db.env.write_txn().unwrap();
+        db.replace_conflicts(&mut txn, &id, &sheet1, vec![conflict("returned_sku", "TSHIRT-RED-L", "second run")]).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(db.conflicts().unwrap(), [
+            conflict("matched_sku", "TSHIRT-RED-M", "first run"),
+            conflict("returned_sku", "TSHIRT-RED-L", "first run"),
+        ]);
+
+        // the sheet moved on: a new conflict
+        let mut txn = db.env.write_txn().unwrap();
+        db.replace_conflicts(&mut txn, &id, COLUMNS, vec![conflict("returned_sku", "TSHIRT-RED-XL", "third run")]).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(db.conflicts().unwrap()[1], conflict("returned_sku", "TSHIRT-RED-XL", "third run"));
+    }
+
+    /// An order on sheet row 1 with an open SKU conflict, sheet `TSHIRT-RED-L` against DB `TSHIRT-RED-M`.
+    async fn conflicted(dir: &tempfile::TempDir) -> (DB, FakeSheets, GoogleSheetsClient, SheetsConfig) {
+        let db = temp_db(dir).await;
+        let order = Order { row_number: Some(1), ..expected_v1() };
+        let mut txn = db.env.write_txn().unwrap();
+        db.save_order(&mut txn, order, &ChangeContext::sheet_sync()).unwrap();
+        db.replace_conflicts(&mut txn, &expected_v1().id, COLUMNS, vec![conflict("returned_sku", "TSHIRT-RED-L", "t")]).unwrap();
+        txn.commit().unwrap();
+
+        let fake = FakeSheets::default();
+        let cells = |cells: &[&str]| cells.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        fake.seed("sheet", "Sheet1", vec![
+            cells(&["ORDER ID", "BIN RACK", "RETURNED SKU"]),
+            cells(&["104522", "B-12", "TSHIRT-RED-L"])
+        ]);
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+        let config = SheetsConfig { spreadsheet_id: "sheet".to_string(), ..SheetsConfig::default() };
+        (db, fake, sheets, config)
+    }
+
+    fn stored_sku(db: &DB) -> (Option<String>, Option<String>) {
+        let txn = db.env.read_txn().unwrap();
+        let id = expected_v1().id;
+        let order = db.order_db.get(&txn, &id).unwrap().unwrap();
+        let baseline = db.sync_db.get(&txn, &id).unwrap().unwrap_or_default();
+        (order.returned_sku, baseline.get("returned_sku").cloned())
+    }
+
+    #[actix_web::test]
+    async fn keeping_the_sheet_value_updates_the_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, fake, sheets, config) = conflicted(&dir).await;
+        let ctx = ChangeContext::sheet_sync();
+        let id = expected_v1().id;
+
+        let resolved = db.resolve_conflict(&sheets, &config, &id, "returned_sku", SyncSide::Sheet, &ctx).await.unwrap();
+        assert_eq!(resolved.map(|c| c.sheet), Some("TSHIRT-RED-L".to_string()));
+        let sku = Some("TSHIRT-RED-L".to_string());
+        assert_eq!(stored_sku(&db), (sku.clone(), sku));
+        assert_eq!(fake.rows("sheet", "Sheet1")[1][2], "TSHIRT-RED-L");
+        assert!(db.conflicts().unwrap().is_empty());
+        // resolved already
+        let again = db.resolve_conflict(&sheets, &config, &id, "returned_sku", SyncSide::Sheet, &ctx).await.unwrap();
+        assert_eq!(again, None);
+    }
+
+    #[actix_web::test]
+    async fn keeping_the_db_value_rewrites_the_cell() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db, fake, sheets, config) = conflicted(&dir).await;
+        let ctx = ChangeContext::sheet_sync();
+
+        db.resolve_conflict(&sheets, &config, &expected_v1().id, "returned_sku", SyncSide::Db, &ctx).await.unwrap();
+        let sku = Some("TSHIRT-RED-M".to_string());
+        assert_eq!(stored_sku(&db), (sku.clone(), sku));
+        // only the conflicting cell is written
+        assert_eq!(fake.rows("sheet", "Sheet1")[1], ["104522", "B-12", "TSHIRT-RED-M"]);
+        assert!(db.conflicts().unwrap().is_empty());
+    }
+
+    fn cells(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[actix_web::test]
+    async fn rows_are_stamped_with_their_id_and_followed_when_moved() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let fake = FakeSheets::default();
+        fake.seed("sheet", "Sheet1", vec![
+            cells(&["ORDER ID", "RETURNED SKU", "MARKETPLACE", "DATE"]),
+            cells(&["7001", "ABC-1", "Debenhams", "2024-03-04"]),
+            cells(&["7002", "XYZ-2", "Matalan", "2024-03-05"])
+        ]);
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+        let config = SheetsConfig { spreadsheet_id: "sheet".to_string(), ..SheetsConfig::default() };
+
+        let report = db.sync_sheet(&sheets, &config, &MatcherRegistry::with_builtin(), None).await.unwrap();
+        assert_eq!(report.added, 2);
+        let id_of = |order_id: &str| {
+            let txn = db.env.read_txn().unwrap();
+            db.order_id_index.get(&txn, order_id).unwrap().unwrap().to_string()
+        };
+        let (first, second) = (id_of("7001"), id_of("7002"));
+        let rows = fake.rows("sheet", "Sheet1");
+        assert_eq!(rows[0], ["ORDER ID", "RETURNED SKU", "MARKETPLACE", "DATE", ROW_ID_HEADER]);
+        assert_eq!(rows[1][4], first);
+        assert_eq!(rows[2][4], second);
+        assert_eq!(fake.hidden_columns("sheet", 0), [4]);
+
+        // someone sorts the sheet and edits a SKU: the rows keep their orders
+        fake.seed("sheet", "Sheet1", vec![
+            rows[0].clone(),
+            cells(&["7002", "XYZ-2", "Matalan", "2024-03-05", &second]),
+            cells(&["7001", "ABC-9", "Debenhams", "2024-03-04", &first])
+        ]);
+        let report = db.sync_sheet(&sheets, &config, &MatcherRegistry::with_builtin(), None).await.unwrap();
+        assert_eq!((report.added, report.pulled, report.conflicts), (0, 1, 0));
+        let txn = db.env.read_txn().unwrap();
+        let first = db.order_db.get(&txn, &first).unwrap().unwrap();
+        assert_eq!((first.row_number, first.returned_sku.as_deref()), (Some(2), Some("ABC-9")));
+        assert_eq!(db.order_db.get(&txn, &second).unwrap().unwrap().row_number, Some(1));
+    }
+
+    #[actix_web::test]
+    async fn the_reconciliation_tab_is_joined_by_order_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let mut txn = db.env.write_txn().unwrap();
+        db.save_order(&mut txn, expected_v1(), &ChangeContext::sheet_sync()).unwrap();
+        db.save_order(&mut txn, expected_v2(), &ChangeContext::sheet_sync()).unwrap();
+        txn.commit().unwrap();
+
+        let fake = FakeSheets::default();
+        fake.seed("sheet", "Sheet1", vec![cells(&["ORDER ID", "RETURNED SKU", ROW_ID_HEADER])]);
+        // status skips a step, qty was edited, match type is blank
+        fake.seed("sheet", "Sheet2", vec![
+            cells(&["ORDER NUMBER", "MATCH TYPE", "STATUS", "QTY"]),
+            cells(&["104522", "", "Restocked", "2"])
+        ]);
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+        let config = SheetsConfig { spreadsheet_id: "sheet".to_string(), ..SheetsConfig::default() };
+
+        let report = db.sync_sheet(&sheets, &config, &MatcherRegistry::with_builtin(), None).await.unwrap();
+        assert_eq!((report.appended, report.pulled, report.conflicts), (1, 1, 1));
+        let rows = fake.rows("sheet", "Sheet2");
+        // the mirrored match type is written back, the order without a row gets one
+        assert_eq!(rows[1], ["104522", "Full Match", "Restocked", "2"]);
+        assert_eq!(rows[2][0], expected_v2().order_id);
+
+        let order = db.get_single(expected_v1().id).unwrap().unwrap();
+        assert_eq!((order.qty, order.status), (Some(2), Some(OrderStatus::Matched)));
+        let [conflict] = db.conflicts().unwrap().try_into().unwrap();
+        assert_eq!((conflict.field.as_str(), conflict.sheet.as_str()), ("status", "Restocked"));
+    }
+
+    #[test]
+    fn row_hash_covers_only_the_cells() {
+        let row = cells(&["Debenhams", "104522", "TSHIRT-RED-M"]);
+        assert_eq!(row_hash(&row), row_hash(&cells(&["Debenhams", "104522", "TSHIRT-RED-M", "", ""])));
+        assert_ne!(row_hash(&row), row_hash(&cells(&["Debenhams", "104522", "TSHIRT-RED-L"])));
+        // cells don't run into each other
+        assert_ne!(row_hash(&cells(&["ab", "c"])), row_hash(&cells(&["a", "bc"])));
+    }
+
+    #[actix_web::test]
+    async fn unchanged_rows_are_skipped_until_they_or_their_order_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let fake = FakeSheets::default();
+        fake.seed("sheet", "Sheet1", vec![
+            cells(&["ORDER ID", "RETURNED SKU", "MARKETPLACE", "DATE"]),
+            cells(&["7001", "ABC-1", "Debenhams", "2024-03-04"]),
+            cells(&["7002", "XYZ-2", "Matalan", "2024-03-05"])
+        ]);
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+        let config = SheetsConfig { spreadsheet_id: "sheet".to_string(), ..SheetsConfig::default() };
+        let matchers = MatcherRegistry::with_builtin();
+        let sync = || db.sync_sheet(&sheets, &config, &matchers, None);
+
+        // stamped rows are fingerprinted once their new content has been read back
+        assert_eq!(sync().await.unwrap().added, 2);
+        assert_eq!((sync().await.unwrap().changed, sync().await.unwrap().unchanged), (2, 2));
+
+        // an edit to the order brings its row back in
+        let id = {
+            let txn = db.env.read_txn().unwrap();
+            db.order_id_index.get(&txn, "7001").unwrap().unwrap().to_string()
+        };
+        let mut txn = db.env.write_txn().unwrap();
+        let order = db.order_db.get(&txn, &id).unwrap().unwrap();
+        let order = Order { qty: Some(3), ..order };
+        db.save_order(&mut txn, order, &ChangeContext::sheet_sync()).unwrap();
+        txn.commit().unwrap();
+        let report = sync().await.unwrap();
+        assert_eq!((report.changed, report.unchanged), (1, 1));
+
+        // so does a row that only moved, its order has to learn the new position
+        let rows = fake.rows("sheet", "Sheet1");
+        fake.seed("sheet", "Sheet1", vec![rows[0].clone(), rows[2].clone(), rows[1].clone()]);
+        let report = sync().await.unwrap();
+        assert_eq!((report.changed, report.unchanged), (2, 0));
+        assert_eq!(db.get_single(id).unwrap().unwrap().row_number, Some(2));
+
+        // a row gone from the sheet drops its fingerprint
+        fake.seed("sheet", "Sheet1", vec![rows[0].clone(), rows[2].clone()]);
+        assert_eq!(sync().await.unwrap().removed, 1);
+    }
+
+    #[tokio::test]
+    async fn a_refused_status_change_rejects_only_its_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let ctx = ChangeContext::sheet_sync();
+        let stored = Order { status: Some(OrderStatus::Confirmed), ..expected_v2() };
+        let mut txn = db.env.write_txn().unwrap();
+        db.save_order(&mut txn, stored.clone(), &ctx).unwrap();
+
+        // the sheet still has the status the order had when the row was planned
+        let stale = Order { status: Some(OrderStatus::Received), ..stored.clone() };
+        let mut report = SyncReport::default();
+        let saved = db.save_synced_order(&mut txn, stale, &ctx, "Sheet2", 4, &mut report).unwrap();
+        assert!(saved.is_none());
+        assert_eq!(report.rejected, vec![RejectedRow {
+            tab: "Sheet2".to_string(),
+            row_number: 5,
+            order_id: Some(stored.order_id.clone()),
+            errors: vec![RowError::InvalidTransition { from: "confirmed".to_string(), to: "received".to_string() }],
+        }]);
+        assert_eq!(db.order_db.get(&txn, &stored.id).unwrap().unwrap().status, Some(OrderStatus::Confirmed));
+
+        let moved_on = Order { status: Some(OrderStatus::Refunded), ..stored.clone() };
+        let saved = db.save_synced_order(&mut txn, moved_on, &ctx, "Sheet2", 4, &mut report).unwrap();
+        assert_eq!(saved.unwrap().status, Some(OrderStatus::Refunded));
+        assert_eq!(report.rejected.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn a_preview_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let mut txn = db.env.write_txn().unwrap();
+        db.save_order(&mut txn, expected_v1(), &ChangeContext::sheet_sync()).unwrap();
+        db.save_order(&mut txn, expected_v2(), &ChangeContext::sheet_sync()).unwrap();
+        txn.commit().unwrap();
+
+        let fake = FakeSheets::default();
+        let sheet1 = vec![
+            cells(&["ORDER ID", "RETURNED SKU", "DATE", "MARKETPLACE", ROW_ID_HEADER]),
+            cells(&["7001", "ABC-1", "2024-03-04", "Matalan"])
+        ];
+        let sheet2 = vec![
+            cells(&["ORDER ID", "MATCH TYPE", "QTY"]),
+            cells(&["104522", "Full Match", "2"])
+        ];
+        fake.seed("sheet", "Sheet1", sheet1.clone());
+        fake.seed("sheet", "Sheet2", sheet2.clone());
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+        let config = SheetsConfig { spreadsheet_id: "sheet".to_string(), ..SheetsConfig::default() };
+
+        let preview = db.preview_sync(&sheets, &config, &MatcherRegistry::with_builtin()).await.unwrap();
+        assert_eq!(preview.created.iter().map(|o| o.order_id.as_str()).collect::<Vec<_>>(), ["7001"]);
+        let [updated] = preview.updated.as_slice() else {
+            panic!("expected one updated order, got {:?}", preview.updated);
+        };
+        assert_eq!(updated.order_id, expected_v1().order_id);
+        assert_eq!(updated.changes[0].field, "qty");
+        // the new row gets its id stamped
+        assert!(preview.sheet.iter().any(|diff| diff.tab == "Sheet1" && diff.order_id == "7001"));
+
+        assert_eq!((fake.rows("sheet", "Sheet1"), fake.rows("sheet", "Sheet2")), (sheet1, sheet2));
+        assert_eq!(db.get_single(expected_v1().id).unwrap().unwrap().qty, Some(1));
+        let txn = db.env.read_txn().unwrap();
+        assert_eq!(db.order_id_index.get(&txn, "7001").unwrap(), None);
+        assert!(db.sync_db.is_empty(&txn).unwrap());
+    }
+
+    #[test]
+    fn marketplaces_default_to_the_matcher_names() {
+        let matchers = MatcherRegistry::with_builtin();
+        let mut sheet = SheetsConfig::default();
+        assert!(sheet.marketplaces.is_empty());
+        assert_eq!(accepted_marketplaces(&sheet, &matchers), matchers.names());
+        assert!(matchers.names().iter().any(|n| n == "Matalan"));
+
+        sheet.marketplaces = vec!["Debenhams".to_string()];
+        assert_eq!(accepted_marketplaces(&sheet, &matchers), vec!["Debenhams".to_string()]);
+    }
+}
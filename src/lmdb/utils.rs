@@ -1,30 +1,325 @@
-use heed::types::SerdeBincode;
+use std::collections::HashMap;
 
-use crate::schema::{ order::Order };
+use heed::{ byteorder::BigEndian, types::{ Bytes, SerdeBincode, Str, Unit, U64 } };
+
+use crate::{
+    config::settings::DbConfig,
+    lmdb::versioned::{ split_envelope, VersionedOrder, ORDER_SCHEMA_VERSION },
+    schema::{
+        catalog::CatalogEntry,
+        history::HistoryEntry,
+        jobs::JobRun,
+        order::Order,
+        outbox::OutboxEntry,
+        sync::{ RowState, SyncBaseline, SyncConflict },
+    },
+};
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct DB {
     pub env: heed::Env,
-    pub order_db: heed::Database<SerdeBincode<String>, SerdeBincode<Order>>,
+    /// Primary store, keyed by `Order.id`
+    pub order_db: heed::Database<SerdeBincode<String>, VersionedOrder>,
+    /// Soft-deleted orders keyed by `Order.id`, kept out of every index until restored
+    pub deleted_db: heed::Database<SerdeBincode<String>, VersionedOrder>,
+    /// `order_id` -> `id`
+    pub order_id_index: heed::Database<Str, Str>,
+    /// sheet row number -> `id`
+    pub row_number_index: heed::Database<U64<BigEndian>, Str>,
+    /// `"{marketplace}\u{1f}{id}"` -> ()
+    pub marketplace_index: heed::Database<Str, Unit>,
+    /// `"{field}\u{1f}{value}\u{1f}{id}"` -> (), backs listing filters and sorting
+    pub field_index: heed::Database<Str, Unit>,
+    /// Layout / bookkeeping values
+    pub meta_db: heed::Database<Str, Str>,
+    /// Append-only change log, `"{id}\u{1f}{seq:020}"` -> entry
+    pub history_db: heed::Database<Str, SerdeBincode<HistoryEntry>>,
+    /// `id` -> owned sheet values at the last sync
+    pub sync_db: heed::Database<Str, SerdeBincode<SyncBaseline>>,
+    /// `"{tab}\u{1f}{id}"` -> row fingerprint at the last sync
+    pub row_state_db: heed::Database<Str, SerdeBincode<RowState>>,
+    /// `"{id}\u{1f}{field}"` -> conflict waiting for review
+    pub conflict_db: heed::Database<Str, SerdeBincode<SyncConflict>>,
+    /// seq -> sheet write waiting for delivery
+    pub outbox_db: heed::Database<U64<BigEndian>, SerdeBincode<OutboxEntry>>,
+    /// seq -> sheet write that ran out of attempts
+    pub dead_letter_db: heed::Database<U64<BigEndian>, SerdeBincode<OutboxEntry>>,
+    /// `"{job}\u{1f}{seq:020}"` -> finished scheduler run
+    pub job_run_db: heed::Database<Str, SerdeBincode<JobRun>>,
+    /// compacted internal SKU -> catalog entry
+    pub catalog_db: heed::Database<Str, SerdeBincode<CatalogEntry>>,
+    /// `"{marketplace}\u{1f}{compacted sku}"` -> catalog key, marketplace empty for any
+    pub sku_alias_index: heed::Database<Str, Str>,
 }
 
-pub async fn init_db<P: AsRef<std::path::Path>>(path: P) -> Result<DB, anyhow::Error> {
+/// Old single-database layout, every order stored under its `order_id` and its row number.
+const LEGACY_ORDERS_DB: &str = "orders";
+const LAYOUT_VERSION_KEY: &str = "layout_version";
+const LAYOUT_VERSION: &str = "4";
+
+pub async fn init_db(config: &DbConfig) -> Result<DB, anyhow::Error> {
     let env = unsafe {
         heed::EnvOpenOptions
             ::new()
-            .map_size(1024 * 1024 * 1024) // 1GB
-            .max_dbs(3)
-            .open(path)?
+            .map_size(config.map_size)
+            .max_dbs(32)
+            .open(&config.path)?
     };
     let new_env = env.clone();
     let mut txn = new_env.write_txn()?;
-    let order_db = env
-        .create_database(&mut txn, Some("orders"))
-        .expect("Failed to create orders database");
+    let order_db = env.create_database(&mut txn, Some("orders_by_id"))?;
+    let deleted_db = env.create_database(&mut txn, Some("orders_deleted"))?;
+    let order_id_index = env.create_database(&mut txn, Some("orders_idx_order_id"))?;
+    let row_number_index = env.create_database(&mut txn, Some("orders_idx_row_number"))?;
+    let marketplace_index = env.create_database(&mut txn, Some("orders_idx_marketplace"))?;
+    let field_index = env.create_database(&mut txn, Some("orders_idx_fields"))?;
+    let meta_db = env.create_database(&mut txn, Some("meta"))?;
+    let history_db = env.create_database(&mut txn, Some("order_history"))?;
+    let sync_db = env.create_database(&mut txn, Some("sheet_sync_state"))?;
+    let row_state_db = env.create_database(&mut txn, Some("sheet_row_state"))?;
+    let conflict_db = env.create_database(&mut txn, Some("sheet_sync_conflicts"))?;
+    let outbox_db = env.create_database(&mut txn, Some("sheet_outbox"))?;
+    let dead_letter_db = env.create_database(&mut txn, Some("sheet_outbox_dead"))?;
+    let job_run_db = env.create_database(&mut txn, Some("job_runs"))?;
+    let catalog_db = env.create_database(&mut txn, Some("sku_catalog"))?;
+    let sku_alias_index = env.create_database(&mut txn, Some("sku_catalog_idx_alias"))?;
     txn.commit()?;
 
-    Ok(DB {
+    let db = DB {
         env,
         order_db,
-    })
+        deleted_db,
+        order_id_index,
+        row_number_index,
+        marketplace_index,
+        field_index,
+        meta_db,
+        history_db,
+        sync_db,
+        row_state_db,
+        conflict_db,
+        outbox_db,
+        dead_letter_db,
+        job_run_db,
+        catalog_db,
+        sku_alias_index,
+    };
+    migrate_legacy_layout(&db)?;
+    upgrade_stored_orders(&db)?;
+
+    Ok(db)
+}
+
+/// One-time move from the dual-keyed `orders` database to the primary + index layout.
+/// Copies are deduplicated by `order_id`, preferring the record stored under its own
+/// `order_id` key since that is the one `insert_all` kept up to date.
+/// Databases already on the indexed layout only get their indexes rebuilt when it changes.
+fn migrate_legacy_layout(db: &DB) -> Result<(), anyhow::Error> {
+    let mut txn = db.env.write_txn()?;
+    match db.meta_db.get(&txn, LAYOUT_VERSION_KEY)? {
+        Some(LAYOUT_VERSION) => {
+            return Ok(());
+        }
+        Some(version) => {
+            println!("Rebuilding order indexes for layout {} -> {}", version, LAYOUT_VERSION);
+            db.reindex_all(&mut txn).map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            db.meta_db.put(&mut txn, LAYOUT_VERSION_KEY, LAYOUT_VERSION)?;
+            txn.commit()?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let legacy: Option<heed::Database<SerdeBincode<String>, VersionedOrder>> = db.env.open_database(
+        &txn,
+        Some(LEGACY_ORDERS_DB)
+    )?;
+    if let Some(legacy) = legacy {
+        let mut by_order_id: HashMap<String, Order> = HashMap::new();
+        let mut aliased: Vec<Order> = Vec::new();
+        for result in legacy.iter(&txn)? {
+            let (key, order) = result?;
+            if key == order.order_id {
+                by_order_id.insert(order.order_id.clone(), order);
+            } else {
+                aliased.push(order);
+            }
+        }
+        for order in aliased {
+            by_order_id.entry(order.order_id.clone()).or_insert(order);
+        }
+
+        println!("Migrating {} orders to indexed layout", by_order_id.len());
+        for order in by_order_id.values() {
+            db.write_order(&mut txn, order).map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        }
+        legacy.clear(&mut txn)?;
+    }
+
+    db.meta_db.put(&mut txn, LAYOUT_VERSION_KEY, LAYOUT_VERSION)?;
+    txn.commit()?;
+    Ok(())
+}
+
+/// Keys of the records in `orders` stored with an older schema version.
+fn outdated_orders(
+    txn: &heed::RoTxn,
+    orders: heed::Database<SerdeBincode<String>, VersionedOrder>
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut outdated = Vec::new();
+    for result in orders.remap_data_type::<Bytes>().iter(txn)? {
+        let (key, bytes) = result?;
+        let (version, _) = split_envelope(bytes);
+        if version < ORDER_SCHEMA_VERSION {
+            outdated.push(key);
+        }
+    }
+    Ok(outdated)
+}
+
+/// Rewrite every live and soft-deleted record stored with an older schema version in the
+/// current one. Reads already upgrade on the fly, this just keeps the stored data from drifting.
+fn upgrade_stored_orders(db: &DB) -> Result<(), anyhow::Error> {
+    let mut txn = db.env.write_txn()?;
+    let outdated = outdated_orders(&txn, db.order_db)?;
+    let outdated_deleted = outdated_orders(&txn, db.deleted_db)?;
+
+    if !outdated.is_empty() || !outdated_deleted.is_empty() {
+        println!(
+            "Upgrading {} orders and {} deleted orders to schema version {}",
+            outdated.len(),
+            outdated_deleted.len(),
+            ORDER_SCHEMA_VERSION
+        );
+    }
+    for key in outdated {
+        let order = db.order_db
+            .get(&txn, &key)?
+            .ok_or_else(|| anyhow::anyhow!("order {} vanished during upgrade", key))?;
+        db.write_order(&mut txn, &order).map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    }
+    // deleted orders stay out of the indexes, so they are rewritten as they are
+    for key in outdated_deleted {
+        let order = db.deleted_db
+            .get(&txn, &key)?
+            .ok_or_else(|| anyhow::anyhow!("deleted order {} vanished during upgrade", key))?;
+        db.deleted_db.put(&mut txn, &key, &order)?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        lmdb::versioned::tests::{ expected_v1, ORDER_V1 },
+        schema::legacy::{ OrderV1, OrderV2, OrderV3 },
+    };
+
+    /// A database in `dir`, small enough for tests.
+    pub(crate) async fn temp_db(dir: &tempfile::TempDir) -> DB {
+        let config = DbConfig { path: dir.path().to_str().unwrap().to_string(), map_size: 10 * 1024 * 1024 };
+        init_db(&config).await.unwrap()
+    }
+
+    /// Orders in the legacy database predate the envelope, so they are version 1 records.
+    fn order(id: &str, order_id: &str, row_number: usize, status: &str) -> OrderV1 {
+        OrderV1 {
+            id: id.to_string(),
+            marketplace: "Debenhams".to_string(),
+            order_id: order_id.to_string(),
+            return_order: None,
+            shopify_id: None,
+            market_place_code: None,
+            returned_sku: Some("TSHIRT-RED-M".to_string()),
+            offer_sku: None,
+            matched_sku: None,
+            match_type: None,
+            row_number: Some(row_number),
+            manual_confirmation: None,
+            status: Some(status.to_string()),
+            qty: Some(1),
+            main_updated: None,
+            date: "2024-03-04".to_string(),
+            created_at: "2024-03-04T10:00:00+00:00".to_string(),
+            updated_at: "2024-03-04T10:00:00+00:00".to_string(),
+            boolean: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_orders_move_to_the_indexed_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        // every order under its order_id and again under its row number, the second
+        // copy going stale as soon as the order was updated
+        let current = order("a1", "104522", 2, "matched");
+        let stale = order("a1", "104522", 2, "received");
+        let row_only = order("b2", "104541", 3, "received");
+        {
+            let env = unsafe { heed::EnvOpenOptions::new().max_dbs(16).open(dir.path()).unwrap() };
+            let mut txn = env.write_txn().unwrap();
+            let legacy: heed::Database<SerdeBincode<String>, SerdeBincode<OrderV1>> = env
+                .create_database(&mut txn, Some(LEGACY_ORDERS_DB))
+                .unwrap();
+            legacy.put(&mut txn, &"2".to_string(), &stale).unwrap();
+            legacy.put(&mut txn, &"104522".to_string(), &current).unwrap();
+            legacy.put(&mut txn, &"3".to_string(), &row_only).unwrap();
+            txn.commit().unwrap();
+            env.prepare_for_closing().wait();
+        }
+
+        let db = temp_db(&dir).await;
+        let txn = db.env.read_txn().unwrap();
+        assert_eq!(db.order_db.len(&txn).unwrap(), 2);
+        let upgraded = |v1: OrderV1| Order::from(OrderV3::from(OrderV2::from(v1)));
+        assert_eq!(db.order_db.get(&txn, &"a1".to_string()).unwrap(), Some(upgraded(current)));
+        assert_eq!(db.order_db.get(&txn, &"b2".to_string()).unwrap(), Some(upgraded(row_only)));
+        assert_eq!(db.order_id_index.get(&txn, "104541").unwrap(), Some("b2"));
+        assert_eq!(db.row_number_index.get(&txn, &2).unwrap(), Some("a1"));
+        assert_eq!(db.marketplace_index.len(&txn).unwrap(), 2);
+        assert_eq!(db.meta_db.get(&txn, LAYOUT_VERSION_KEY).unwrap(), Some(LAYOUT_VERSION));
+        let legacy: heed::Database<SerdeBincode<String>, SerdeBincode<OrderV1>> = db.env
+            .open_database(&txn, Some(LEGACY_ORDERS_DB))
+            .unwrap()
+            .unwrap();
+        assert!(legacy.is_empty(&txn).unwrap());
+    }
+
+    fn stored_version(
+        db: &DB,
+        orders: heed::Database<SerdeBincode<String>, VersionedOrder>,
+        id: &str
+    ) -> u16 {
+        let txn = db.env.read_txn().unwrap();
+        let bytes = orders.remap_data_type::<Bytes>().get(&txn, &id.to_string()).unwrap().unwrap();
+        split_envelope(bytes).0
+    }
+
+    #[tokio::test]
+    async fn outdated_records_are_rewritten_and_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let order = expected_v1();
+        // the same v1 record, once live and once soft-deleted
+        let deleted_id = "deleted".to_string();
+
+        let mut txn = db.env.write_txn().unwrap();
+        db.order_db.remap_data_type::<Bytes>().put(&mut txn, &order.id, ORDER_V1).unwrap();
+        db.deleted_db.remap_data_type::<Bytes>().put(&mut txn, &deleted_id, ORDER_V1).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(stored_version(&db, db.order_db, &order.id), 1);
+        assert_eq!(stored_version(&db, db.deleted_db, &deleted_id), 1);
+
+        upgrade_stored_orders(&db).unwrap();
+
+        assert_eq!(stored_version(&db, db.order_db, &order.id), ORDER_SCHEMA_VERSION);
+        assert_eq!(stored_version(&db, db.deleted_db, &deleted_id), ORDER_SCHEMA_VERSION);
+        let txn = db.env.read_txn().unwrap();
+        assert_eq!(db.order_db.get(&txn, &order.id).unwrap(), Some(order.clone()));
+        assert_eq!(db.deleted_db.get(&txn, &deleted_id).unwrap(), Some(order.clone()));
+        // only the live one is indexed
+        assert_eq!(db.order_id_index.get(&txn, &order.order_id).unwrap(), Some(order.id.as_str()));
+        assert_eq!(db.order_id_index.len(&txn).unwrap(), 1);
+    }
 }
@@ -0,0 +1,204 @@
+use std::borrow::Cow;
+
+use heed::{ BoxedError, BytesDecode, BytesEncode, types::SerdeBincode };
+
+use crate::schema::{ legacy::{ OrderV1, OrderV2, OrderV3 }, order::Order };
+
+/// Version written for every new record. Bump it together with a new arm in
+/// `decode_order` whenever the layout of `Order` changes.
+pub const ORDER_SCHEMA_VERSION: u16 = 4;
+
+/// Records start with this tag followed by a big-endian `u16` version.
+/// Anything without it was written before the envelope existed and is version 1.
+const ENVELOPE_MAGIC: &[u8; 4] = b"ORDV";
+const ENVELOPE_HEADER_LEN: usize = ENVELOPE_MAGIC.len() + 2;
+
+/// heed codec storing `Order`s inside a versioned envelope.
+pub struct VersionedOrder;
+
+impl<'a> BytesEncode<'a> for VersionedOrder {
+    type EItem = Order;
+
+    fn bytes_encode(order: &'a Order) -> Result<Cow<'a, [u8]>, BoxedError> {
+        let payload = SerdeBincode::<Order>::bytes_encode(order)?;
+        let mut bytes = Vec::with_capacity(ENVELOPE_HEADER_LEN + payload.len());
+        bytes.extend_from_slice(ENVELOPE_MAGIC);
+        bytes.extend_from_slice(&ORDER_SCHEMA_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+impl<'a> BytesDecode<'a> for VersionedOrder {
+    type DItem = Order;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Order, BoxedError> {
+        let (version, payload) = split_envelope(bytes);
+        decode_order(version, payload)
+    }
+}
+
+/// Split a stored record into its schema version and the bincode payload.
+pub fn split_envelope(bytes: &[u8]) -> (u16, &[u8]) {
+    if bytes.len() >= ENVELOPE_HEADER_LEN && bytes.starts_with(ENVELOPE_MAGIC) {
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        (version, &bytes[ENVELOPE_HEADER_LEN..])
+    } else {
+        (1, bytes)
+    }
+}
+
+/// Decode a payload written with `version` and upgrade it to the current `Order`.
+fn decode_order(version: u16, payload: &[u8]) -> Result<Order, BoxedError> {
+    match version {
+        1 =>
+            SerdeBincode::<OrderV1>
+                ::bytes_decode(payload)
+                .map(|v1| OrderV3::from(OrderV2::from(v1)).into()),
+        2 => SerdeBincode::<OrderV2>::bytes_decode(payload).map(|v2| OrderV3::from(v2).into()),
+        3 => SerdeBincode::<OrderV3>::bytes_decode(payload).map(Order::from),
+        4 => SerdeBincode::<Order>::bytes_decode(payload),
+        v if v > ORDER_SCHEMA_VERSION => {
+            Err(
+                format!(
+                    "order record has schema version {} but this build only understands up to {}",
+                    v,
+                    ORDER_SCHEMA_VERSION
+                ).into()
+            )
+        }
+        v => Err(format!("no migration registered for order schema version {}", v).into()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::schema::status::{ MainUpdated, ManualConfirmation, MatchType, OrderStatus };
+
+    /// Records as older builds wrote them: v1 predates the envelope.
+    pub(crate) const ORDER_V1: &[u8] = include_bytes!("../../tests/fixtures/orders/order_v1.bin");
+    pub(crate) const ORDER_V2: &[u8] = include_bytes!("../../tests/fixtures/orders/order_v2.bin");
+    pub(crate) const ORDER_V3: &[u8] = include_bytes!("../../tests/fixtures/orders/order_v3.bin");
+
+    pub(crate) fn expected_v1() -> Order {
+        Order {
+            id: "0b9c6f5e-1c2d-4e3f-8a7b-6c5d4e3f2a01".to_string(),
+            marketplace: "Debenhams".to_string(),
+            order_id: "104522".to_string(),
+            return_order: Some(55012),
+            shopify_id: Some("#SH10452".to_string()),
+            market_place_code: Some("DB-7781234".to_string()),
+            returned_sku: Some("TSHIRT-RED-M".to_string()),
+            offer_sku: None,
+            matched_sku: Some("TSHIRT-RED-M".to_string()),
+            match_type: Some(MatchType::FullMatch),
+            match_confidence: None,
+            match_explanation: None,
+            matched_line: None,
+            row_number: Some(14),
+            // stored as "no idea", which no variant matches
+            manual_confirmation: None,
+            status: Some(OrderStatus::Matched),
+            qty: Some(1),
+            main_updated: Some(MainUpdated::Pending),
+            date: "2024-03-04T09:12:44+00:00".to_string(),
+            created_at: "2024-03-04T10:00:00+00:00".to_string(),
+            updated_at: "2024-03-05T08:30:00+00:00".to_string(),
+            boolean: false,
+        }
+    }
+
+    pub(crate) fn expected_v2() -> Order {
+        Order {
+            id: "5d2e8a41-7b3c-4f6d-9e1a-2b3c4d5e6f02".to_string(),
+            marketplace: "Matalan".to_string(),
+            order_id: "104541".to_string(),
+            return_order: None,
+            shopify_id: Some("000".to_string()),
+            market_place_code: Some("MAT-2024-88120".to_string()),
+            returned_sku: Some("DRESS-GRN-10".to_string()),
+            offer_sku: None,
+            matched_sku: None,
+            match_type: Some(MatchType::NoMatch),
+            match_confidence: None,
+            match_explanation: None,
+            matched_line: None,
+            row_number: Some(21),
+            manual_confirmation: Some(ManualConfirmation::Pending),
+            status: Some(OrderStatus::Received),
+            qty: Some(2),
+            main_updated: Some(MainUpdated::Updated),
+            date: "2024-03-06T11:02:00+00:00".to_string(),
+            created_at: "2024-03-06T12:00:00+00:00".to_string(),
+            updated_at: "2024-03-06T12:00:00+00:00".to_string(),
+            boolean: true,
+        }
+    }
+
+    pub(crate) fn expected_v3() -> Order {
+        Order {
+            id: "9f8e7d6c-5b4a-4c3d-8e2f-1a0b9c8d7e03".to_string(),
+            marketplace: "Secret Sales".to_string(),
+            order_id: "104530".to_string(),
+            return_order: Some(55020),
+            shopify_id: Some("000".to_string()),
+            market_place_code: Some("SS-556677".to_string()),
+            returned_sku: Some("jeans-blue-32".to_string()),
+            offer_sku: None,
+            matched_sku: Some("JEANS-BLUE-32".to_string()),
+            match_type: Some(MatchType::FullMatch),
+            match_confidence: None,
+            match_explanation: None,
+            matched_line: Some(2),
+            row_number: Some(30),
+            manual_confirmation: Some(ManualConfirmation::Pending),
+            status: Some(OrderStatus::Matched),
+            qty: Some(1),
+            main_updated: None,
+            date: "2024-03-07T15:45:00+00:00".to_string(),
+            created_at: "2024-03-07T16:00:00+00:00".to_string(),
+            updated_at: "2024-03-08T09:10:00+00:00".to_string(),
+            boolean: false,
+        }
+    }
+
+    #[test]
+    fn decodes_v1_records_without_envelope() {
+        assert_eq!(split_envelope(ORDER_V1).0, 1);
+        assert_eq!(VersionedOrder::bytes_decode(ORDER_V1).unwrap(), expected_v1());
+    }
+
+    #[test]
+    fn decodes_v2_records() {
+        assert_eq!(split_envelope(ORDER_V2).0, 2);
+        assert_eq!(VersionedOrder::bytes_decode(ORDER_V2).unwrap(), expected_v2());
+    }
+
+    #[test]
+    fn decodes_v3_records() {
+        assert_eq!(split_envelope(ORDER_V3).0, 3);
+        assert_eq!(VersionedOrder::bytes_decode(ORDER_V3).unwrap(), expected_v3());
+    }
+
+    #[test]
+    fn current_records_round_trip() {
+        let order = Order {
+            match_confidence: Some(95),
+            match_explanation: Some("line 2 of 2 (\"jeans-blue-32\"): same SKU ignoring case and spaces".to_string()),
+            ..expected_v3()
+        };
+        let bytes = VersionedOrder::bytes_encode(&order).unwrap();
+        assert!(bytes.starts_with(ENVELOPE_MAGIC));
+        assert_eq!(split_envelope(&bytes).0, ORDER_SCHEMA_VERSION);
+        assert_eq!(VersionedOrder::bytes_decode(&bytes).unwrap(), order);
+    }
+
+    #[test]
+    fn rejects_future_versions() {
+        let mut bytes = VersionedOrder::bytes_encode(&expected_v1()).unwrap().into_owned();
+        bytes[4..ENVELOPE_HEADER_LEN].copy_from_slice(&(ORDER_SCHEMA_VERSION + 1).to_be_bytes());
+        let err = VersionedOrder::bytes_decode(&bytes).unwrap_err();
+        assert!(err.to_string().contains("only understands up to"), "{}", err);
+    }
+}
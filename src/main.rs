@@ -2,7 +2,23 @@ use actix_web::{ web, App, HttpServer };
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{ lmdb::utils::init_db, routes::order::order_config, utopia::openapi::ApiDoc };
+use crate::{
+    config::settings::{ AppConfig, MarketplaceRulesConfig, SheetsConfig },
+    lmdb::utils::init_db,
+    routes::{ catalog::catalog_config, jobs::jobs_config, order::order_config, outbox::outbox_config, sync::sync_config },
+    scripts::{
+        fake_sheets::FakeSheets,
+        http::HttpClient,
+        jobs::JobScheduler,
+        linnworks::LinnworksSession,
+        marketplaces::MatcherRegistry,
+        marketplace_rules::{ registry_from_rules, watch_rules },
+        auth::{ ServiceAccount, TokenProvider },
+        sheets::GoogleSheetsClient,
+    },
+    utopia::openapi::ApiDoc,
+};
+mod config;
 mod scripts;
 mod lmdb;
 mod utopia;
@@ -10,20 +26,73 @@ mod schema;
 mod routes;
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let path = "./lmdb_data";
-    let db = init_db(path).await.expect("Failed to initialize database");
-    // initialise env
-    // dotenv::dotenv().ok();
-    println!("🚀 Server starting at http://127.0.0.1:8080");
+    let config = AppConfig::load().map_err(|e| std::io::Error::other(e.to_string()))?;
+    let db = init_db(&config.db).await.expect("Failed to initialize database");
+    let http = HttpClient::new(&config.http).map_err(std::io::Error::other)?;
+    let sheets = sheets_client(&config.sheets, &http)?;
+    let linnworks = LinnworksSession::new(http.clone(), config.linnworks.clone());
+    let matchers = marketplace_matchers(&config.marketplace_rules)?;
+    let scheduler = JobScheduler::new(
+        db.clone(),
+        sheets.clone(),
+        linnworks.clone(),
+        matchers.clone(),
+        config.clone()
+    );
+    scheduler.start();
+    let bind = (config.server.host.clone(), config.server.port);
+    println!("🚀 Server starting at http://{}:{}", bind.0, bind.1);
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(sheets.clone()))
+            .app_data(web::Data::new(linnworks.clone()))
+            .app_data(web::Data::new(matchers.clone()))
+            .app_data(web::Data::new(scheduler.clone()))
             .configure(order_config) // routes
+            .configure(sync_config)
+            .configure(outbox_config)
+            .configure(jobs_config)
+            .configure(catalog_config)
             .service(
                 SwaggerUi::new("/docs/{_:.*}").url("/api-docs/openapi.json", ApiDoc::openapi())
             )
     })
-        .bind(("127.0.0.1", 8080))?
+        .bind(bind)?
         .run().await
 }
+
+/// The rules from the configured file, kept up to date with it, or the built-in matchers.
+fn marketplace_matchers(config: &MarketplaceRulesConfig) -> std::io::Result<MatcherRegistry> {
+    let Some(path) = &config.path else {
+        return Ok(MatcherRegistry::with_builtin());
+    };
+    let registry = registry_from_rules(path).map_err(|e| std::io::Error::other(e.to_string()))?;
+    println!("📏 Marketplace rules loaded from {}", path);
+    if config.reload_interval_secs > 0 {
+        watch_rules(registry.clone(), path.clone(), config.reload_interval_secs);
+    }
+    Ok(registry)
+}
+
+/// Either the in-memory fake (seeded from `sheets.fake_seed`) or Google at the configured URLs.
+fn sheets_client(config: &SheetsConfig, http: &HttpClient) -> std::io::Result<GoogleSheetsClient> {
+    if config.fake {
+        let fake = FakeSheets::default();
+        if let Some(seed) = &config.fake_seed {
+            fake.seed_from_file(seed).map_err(|e| std::io::Error::other(e.to_string()))?;
+        }
+        let base_url = fake.start()?;
+        println!("🧪 Using fake Google Sheets at {}", base_url);
+        let tokens = TokenProvider::new(http.clone(), &format!("{}/token", base_url));
+        return Ok(GoogleSheetsClient::new(http.clone(), &base_url, tokens, None));
+    }
+
+    let credentials = ServiceAccount::load(&config.service_account_path).map_err(|e|
+        std::io::Error::other(e.to_string())
+    )?;
+    let tokens = TokenProvider::new(http.clone(), &config.token_url);
+    Ok(GoogleSheetsClient::new(http.clone(), &config.base_url, tokens, Some(credentials)))
+}
@@ -0,0 +1,180 @@
+use std::error::Error;
+
+use actix_web::{ web, HttpResponse, Responder };
+
+use crate::{
+    lmdb::{ catalog::DBCatalog, utils::DB },
+    schema::catalog::{
+        parse_catalog_csv,
+        CatalogEntry,
+        CatalogError,
+        CatalogImportReport,
+        InvalidCatalogEntry,
+    },
+};
+
+/// 409 for conflicting SKUs and aliases, 400 for entries that can't be stored.
+fn catalog_error(e: Box<dyn Error>) -> HttpResponse {
+    if e.is::<CatalogError>() {
+        HttpResponse::Conflict().body(e.to_string())
+    } else if e.is::<InvalidCatalogEntry>() {
+        HttpResponse::BadRequest().body(e.to_string())
+    } else {
+        HttpResponse::InternalServerError().body(format!("Catalog error: {}", e))
+    }
+}
+
+/// Every catalog entry, ordered by SKU
+#[utoipa::path(
+    get,
+    path = "/catalog",
+    responses(
+        (status = 200, description = "Catalog entries", body = [CatalogEntry]),
+        (status = 500, description = "Catalog error")
+    )
+)]
+pub async fn list_catalog(db: web::Data<DB>) -> impl Responder {
+    match db.catalog_entries() {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Get a catalog entry by its internal SKU
+#[utoipa::path(
+    get,
+    path = "/catalog/{sku}",
+    params(("sku" = String, Path, description = "Internal SKU")),
+    responses(
+        (status = 200, description = "Catalog entry", body = CatalogEntry),
+        (status = 404, description = "SKU not in the catalog"),
+        (status = 500, description = "Catalog error")
+    )
+)]
+pub async fn get_catalog_entry(db: web::Data<DB>, path: web::Path<String>) -> impl Responder {
+    match db.catalog_entry(&path) {
+        Ok(Some(entry)) => HttpResponse::Ok().json(entry),
+        Ok(None) => HttpResponse::NotFound().body("SKU not found"),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Add a SKU to the catalog
+#[utoipa::path(
+    post,
+    path = "/catalog",
+    request_body = CatalogEntry,
+    responses(
+        (status = 201, description = "Entry added", body = CatalogEntry),
+        (status = 400, description = "Empty SKU or alias"),
+        (status = 409, description = "The SKU or one of its aliases is taken"),
+        (status = 500, description = "Catalog error")
+    )
+)]
+pub async fn insert_catalog_entry(
+    db: web::Data<DB>,
+    item: web::Json<CatalogEntry>
+) -> impl Responder {
+    match db.insert_catalog_entry(item.into_inner()) {
+        Ok(entry) => HttpResponse::Created().json(entry),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Replace a catalog entry, the SKU itself may change
+#[utoipa::path(
+    put,
+    path = "/catalog/{sku}",
+    params(("sku" = String, Path, description = "Internal SKU")),
+    request_body = CatalogEntry,
+    responses(
+        (status = 200, description = "Entry replaced", body = CatalogEntry),
+        (status = 400, description = "Empty SKU or alias"),
+        (status = 404, description = "SKU not in the catalog"),
+        (status = 409, description = "The SKU or one of its aliases is taken"),
+        (status = 500, description = "Catalog error")
+    )
+)]
+pub async fn replace_catalog_entry(
+    db: web::Data<DB>,
+    path: web::Path<String>,
+    item: web::Json<CatalogEntry>
+) -> impl Responder {
+    match db.replace_catalog_entry(&path, item.into_inner()) {
+        Ok(Some(entry)) => HttpResponse::Ok().json(entry),
+        Ok(None) => HttpResponse::NotFound().body("SKU not found"),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Remove a SKU and its aliases from the catalog
+#[utoipa::path(
+    delete,
+    path = "/catalog/{sku}",
+    params(("sku" = String, Path, description = "Internal SKU")),
+    responses(
+        (status = 200, description = "Entry removed", body = CatalogEntry),
+        (status = 404, description = "SKU not in the catalog"),
+        (status = 500, description = "Catalog error")
+    )
+)]
+pub async fn delete_catalog_entry(db: web::Data<DB>, path: web::Path<String>) -> impl Responder {
+    match db.delete_catalog_entry(&path) {
+        Ok(Some(entry)) => HttpResponse::Ok().json(entry),
+        Ok(None) => HttpResponse::NotFound().body("SKU not found"),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Bulk add SKUs and aliases from a CSV with a `sku,marketplace,alias,title` header.
+/// Existing entries keep their aliases, lines that can't be applied are reported.
+#[utoipa::path(
+    post,
+    path = "/catalog/import",
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (status = 200, description = "Import applied", body = CatalogImportReport),
+        (status = 400, description = "Empty CSV or no sku column"),
+        (status = 500, description = "Catalog error")
+    )
+)]
+pub async fn import_catalog(db: web::Data<DB>, body: String) -> impl Responder {
+    let (rows, rejected) = match parse_catalog_csv(&body) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return HttpResponse::BadRequest().body(e.to_string());
+        }
+    };
+    match db.import_catalog(rows) {
+        Ok(mut report) => {
+            report.rejected.extend(rejected);
+            report.rejected.sort_by_key(|r| r.line);
+            println!(
+                "📦 Catalog import: {} created, {} updated, {} rejected",
+                report.created,
+                report.updated,
+                report.rejected.len()
+            );
+            HttpResponse::Ok().json(report)
+        }
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Configure routes for the SKU catalog
+pub fn catalog_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web
+            ::resource("/catalog")
+            .route(web::get().to(list_catalog))
+            .route(web::post().to(insert_catalog_entry))
+    )
+        .service(web::resource("/catalog/import").route(web::post().to(import_catalog)))
+        .service(
+            web
+                ::resource("/catalog/{sku}")
+                .route(web::get().to(get_catalog_entry))
+                .route(web::put().to(replace_catalog_entry))
+                .route(web::delete().to(delete_catalog_entry))
+        );
+}
@@ -0,0 +1,81 @@
+use actix_web::{ web, HttpResponse, Responder };
+
+use crate::{
+    lmdb::{ jobs::DBJobs, utils::DB },
+    schema::jobs::{ JobKind, JobRun, JobStatus, JobTrigger },
+    scripts::{ jobs::JobScheduler, sheets::GoogleSheetsClient },
+};
+
+fn job_from_path(name: &str) -> Option<JobKind> {
+    JobKind::ALL.into_iter().find(|job| job.as_str() == name)
+}
+
+/// Background jobs with their schedule and last run
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    responses(
+        (status = 200, description = "Job status", body = [JobStatus]),
+        (status = 500, description = "Job error")
+    )
+)]
+pub async fn list_jobs(scheduler: web::Data<JobScheduler<GoogleSheetsClient>>) -> impl Responder {
+    match scheduler.status() {
+        Ok(jobs) => HttpResponse::Ok().json(jobs),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Job error: {}", e)),
+    }
+}
+
+/// Run a job now
+#[utoipa::path(
+    post,
+    path = "/jobs/{job}/run",
+    params(("job" = JobKind, Path, description = "Job to run")),
+    responses(
+        (status = 200, description = "Run finished, successfully or not", body = JobRun),
+        (status = 404, description = "No such job"),
+        (status = 409, description = "The job or an overlapping one is running"),
+        (status = 500, description = "Job error")
+    )
+)]
+pub async fn run_job(
+    scheduler: web::Data<JobScheduler<GoogleSheetsClient>>,
+    path: web::Path<String>
+) -> impl Responder {
+    let Some(job) = job_from_path(&path) else {
+        return HttpResponse::NotFound().body("Job not found");
+    };
+    match scheduler.run(job, JobTrigger::Manual).await {
+        Ok(Some(run)) => HttpResponse::Ok().json(run),
+        Ok(None) => HttpResponse::Conflict().body(format!("Job {} is busy", job.as_str())),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Job error: {}", e)),
+    }
+}
+
+/// Recorded runs of a job, oldest first
+#[utoipa::path(
+    get,
+    path = "/jobs/{job}/runs",
+    params(("job" = JobKind, Path, description = "Job")),
+    responses(
+        (status = 200, description = "Recorded runs", body = [JobRun]),
+        (status = 404, description = "No such job"),
+        (status = 500, description = "Job error")
+    )
+)]
+pub async fn list_job_runs(db: web::Data<DB>, path: web::Path<String>) -> impl Responder {
+    let Some(job) = job_from_path(&path) else {
+        return HttpResponse::NotFound().body("Job not found");
+    };
+    match db.job_runs(job) {
+        Ok(runs) => HttpResponse::Ok().json(runs),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Job error: {}", e)),
+    }
+}
+
+/// Configure routes for background jobs
+pub fn jobs_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/jobs").route(web::get().to(list_jobs)))
+        .service(web::resource("/jobs/{job}/run").route(web::post().to(run_job)))
+        .service(web::resource("/jobs/{job}/runs").route(web::get().to(list_job_runs)));
+}
@@ -1,2 +1,6 @@
 pub mod order;
+pub mod sync;
+pub mod outbox;
+pub mod jobs;
+pub mod catalog;
 // pub mod linnworks_order;
@@ -1,22 +1,26 @@
-use std::fs;
-use actix_web::{ web, HttpResponse, Responder };
+use actix_web::{ web, HttpRequest, HttpResponse, Responder };
 use serde::Deserialize;
+use utoipa::IntoParams;
 use crate::{
-    lmdb::{ order::DBOrder, utils::DB },
-    schema::order::Order,
+    config::settings::AppConfig,
+    lmdb::{ history::DBHistory, order::DBOrder, query::DBOrderQuery, sync::DBSync, utils::DB },
+    schema::{
+        history::{ ChangeContext, HistoryEntry },
+        order::{ Order, RestoreError },
+        jobs::JobKind,
+        order_query::{ OrderPage, OrderQuery, QueryError },
+        status::TransitionError,
+    },
+    routes::sync::DryRunParams,
     scripts::{
-        order::{ append_to_google_sheets, update_order_in_sheets },
-        update_fixed::update,
-        utils::get_or_generate_token,
+        linnworks::LinnworksSession,
+        marketplaces::{ AmbiguousMarketplace, MatcherRegistry },
+        jobs::JobScheduler,
+        sheets::GoogleSheetsClient,
+        update_fixed::{ preview_update, update },
     },
 };
 
-#[derive(serde::Deserialize)]
-struct ServiceAccount {
-    client_email: String,
-    private_key: String,
-}
-
 /// Insert a new Order
 #[utoipa::path(
     post,
@@ -27,29 +31,38 @@ struct ServiceAccount {
         (status = 500, description = "Insert error")
     )
 )]
-pub async fn insert_order(db: web::Data<DB>, item: web::Json<Order>) -> impl Responder {
-    let order = item.into_inner();
-    match db.insert(order.clone()).await {
-        Ok(_) => {
-            let values = Order::to_sheet1_row(&order).await;
-            let file_content = fs::read_to_string("./src/service_account.json");
-            let sa: ServiceAccount = serde_json::from_str(&file_content.unwrap()).unwrap();
-            let access_token = get_or_generate_token(&sa.client_email, &sa.private_key).await;
-            let _res = append_to_google_sheets(
-                access_token.unwrap(),
-                "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas",
-                "Sheet1!A:Z",
-                values
-            ).await.unwrap();
-            HttpResponse::Created().finish()
-        }
+pub async fn insert_order(
+    req: HttpRequest,
+    db: web::Data<DB>,
+    item: web::Json<Order>
+) -> impl Responder {
+    // the sheet row is appended by the outbox worker
+    match db.insert(item.into_inner(), &ChangeContext::http(&req)) {
+        Ok(_) => HttpResponse::Created().finish(),
         Err(e) => HttpResponse::InternalServerError().body(format!("Insert error: {}", e)),
     }
 }
 
-pub async fn insert_all(db: web::Data<DB>) -> impl Responder {
-    match db.insert_all().await {
-        Ok(_) => HttpResponse::Created().finish(),
+pub async fn insert_all(
+    db: web::Data<DB>,
+    config: web::Data<AppConfig>,
+    sheets: web::Data<GoogleSheetsClient>,
+    matchers: web::Data<MatcherRegistry>,
+    scheduler: web::Data<JobScheduler<GoogleSheetsClient>>,
+    query: web::Query<DryRunParams>
+) -> impl Responder {
+    if query.dry_run {
+        return match db.preview_sync(sheets.get_ref(), &config.sheets, &matchers).await {
+            Ok(preview) => HttpResponse::Ok().json(preview),
+            Err(e) => HttpResponse::InternalServerError().body(format!("Insert error: {}", e)),
+        };
+    }
+    let Some(_guard) = scheduler.try_claim(JobKind::SheetImport) else {
+        return HttpResponse::Conflict().body("A sheet import or outbox flush is running");
+    };
+    match db.sync_sheet(sheets.get_ref(), &config.sheets, &matchers, None).await {
+        Ok(report) => HttpResponse::Created().json(report),
+        Err(e) if e.is::<TransitionError>() => HttpResponse::Conflict().body(e.to_string()),
         Err(e) => HttpResponse::InternalServerError().body(format!("Insert error: {}", e)),
     }
 }
@@ -73,19 +86,21 @@ pub async fn get_order(db: web::Data<DB>, path: web::Path<String>) -> impl Respo
     }
 }
 
-/// List all Orders
+/// List Orders, filtered and paginated
 #[utoipa::path(
     get,
     path = "/orders",
+    params(OrderQuery),
     responses(
-        (status = 200, description = "List all orders", body = [Order]),
+        (status = 200, description = "One page of matching orders", body = OrderPage),
+        (status = 400, description = "Invalid filter or cursor"),
         (status = 500, description = "List error")
     )
 )]
-pub async fn list_orders(db: web::Data<DB>) -> impl Responder {
-    match db.get() {
-        Ok(Some(orders)) => HttpResponse::Ok().json(orders),
-        Ok(None) => HttpResponse::Ok().json(Vec::<Order>::new()), // empty list instead of None
+pub async fn list_orders(db: web::Data<DB>, query: web::Query<OrderQuery>) -> impl Responder {
+    match db.query(&query.into_inner()) {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(e) if e.is::<QueryError>() => HttpResponse::BadRequest().body(e.to_string()),
         Err(e) => HttpResponse::InternalServerError().body(format!("List error: {}", e)),
     }
 }
@@ -97,70 +112,158 @@ pub async fn list_orders(db: web::Data<DB>) -> impl Responder {
     request_body = Order,
     responses(
         (status = 200, description = "Order updated"),
+        (status = 409, description = "Status transition not allowed"),
         (status = 500, description = "Update error")
     )
 )]
-pub async fn update_order(db: web::Data<DB>, item: web::Json<Order>) -> impl Responder {
+pub async fn update_order(
+    req: HttpRequest,
+    db: web::Data<DB>,
+    item: web::Json<Order>
+) -> impl Responder {
     let order = item.into_inner();
     println!("Updating order: {:?}", order);
-    // 1. Pehle DB me Order update kar
-    let _ = db.put(order.clone());
-    println!("entering update_order_in_sheets");
-    let values = Order::to_sheet1_row(&order).await;
-    let row_number = order.row_number.unwrap_or(0);
-    let file_content = fs
-        ::read_to_string("./src/service_account.json")
-        .expect("Failed to read service account file");
-    let sa: ServiceAccount = serde_json::from_str(&file_content).unwrap();
-    // println!("Service Account JSON: {}", &file_content.as_ref().unwrap().len());
-    let access_token = get_or_generate_token(&sa.client_email, &sa.private_key).await;
-
-    let values_2_d = vec![values];
-    println!("Updating order in sheets with row number: {}", row_number);
-    let ress = update_order_in_sheets(
-        access_token.unwrap(),
-        "16pzLDZosE9HIhrWRrxc8ZkWERhWf0LVnqx0SI4e_eas",
-        "Sheet1!A:Z",
-        row_number,
-        values_2_d
-    ).await;
-    match ress {
-        Ok(_) => println!("Order updated successfully in sheets"),
-        Err(e) => {
-            return HttpResponse::InternalServerError().body(format!("Sheets update error: {}", e));
-        }
+    // DB me update, sheet row is synced by the outbox worker
+    match db.put(order, &ChangeContext::http(&req)) {
+        Ok(_) => HttpResponse::Ok().body("Order updated, sheet update queued"),
+        Err(e) if e.is::<TransitionError>() => HttpResponse::Conflict().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Update error: {}", e)),
     }
+}
 
-    HttpResponse::Ok().body("Order and sheets updated successfully")
+#[derive(Debug, Deserialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteParams {
+    /// Remove the order for good instead of keeping it for restore
+    #[serde(default)]
+    pub hard: bool,
 }
 
-/// Delete an Order by id
+/// Delete an Order by id. Soft by default, the sheet row is cleared either way.
 #[utoipa::path(
     delete,
     path = "/orders/{id}",
-    params(("id" = String, Path, description = "Order ID")),
+    params(("id" = String, Path, description = "Order ID"), DeleteParams),
     responses(
-        (status = 200, description = "Order deleted"),
+        (status = 200, description = "Order deleted, sheet row removal queued", body = Order),
+        (status = 404, description = "Order not found"),
         (status = 500, description = "Delete error")
     )
 )]
-pub async fn delete_order(db: web::Data<DB>, path: web::Path<String>) -> impl Responder {
-    match db.delete(path.into_inner()) {
-        Ok(_) => HttpResponse::Ok().finish(),
+pub async fn delete_order(
+    req: HttpRequest,
+    db: web::Data<DB>,
+    path: web::Path<String>,
+    query: web::Query<DeleteParams>
+) -> impl Responder {
+    match db.delete(path.into_inner(), query.hard, &ChangeContext::http(&req)) {
+        Ok(Some(order)) => HttpResponse::Ok().json(order),
+        Ok(None) => HttpResponse::NotFound().body("Order not found"),
         Err(e) => HttpResponse::InternalServerError().body(format!("Delete error: {}", e)),
     }
 }
+
+/// Soft-deleted Orders that can still be restored
+#[utoipa::path(
+    get,
+    path = "/orders/deleted",
+    responses(
+        (status = 200, description = "Soft-deleted orders", body = [Order]),
+        (status = 500, description = "List error")
+    )
+)]
+pub async fn list_deleted_orders(db: web::Data<DB>) -> impl Responder {
+    match db.deleted() {
+        Ok(orders) => HttpResponse::Ok().json(orders),
+        Err(e) => HttpResponse::InternalServerError().body(format!("List error: {}", e)),
+    }
+}
+
+/// Restore a soft-deleted Order, its sheet row is appended again
+#[utoipa::path(
+    post,
+    path = "/orders/deleted/{id}/restore",
+    params(("id" = String, Path, description = "Id of the deleted order, see `GET /orders/deleted`")),
+    responses(
+        (status = 200, description = "Order restored, sheet append queued", body = Order),
+        (status = 404, description = "No deleted order with this id"),
+        (status = 409, description = "Another order uses the same order_id"),
+        (status = 500, description = "Restore error")
+    )
+)]
+pub async fn restore_order(
+    req: HttpRequest,
+    db: web::Data<DB>,
+    path: web::Path<String>
+) -> impl Responder {
+    match db.restore(path.into_inner(), &ChangeContext::http(&req)) {
+        Ok(Some(order)) => HttpResponse::Ok().json(order),
+        Ok(None) => HttpResponse::NotFound().body("Deleted order not found"),
+        Err(e) if e.is::<RestoreError>() => HttpResponse::Conflict().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Restore error: {}", e)),
+    }
+}
+
+/// Change history of an Order, oldest first
+#[utoipa::path(
+    get,
+    path = "/orders/{id}/history",
+    params(("id" = String, Path, description = "Order ID")),
+    responses(
+        (status = 200, description = "Change history", body = [HistoryEntry]),
+        (status = 404, description = "No history for this order"),
+        (status = 500, description = "History error")
+    )
+)]
+pub async fn get_order_history(db: web::Data<DB>, path: web::Path<String>) -> impl Responder {
+    match db.history(path.into_inner()) {
+        Ok(entries) if entries.is_empty() => HttpResponse::NotFound().body("Order not found"),
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => HttpResponse::InternalServerError().body(format!("History error: {}", e)),
+    }
+}
 #[derive(Deserialize)]
 struct UpdateParams {
     order_id: String,
     row_number: String,
+    /// Return the match that would be assigned instead of saving it
+    #[serde(default)]
+    dry_run: bool,
 }
 
-async fn update_by_api(db: web::Data<DB>,query: web::Query<UpdateParams>) -> impl Responder {
+async fn update_by_api(
+    db: web::Data<DB>,
+    linnworks: web::Data<LinnworksSession>,
+    scheduler: web::Data<JobScheduler<GoogleSheetsClient>>,
+    matchers: web::Data<MatcherRegistry>,
+    config: web::Data<AppConfig>,
+    query: web::Query<UpdateParams>
+) -> impl Responder {
     let params: UpdateParams = query.into_inner();
     println!("Updating order by API: {}", params.order_id);
-    match update(db , &params.order_id, params.row_number).await {
+    if params.dry_run {
+        let preview = preview_update(
+            &db,
+            &linnworks,
+            &matchers,
+            &config.matching,
+            &params.order_id,
+            &params.row_number
+        ).await;
+        return match preview {
+            Ok(preview) => HttpResponse::Ok().json(preview),
+            Err(e) if e.is::<AmbiguousMarketplace>() => HttpResponse::Conflict().body(e.to_string()),
+            Err(e) => HttpResponse::InternalServerError().body(format!("Update error: {}", e)),
+        };
+    }
+    let Some(_guard) = scheduler.try_claim(JobKind::LinnworksReconcile) else {
+        return HttpResponse::Conflict().body("A Linnworks reconcile is running");
+    };
+    match update(db, &linnworks, &matchers, &config.matching, &params.order_id, params.row_number).await {
         Ok(_) => HttpResponse::Ok().body("Order updated successfully"),
+        Err(e) if e.is::<TransitionError>() || e.is::<AmbiguousMarketplace>() => {
+            HttpResponse::Conflict().body(e.to_string())
+        }
         Err(e) => HttpResponse::InternalServerError().body(format!("Update error: {}", e)),
     }
 }
@@ -175,14 +278,44 @@ pub fn order_config(cfg: &mut web::ServiceConfig) {
             .route(web::get().to(list_orders))
     )
         .service(web::resource("/orders/insert_all").route(web::get().to(insert_all)))
+        .service(web::resource("/orders/deleted").route(web::get().to(list_deleted_orders)))
+        .service(
+            web::resource("/orders/deleted/{id}/restore").route(web::post().to(restore_order))
+        )
         .service(
             web
                 ::resource("/orders/{id}")
                 .route(web::get().to(get_order))
                 .route(web::delete().to(delete_order))
-        ).service(
+        )
+        .service(web::resource("/orders/{id}/history").route(web::get().to(get_order_history)))
+        .service(
             web::resource("/order/update")
                 .route(web::get().to(update_by_api))
         )
         ;
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{ http::StatusCode, test, App };
+
+    use super::*;
+    use crate::lmdb::utils::tests::temp_db;
+
+    #[actix_web::test]
+    async fn bad_listing_queries_are_bad_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(db)).configure(order_config)
+        ).await;
+
+        for uri in ["/orders?cursor=zz", "/orders?date_to=yesterday"] {
+            let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+        let res = test::call_service(&app, test::TestRequest::get().uri("/orders").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
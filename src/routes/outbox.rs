@@ -0,0 +1,91 @@
+use actix_web::{ web, HttpResponse, Responder };
+
+use crate::{ lmdb::{ outbox::DBOutbox, utils::DB }, schema::outbox::OutboxEntry };
+
+/// Sheet writes waiting for delivery
+#[utoipa::path(
+    get,
+    path = "/admin/outbox",
+    responses(
+        (status = 200, description = "Pending sheet writes", body = [OutboxEntry]),
+        (status = 500, description = "Outbox error")
+    )
+)]
+pub async fn list_outbox(db: web::Data<DB>) -> impl Responder {
+    match db.pending() {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Outbox error: {}", e)),
+    }
+}
+
+/// Sheet writes that ran out of retries
+#[utoipa::path(
+    get,
+    path = "/admin/outbox/dead-letters",
+    responses(
+        (status = 200, description = "Dead-lettered sheet writes", body = [OutboxEntry]),
+        (status = 500, description = "Outbox error")
+    )
+)]
+pub async fn list_dead_letters(db: web::Data<DB>) -> impl Responder {
+    match db.dead_letters() {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Outbox error: {}", e)),
+    }
+}
+
+/// Queue a dead-lettered write again
+#[utoipa::path(
+    post,
+    path = "/admin/outbox/dead-letters/{seq}/retry",
+    params(("seq" = u64, Path, description = "Outbox sequence number")),
+    responses(
+        (status = 200, description = "Back in the outbox", body = OutboxEntry),
+        (status = 404, description = "No such dead letter"),
+        (status = 500, description = "Outbox error")
+    )
+)]
+pub async fn retry_dead_letter(db: web::Data<DB>, path: web::Path<u64>) -> impl Responder {
+    match db.retry_dead_letter(path.into_inner()) {
+        Ok(Some(entry)) => HttpResponse::Ok().json(entry),
+        Ok(None) => HttpResponse::NotFound().body("Dead letter not found"),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Outbox error: {}", e)),
+    }
+}
+
+/// Drop a dead-lettered write for good
+#[utoipa::path(
+    delete,
+    path = "/admin/outbox/dead-letters/{seq}",
+    params(("seq" = u64, Path, description = "Outbox sequence number")),
+    responses(
+        (status = 200, description = "Dead letter discarded", body = OutboxEntry),
+        (status = 404, description = "No such dead letter"),
+        (status = 500, description = "Outbox error")
+    )
+)]
+pub async fn discard_dead_letter(db: web::Data<DB>, path: web::Path<u64>) -> impl Responder {
+    match db.discard_dead_letter(path.into_inner()) {
+        Ok(Some(entry)) => HttpResponse::Ok().json(entry),
+        Ok(None) => HttpResponse::NotFound().body("Dead letter not found"),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Outbox error: {}", e)),
+    }
+}
+
+/// Configure admin routes for the sheet outbox
+pub fn outbox_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/admin/outbox").route(web::get().to(list_outbox)))
+        .service(
+            web::resource("/admin/outbox/dead-letters").route(web::get().to(list_dead_letters))
+        )
+        .service(
+            web
+                ::resource("/admin/outbox/dead-letters/{seq}")
+                .route(web::delete().to(discard_dead_letter))
+        )
+        .service(
+            web
+                ::resource("/admin/outbox/dead-letters/{seq}/retry")
+                .route(web::post().to(retry_dead_letter))
+        );
+}
@@ -0,0 +1,128 @@
+use actix_web::{ web, HttpRequest, HttpResponse, Responder };
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+    config::settings::AppConfig,
+    lmdb::{ sync::DBSync, utils::DB },
+    schema::{
+        history::ChangeContext,
+        jobs::JobKind,
+        status::TransitionError,
+        sync::{ ResolveConflict, SyncConflict, SyncReport },
+    },
+    scripts::{ jobs::JobScheduler, marketplaces::MatcherRegistry, sheets::GoogleSheetsClient },
+};
+
+#[derive(Debug, Deserialize, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct DryRunParams {
+    /// Return what would change instead of writing it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Run a full sheet/DB sync
+#[utoipa::path(
+    post,
+    path = "/sync",
+    params(DryRunParams),
+    responses(
+        (status = 200, description = "Sync finished, a `SyncPreview` for a dry run", body = SyncReport),
+        (status = 409, description = "A sheet import or outbox flush is running"),
+        (status = 500, description = "Sync error")
+    )
+)]
+pub async fn run_sync(
+    db: web::Data<DB>,
+    config: web::Data<AppConfig>,
+    sheets: web::Data<GoogleSheetsClient>,
+    matchers: web::Data<MatcherRegistry>,
+    scheduler: web::Data<JobScheduler<GoogleSheetsClient>>,
+    query: web::Query<DryRunParams>
+) -> impl Responder {
+    if query.dry_run {
+        return match db.preview_sync(sheets.get_ref(), &config.sheets, &matchers).await {
+            Ok(preview) => HttpResponse::Ok().json(preview),
+            Err(e) => HttpResponse::InternalServerError().body(format!("Sync error: {}", e)),
+        };
+    }
+    let Some(_guard) = scheduler.try_claim(JobKind::SheetImport) else {
+        return HttpResponse::Conflict().body("A sheet import or outbox flush is running");
+    };
+    match db.sync_sheet(sheets.get_ref(), &config.sheets, &matchers, None).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Sync error: {}", e)),
+    }
+}
+
+/// Fields edited on both sides, waiting for review
+#[utoipa::path(
+    get,
+    path = "/sync/conflicts",
+    responses(
+        (status = 200, description = "Open conflicts", body = [SyncConflict]),
+        (status = 500, description = "Conflict list error")
+    )
+)]
+pub async fn list_conflicts(db: web::Data<DB>) -> impl Responder {
+    match db.conflicts() {
+        Ok(conflicts) => HttpResponse::Ok().json(conflicts),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Conflict list error: {}", e)),
+    }
+}
+
+/// Resolve a conflict by keeping the sheet or the DB value
+#[utoipa::path(
+    post,
+    path = "/sync/conflicts/{id}/{field}/resolve",
+    params(
+        ("id" = String, Path, description = "Order primary id"),
+        ("field" = String, Path, description = "Conflicting field")
+    ),
+    request_body = ResolveConflict,
+    responses(
+        (status = 200, description = "Conflict resolved", body = SyncConflict),
+        (status = 404, description = "No such conflict"),
+        (status = 409, description = "Status transition not allowed, or a sheet import is running"),
+        (status = 500, description = "Resolve error")
+    )
+)]
+pub async fn resolve_conflict(
+    req: HttpRequest,
+    db: web::Data<DB>,
+    config: web::Data<AppConfig>,
+    sheets: web::Data<GoogleSheetsClient>,
+    scheduler: web::Data<JobScheduler<GoogleSheetsClient>>,
+    path: web::Path<(String, String)>,
+    item: web::Json<ResolveConflict>
+) -> impl Responder {
+    // the import would read the row and baseline half-way through the resolution
+    let Some(_guard) = scheduler.try_claim(JobKind::SheetImport) else {
+        return HttpResponse::Conflict().body("A sheet import or outbox flush is running");
+    };
+    let (id, field) = path.into_inner();
+    let result = db.resolve_conflict(
+        sheets.get_ref(),
+        &config.sheets,
+        &id,
+        &field,
+        item.keep,
+        &ChangeContext::http(&req)
+    ).await;
+    match result {
+        Ok(Some(conflict)) => HttpResponse::Ok().json(conflict),
+        Ok(None) => HttpResponse::NotFound().body("Conflict not found"),
+        Err(e) if e.is::<TransitionError>() => HttpResponse::Conflict().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Resolve error: {}", e)),
+    }
+}
+
+/// Configure routes for sheet sync
+pub fn sync_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/sync").route(web::post().to(run_sync)))
+        .service(web::resource("/sync/conflicts").route(web::get().to(list_conflicts)))
+        .service(
+            web::resource("/sync/conflicts/{id}/{field}/resolve").route(web::post().to(resolve_conflict))
+        );
+}
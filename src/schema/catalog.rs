@@ -0,0 +1,251 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+/// Characters that only separate the parts of a SKU.
+pub const SKU_SEPARATORS: [char; 5] = ['-', '_', ' ', '/', '.'];
+
+/// Trimmed and lowercased, so SKUs from the sheet and from Linnworks compare equal.
+pub fn normalize_sku(sku: &str) -> String {
+    sku.trim().to_lowercase()
+}
+
+/// Normalized with the separators dropped, `ABC-12 xl` becomes `abc12xl`.
+pub fn compact_sku(sku: &str) -> String {
+    normalize_sku(sku)
+        .chars()
+        .filter(|c| !SKU_SEPARATORS.contains(c))
+        .collect()
+}
+
+/// One of our products and the SKUs it is known by elsewhere.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    /// Internal SKU, unique ignoring case and separators
+    #[schema(example = "TSHIRT-RED")]
+    pub sku: String,
+    #[schema(example = "Red t-shirt")]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<SkuAlias>,
+    #[serde(default)]
+    #[schema(value_type = String, example = "2023-01-01T00:00:00Z")]
+    pub updated_at: String,
+}
+
+/// A SKU the product is listed or returned under.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct SkuAlias {
+    /// Marketplace using the alias, any marketplace and Linnworks when unset
+    #[schema(example = "Debenhams")]
+    pub marketplace: Option<String>,
+    #[schema(example = "DEB-778812")]
+    pub sku: String,
+}
+
+impl CatalogEntry {
+    /// The SKU `marketplace` lists the product under, `None` when it has no alias of its own.
+    pub fn offer_sku(&self, marketplace: &str) -> Option<&str> {
+        self.aliases
+            .iter()
+            .find(|a| a.marketplace.as_deref().is_some_and(|m| m.trim().eq_ignore_ascii_case(marketplace.trim())))
+            .map(|a| a.sku.as_str())
+    }
+}
+
+/// Rejected catalog change, surfaced as 409 by the routes.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CatalogError {
+    #[error("catalog already has SKU {sku}")]
+    Exists {
+        sku: String,
+    },
+    #[error("{alias} is already used by SKU {sku}{}", marketplace_suffix(.marketplace))]
+    AliasTaken {
+        alias: String,
+        marketplace: Option<String>,
+        sku: String,
+    },
+}
+
+fn marketplace_suffix(marketplace: &Option<String>) -> String {
+    marketplace.as_ref().map(|m| format!(" on {}", m)).unwrap_or_default()
+}
+
+/// Catalog entry that can't be stored as sent, surfaced as 400 by the routes.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("invalid catalog entry: {0}")]
+pub struct InvalidCatalogEntry(pub String);
+
+/// One data line of a catalog CSV: `sku,marketplace,alias,title`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogImportRow {
+    /// 1-based line the record starts on, the header is line 1
+    pub line: usize,
+    pub sku: String,
+    pub title: Option<String>,
+    pub alias: Option<SkuAlias>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default, PartialEq, Eq)]
+pub struct CatalogImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub aliases_added: usize,
+    pub rejected: Vec<RejectedCatalogLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct RejectedCatalogLine {
+    /// 1-based line the record starts on, the header is line 1
+    pub line: usize,
+    pub reason: String,
+}
+
+/// The records of a CSV with the 1-based line each starts on, blank lines skipped.
+/// Fields may be quoted, a quoted field may hold commas and line breaks and `""` inside
+/// quotes is a literal quote.
+fn csv_records(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let (mut line, mut start) = (1, 1);
+    let mut end_record = |fields: &mut Vec<String>, field: &mut String, start: usize| {
+        fields.push(std::mem::take(field));
+        let record: Vec<String> = fields.drain(..).map(|f| f.trim().to_string()).collect();
+        if record.iter().any(|f| !f.is_empty()) {
+            records.push((start, record));
+        }
+    };
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => {
+                quoted = !quoted;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                end_record(&mut fields, &mut field, start);
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    end_record(&mut fields, &mut field, start);
+    records
+}
+
+/// Parse a catalog CSV whose header names the columns (`sku` required, `marketplace`,
+/// `alias` and `title` optional, any order). Lines that can't be used are returned as rejected.
+pub fn parse_catalog_csv(
+    text: &str
+) -> Result<(Vec<CatalogImportRow>, Vec<RejectedCatalogLine>), InvalidCatalogEntry> {
+    let mut records = csv_records(text).into_iter();
+    let (_, header) = records.next().ok_or_else(|| InvalidCatalogEntry("the CSV is empty".to_string()))?;
+    let header: Vec<String> = header.iter().map(|h| h.to_lowercase()).collect();
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let sku_column = column("sku").ok_or_else(|| {
+        InvalidCatalogEntry("the CSV header has no sku column".to_string())
+    })?;
+    let (marketplace_column, alias_column, title_column) = (
+        column("marketplace"),
+        column("alias"),
+        column("title"),
+    );
+
+    let mut rows = Vec::new();
+    let mut rejected = Vec::new();
+    for (line, fields) in records {
+        let cell = |column: Option<usize>| {
+            column
+                .and_then(|c| fields.get(c))
+                .filter(|v| !v.is_empty())
+                .cloned()
+        };
+        let Some(sku) = cell(Some(sku_column)) else {
+            rejected.push(RejectedCatalogLine { line, reason: "empty sku".to_string() });
+            continue;
+        };
+        let marketplace = cell(marketplace_column);
+        let alias = match cell(alias_column) {
+            Some(alias) => Some(SkuAlias { marketplace, sku: alias }),
+            None if marketplace.is_some() => {
+                rejected.push(RejectedCatalogLine {
+                    line,
+                    reason: "marketplace given without an alias".to_string(),
+                });
+                continue;
+            }
+            None => None,
+        };
+        rows.push(CatalogImportRow { line, sku, title: cell(title_column), alias });
+    }
+    Ok((rows, rejected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(marketplace: Option<&str>, sku: &str) -> Option<SkuAlias> {
+        Some(SkuAlias { marketplace: marketplace.map(str::to_string), sku: sku.to_string() })
+    }
+
+    #[test]
+    fn columns_are_found_in_any_order() {
+        let csv = "Title,ALIAS,sku,Marketplace\nRed tee,DEB-1,TSHIRT-RED,Debenhams\n";
+        let (rows, rejected) = parse_catalog_csv(csv).unwrap();
+        assert!(rejected.is_empty());
+        assert_eq!(rows, [CatalogImportRow {
+            line: 2,
+            sku: "TSHIRT-RED".to_string(),
+            title: Some("Red tee".to_string()),
+            alias: alias(Some("Debenhams"), "DEB-1"),
+        }]);
+    }
+
+    #[test]
+    fn quoted_fields_keep_commas_quotes_and_line_breaks() {
+        let csv = "sku,alias,title\r\n\"JEANS, BLUE\",\"JB \"\"32\"\"\",\"Blue jeans\nslim fit\"\r\n\nSOCKS,,\n";
+        let (rows, rejected) = parse_catalog_csv(csv).unwrap();
+        assert!(rejected.is_empty());
+        assert_eq!(rows, [
+            CatalogImportRow {
+                line: 2,
+                sku: "JEANS, BLUE".to_string(),
+                title: Some("Blue jeans\nslim fit".to_string()),
+                alias: alias(None, "JB \"32\""),
+            },
+            // the quoted line break and the blank line still count
+            CatalogImportRow { line: 5, sku: "SOCKS".to_string(), title: None, alias: None },
+        ]);
+    }
+
+    #[test]
+    fn unusable_lines_are_rejected_with_their_line_number() {
+        let csv = "sku,marketplace,alias\n,Debenhams,DEB-1\nTSHIRT-RED,Debenhams,\nJEANS,,\n";
+        let (rows, rejected) = parse_catalog_csv(csv).unwrap();
+        assert_eq!(rows.iter().map(|r| r.sku.as_str()).collect::<Vec<_>>(), ["JEANS"]);
+        assert_eq!(rejected, [
+            RejectedCatalogLine { line: 2, reason: "empty sku".to_string() },
+            RejectedCatalogLine { line: 3, reason: "marketplace given without an alias".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn a_csv_without_a_sku_column_is_refused() {
+        assert!(parse_catalog_csv("").is_err());
+        assert!(parse_catalog_csv("title,alias\nRed tee,DEB-1\n").is_err());
+    }
+}
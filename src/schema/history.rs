@@ -0,0 +1,184 @@
+use actix_web::HttpRequest;
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+use crate::schema::order::Order;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Create,
+    Update,
+    /// Removed from the live orders, soft deletes can still be restored
+    Delete,
+    Restore,
+    /// Soft-deleted order removed for good
+    Purge,
+}
+
+/// Where a change came from.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeSource {
+    Http,
+    SheetSync,
+    LinnworksReconcile,
+}
+
+/// Who is writing and through which path, passed down to every DB write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeContext {
+    /// Name the writer gave for itself. Requests aren't authenticated, so for HTTP
+    /// writes this is whatever the caller put in `X-Actor` and can't be trusted.
+    pub claimed_actor: String,
+    pub source: ChangeSource,
+}
+
+impl ChangeContext {
+    /// Actor claimed in the unverified `X-Actor` header, falls back to "anonymous".
+    pub fn http(req: &HttpRequest) -> Self {
+        let claimed_actor = req
+            .headers()
+            .get("X-Actor")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "anonymous".to_string());
+        ChangeContext { claimed_actor, source: ChangeSource::Http }
+    }
+
+    pub fn sheet_sync() -> Self {
+        ChangeContext { claimed_actor: "system".to_string(), source: ChangeSource::SheetSync }
+    }
+
+    pub fn linnworks_reconcile() -> Self {
+        ChangeContext {
+            claimed_actor: "system".to_string(),
+            source: ChangeSource::LinnworksReconcile,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    #[schema(example = "match_type")]
+    pub field: String,
+    #[schema(example = "None")]
+    pub before: Option<String>,
+    #[schema(example = "Full Match")]
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    #[schema(example = 42)]
+    pub seq: u64,
+    #[schema(value_type = String, example = "550e8400-e29b-41d4-a716-446655440000")]
+    pub id: String,
+    #[schema(example = "1234567890")]
+    pub order_id: String,
+    pub action: ChangeAction,
+    /// Who the writer said it was, unverified for HTTP writes (`X-Actor` header)
+    #[schema(example = "anonymous")]
+    pub claimed_actor: String,
+    pub source: ChangeSource,
+    #[schema(value_type = String, example = "2023-01-01T00:00:00Z")]
+    pub at: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Fields that change on every write and would only add noise to a diff.
+const IGNORED_FIELDS: [&str; 1] = ["updated_at"];
+
+/// Field level diff between two versions of an order, either side may be missing.
+pub fn diff_orders(before: Option<&Order>, after: Option<&Order>) -> Vec<FieldChange> {
+    let before = before.map(serde_json::to_value).transpose().ok().flatten();
+    let after = after.map(serde_json::to_value).transpose().ok().flatten();
+    let empty = serde_json::Map::new();
+    let before_fields = before.as_ref().and_then(|v| v.as_object()).unwrap_or(&empty);
+    let after_fields = after.as_ref().and_then(|v| v.as_object()).unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = before_fields.keys().chain(after_fields.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter(|field| !IGNORED_FIELDS.contains(&field.as_str()))
+        .filter_map(|field| {
+            let old = before_fields.get(field).and_then(render_value);
+            let new = after_fields.get(field).and_then(render_value);
+            if old == new {
+                None
+            } else {
+                Some(FieldChange { field: field.clone(), before: old, after: new })
+            }
+        })
+        .collect()
+}
+
+fn render_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+    use crate::{ lmdb::versioned::tests::expected_v1, schema::status::OrderStatus };
+
+    fn change(field: &str, before: Option<&str>, after: Option<&str>) -> FieldChange {
+        FieldChange {
+            field: field.to_string(),
+            before: before.map(str::to_string),
+            after: after.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn diff_lists_changed_fields_only() {
+        let before = expected_v1();
+        let after = Order {
+            status: Some(OrderStatus::Confirmed),
+            qty: Some(2),
+            offer_sku: Some("DB-TS-RED-M".to_string()),
+            updated_at: "2024-04-01T00:00:00+00:00".to_string(),
+            ..before.clone()
+        };
+        assert_eq!(diff_orders(Some(&before), Some(&after)), vec![
+            change("offer_sku", None, Some("DB-TS-RED-M")),
+            change("qty", Some("1"), Some("2")),
+            change("status", Some("matched"), Some("confirmed"))
+        ]);
+        assert!(diff_orders(Some(&before), Some(&before)).is_empty());
+    }
+
+    #[test]
+    fn diff_of_a_created_or_deleted_order_covers_every_set_field() {
+        let order = expected_v1();
+        let created = diff_orders(None, Some(&order));
+        assert!(created.iter().all(|c| c.before.is_none() && c.after.is_some()));
+        assert!(created.contains(&change("order_id", None, Some("104522"))));
+        // unset fields and updated_at are left out
+        assert!(!created.iter().any(|c| c.field == "offer_sku" || c.field == "updated_at"));
+
+        let deleted = diff_orders(Some(&order), None);
+        assert_eq!(deleted.len(), created.len());
+        assert!(deleted.contains(&change("boolean", Some("false"), None)));
+    }
+
+    #[test]
+    fn http_actor_is_the_claimed_header_value() {
+        let req = TestRequest::default().insert_header(("X-Actor", "  alice ")).to_http_request();
+        assert_eq!(ChangeContext::http(&req).claimed_actor, "alice");
+        let req = TestRequest::default().insert_header(("X-Actor", " ")).to_http_request();
+        assert_eq!(ChangeContext::http(&req).claimed_actor, "anonymous");
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(ChangeContext::http(&req).source, ChangeSource::Http);
+    }
+}
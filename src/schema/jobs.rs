@@ -0,0 +1,91 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+/// Work the in-process scheduler runs on an interval.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Full sheet/DB sync
+    SheetImport,
+    /// Deliver queued sheet writes
+    OutboxFlush,
+    /// Look up unmatched orders in Linnworks
+    LinnworksReconcile,
+}
+
+impl JobKind {
+    pub const ALL: [JobKind; 3] = [
+        JobKind::SheetImport,
+        JobKind::OutboxFlush,
+        JobKind::LinnworksReconcile,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::SheetImport => "sheet_import",
+            JobKind::OutboxFlush => "outbox_flush",
+            JobKind::LinnworksReconcile => "linnworks_reconcile",
+        }
+    }
+
+    /// Jobs that must not run at the same time as this one, because both write the sheet.
+    pub fn excludes(&self) -> &'static [JobKind] {
+        match self {
+            JobKind::SheetImport => &[JobKind::OutboxFlush],
+            JobKind::OutboxFlush => &[JobKind::SheetImport],
+            JobKind::LinnworksReconcile => &[],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTrigger {
+    Schedule,
+    Manual,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct JobRun {
+    pub seq: u64,
+    pub job: JobKind,
+    pub trigger: JobTrigger,
+    #[schema(value_type = String, example = "2023-01-01T00:00:00Z")]
+    pub started_at: String,
+    #[schema(value_type = String, example = "2023-01-01T00:00:05Z")]
+    pub finished_at: String,
+    pub outcome: JobOutcome,
+    /// Report of a successful run as JSON, or the error of a failed one
+    pub detail: String,
+}
+
+/// One job as shown by `GET /jobs`.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct JobStatus {
+    pub job: JobKind,
+    /// 0 when the job only runs on demand
+    pub interval_secs: u64,
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+    /// `None` when the job is not scheduled
+    pub next_run_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Orders looked up in Linnworks
+    pub checked: usize,
+    /// Orders that ended up with a full match
+    pub matched: usize,
+    /// Orders matched with low confidence, waiting for manual confirmation
+    pub needs_review: usize,
+    /// Lookups that failed, retried on a later run
+    pub failed: usize,
+}
@@ -0,0 +1,180 @@
+//! Frozen copies of older on-disk `Order` layouts. Never edit these, add a new
+//! version instead and chain a `From` impl to the next one.
+use serde::{ Deserialize, Serialize };
+
+use crate::schema::{
+    order::Order,
+    status::{ MainUpdated, ManualConfirmation, MatchType, OrderStatus },
+};
+
+/// Schema version 1, status fields as free-form strings.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderV1 {
+    pub id: String,
+    pub marketplace: String,
+    pub order_id: String,
+    pub return_order: Option<u64>,
+    pub shopify_id: Option<String>,
+    pub market_place_code: Option<String>,
+    pub returned_sku: Option<String>,
+    pub offer_sku: Option<String>,
+    pub matched_sku: Option<String>,
+    pub match_type: Option<String>,
+    pub row_number: Option<usize>,
+    pub manual_confirmation: Option<String>,
+    pub status: Option<String>,
+    pub qty: Option<u32>,
+    pub main_updated: Option<String>,
+    pub date: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub boolean: bool,
+}
+
+/// Schema version 2, status fields as enums.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderV2 {
+    pub id: String,
+    pub marketplace: String,
+    pub order_id: String,
+    pub return_order: Option<u64>,
+    pub shopify_id: Option<String>,
+    pub market_place_code: Option<String>,
+    pub returned_sku: Option<String>,
+    pub offer_sku: Option<String>,
+    pub matched_sku: Option<String>,
+    pub match_type: Option<MatchType>,
+    pub row_number: Option<usize>,
+    pub manual_confirmation: Option<ManualConfirmation>,
+    pub status: Option<OrderStatus>,
+    pub qty: Option<u32>,
+    pub main_updated: Option<MainUpdated>,
+    pub date: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub boolean: bool,
+}
+
+/// Parse a free-form value, dropping anything that isn't a known variant.
+fn parse_or_drop<T: std::str::FromStr>(id: &str, value: Option<String>) -> Option<T>
+    where T::Err: std::fmt::Display
+{
+    let value = value?;
+    if value.trim().is_empty() {
+        return None;
+    }
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            println!("Dropping value while upgrading order {}: {}", id, e);
+            None
+        }
+    }
+}
+
+impl From<OrderV1> for OrderV2 {
+    fn from(v1: OrderV1) -> Self {
+        OrderV2 {
+            match_type: parse_or_drop(&v1.id, v1.match_type),
+            manual_confirmation: parse_or_drop(&v1.id, v1.manual_confirmation),
+            status: parse_or_drop(&v1.id, v1.status),
+            main_updated: parse_or_drop(&v1.id, v1.main_updated),
+            id: v1.id,
+            marketplace: v1.marketplace,
+            order_id: v1.order_id,
+            return_order: v1.return_order,
+            shopify_id: v1.shopify_id,
+            market_place_code: v1.market_place_code,
+            returned_sku: v1.returned_sku,
+            offer_sku: v1.offer_sku,
+            matched_sku: v1.matched_sku,
+            row_number: v1.row_number,
+            qty: v1.qty,
+            date: v1.date,
+            created_at: v1.created_at,
+            updated_at: v1.updated_at,
+            boolean: v1.boolean,
+        }
+    }
+}
+
+/// Schema version 3, the matched Linnworks line but no match confidence yet.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderV3 {
+    pub id: String,
+    pub marketplace: String,
+    pub order_id: String,
+    pub return_order: Option<u64>,
+    pub shopify_id: Option<String>,
+    pub market_place_code: Option<String>,
+    pub returned_sku: Option<String>,
+    pub offer_sku: Option<String>,
+    pub matched_sku: Option<String>,
+    pub match_type: Option<MatchType>,
+    pub matched_line: Option<usize>,
+    pub row_number: Option<usize>,
+    pub manual_confirmation: Option<ManualConfirmation>,
+    pub status: Option<OrderStatus>,
+    pub qty: Option<u32>,
+    pub main_updated: Option<MainUpdated>,
+    pub date: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub boolean: bool,
+}
+
+impl From<OrderV2> for OrderV3 {
+    fn from(v2: OrderV2) -> Self {
+        OrderV3 {
+            id: v2.id,
+            marketplace: v2.marketplace,
+            order_id: v2.order_id,
+            return_order: v2.return_order,
+            shopify_id: v2.shopify_id,
+            market_place_code: v2.market_place_code,
+            returned_sku: v2.returned_sku,
+            offer_sku: v2.offer_sku,
+            matched_sku: v2.matched_sku,
+            match_type: v2.match_type,
+            matched_line: None,
+            row_number: v2.row_number,
+            manual_confirmation: v2.manual_confirmation,
+            status: v2.status,
+            qty: v2.qty,
+            main_updated: v2.main_updated,
+            date: v2.date,
+            created_at: v2.created_at,
+            updated_at: v2.updated_at,
+            boolean: v2.boolean,
+        }
+    }
+}
+
+impl From<OrderV3> for Order {
+    fn from(v3: OrderV3) -> Self {
+        Order {
+            id: v3.id,
+            marketplace: v3.marketplace,
+            order_id: v3.order_id,
+            return_order: v3.return_order,
+            shopify_id: v3.shopify_id,
+            market_place_code: v3.market_place_code,
+            returned_sku: v3.returned_sku,
+            offer_sku: v3.offer_sku,
+            matched_sku: v3.matched_sku,
+            match_type: v3.match_type,
+            match_confidence: None,
+            match_explanation: None,
+            matched_line: v3.matched_line,
+            row_number: v3.row_number,
+            manual_confirmation: v3.manual_confirmation,
+            status: v3.status,
+            qty: v3.qty,
+            main_updated: v3.main_updated,
+            date: v3.date,
+            created_at: v3.created_at,
+            updated_at: v3.updated_at,
+            boolean: v3.boolean,
+        }
+    }
+}
@@ -1,2 +1,10 @@
 pub mod order;
 pub mod order_api;
+pub mod history;
+pub mod order_query;
+pub mod status;
+pub mod legacy;
+pub mod sync;
+pub mod outbox;
+pub mod jobs;
+pub mod catalog;
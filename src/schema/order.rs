@@ -1,6 +1,7 @@
 use serde::{ Deserialize, Serialize };
 use utoipa::ToSchema;
-use crate::schema::order_api::Orders;
+
+use crate::schema::status::{ MainUpdated, ManualConfirmation, MatchType, OrderStatus };
 
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -32,23 +33,31 @@ pub struct Order {
     #[schema(example = "1234567890", max_length = 20)]
     pub matched_sku: Option<String>, // will update later with the help of api
 
-    #[schema(example = "automatic", max_length = 20)]
-    pub match_type: Option<String>, // needed but optioanl for current
+    pub match_type: Option<MatchType>,
+
+    /// 0-100, how sure the SKU match is
+    #[schema(example = 95, maximum = 100)]
+    pub match_confidence: Option<u8>,
+
+    /// How the returned SKU was matched to a Linnworks line, or why it wasn't
+    #[schema(example = "same SKU ignoring case and spaces")]
+    pub match_explanation: Option<String>,
+
+    /// 1-based line of the Linnworks order the returned SKU was matched to
+    #[schema(example = 1)]
+    pub matched_line: Option<usize>,
 
     #[schema(example = "1", maximum = 9999999)]
     pub row_number: Option<usize>, 
 
-    #[schema(example = "confirmed", max_length = 20)]
-    pub manual_confirmation: Option<String>, // needed but optioanl for current
+    pub manual_confirmation: Option<ManualConfirmation>,
 
-    #[schema(example = "processed", max_length = 20)]
-    pub status: Option<String>, // needed but optioanl for current
+    pub status: Option<OrderStatus>,
 
     #[schema(example = "1", maximum = 999)]
     pub qty: Option<u32>, // needed but optioanl for current
 
-    #[schema(example = "true", max_length = 5)]
-    pub main_updated: Option<String>, // needed but optioanl for current
+    pub main_updated: Option<MainUpdated>,
 
     #[schema(value_type = String, example = "2023-01-01T00:00:00Z")]
     pub date: String, // needed but optioanl for current
@@ -62,6 +71,50 @@ pub struct Order {
     pub boolean: bool, // not needed
 }
 
+/// A deleted order can't come back while another live order uses its `order_id`.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("order_id {order_id} is already used by order {id}")]
+pub struct RestoreError {
+    pub order_id: String,
+    pub id: String,
+}
+
+/// Why a sheet row can't become an order.
+#[derive(Debug, thiserror::Error, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RowError {
+    #[error("missing order id")]
+    MissingOrderId,
+    #[error("empty SKU")]
+    EmptySku,
+    #[error("unparseable date {value:?}")]
+    BadDate {
+        value: String,
+    },
+    #[error("unknown marketplace {value:?}")]
+    UnknownMarketplace {
+        value: String,
+    },
+    #[error("status cannot move from {from} to {to}")]
+    InvalidTransition {
+        from: String,
+        to: String,
+    },
+}
+
 lazy_static::lazy_static! {
     static ref MARKETPLACE_REGEX: regex::Regex = regex::Regex::new(r"^[a-zA-Z0-9_\-]+$").unwrap();
 }
+
+/// Sheet dates come in a few shapes ("05/08/25", "2025-08-05", RFC 3339),
+/// bring them to `YYYY-MM-DD` so they sort and compare as strings.
+pub fn normalize_date(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(raw) {
+        return Some(dt.date_naive().format("%Y-%m-%d").to_string());
+    }
+    ["%Y-%m-%d", "%d/%m/%y", "%d/%m/%Y"]
+        .iter()
+        .find_map(|fmt| chrono::NaiveDate::parse_from_str(raw, fmt).ok())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+}
@@ -0,0 +1,87 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::{ IntoParams, ToSchema };
+
+use crate::schema::{ order::Order, status::ParseStatusError };
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSort {
+    #[default]
+    RowNumber,
+    CreatedAt,
+    Date,
+    OrderId,
+}
+
+impl OrderSort {
+    /// Field index the sort walks.
+    pub fn field(&self) -> &'static str {
+        match self {
+            OrderSort::RowNumber => "row_number",
+            OrderSort::CreatedAt => "created_at",
+            OrderSort::Date => "date",
+            OrderSort::OrderId => "order_id",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query string accepted by `GET /orders`.
+#[derive(Debug, Deserialize, IntoParams, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct OrderQuery {
+    /// Case-insensitive marketplace name
+    pub marketplace: Option<String>,
+    /// Order status, e.g. `matched`
+    pub status: Option<String>,
+    /// Match type, e.g. `full_match` or `Full Match`
+    pub match_type: Option<String>,
+    /// Manual confirmation, e.g. `pending`
+    pub manual_confirmation: Option<String>,
+    /// Case-insensitive returned SKU
+    pub sku: Option<String>,
+    /// `Order.boolean`, set when a refund was requested but not ticked as refunded
+    pub boolean: Option<bool>,
+    /// Inclusive lower bound on the return date, `YYYY-MM-DD`
+    pub date_from: Option<String>,
+    /// Inclusive upper bound on the return date, `YYYY-MM-DD`
+    pub date_to: Option<String>,
+    #[param(inline)]
+    pub sort: Option<OrderSort>,
+    #[param(inline)]
+    pub direction: Option<SortDirection>,
+    /// Page size, defaults to 50, capped at 500
+    pub limit: Option<usize>,
+    /// `next_cursor` from the previous page
+    pub cursor: Option<String>,
+}
+
+/// Problems with the query itself, reported as 400.
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("Invalid cursor")]
+    Cursor,
+    #[error("Invalid date: {0}")]
+    Date(String),
+    #[error("Invalid filter: {0}")]
+    Filter(#[from] ParseStatusError),
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct OrderPage {
+    pub items: Vec<Order>,
+    /// Number of orders matching the filters across all pages
+    pub total: usize,
+    /// Pass back as `cursor` to fetch the next page, absent on the last one
+    pub next_cursor: Option<String>,
+}
@@ -0,0 +1,52 @@
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+/// A sheet mutation waiting to be delivered. Only ids are stored, the row is rendered
+/// from the order as it is when the entry is delivered.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SheetOp {
+    /// Add a row for a new order, skipped if the sheet already has one
+    AppendOrder {
+        id: String,
+    },
+    /// Bring the order's existing row in step with the DB
+    SyncOrder {
+        id: String,
+        order_id: String,
+    },
+    /// Blank out the row of a deleted order
+    ClearRow {
+        id: String,
+        order_id: String,
+    },
+}
+
+impl SheetOp {
+    pub fn order_key(&self) -> &str {
+        match self {
+            | SheetOp::AppendOrder { id }
+            | SheetOp::SyncOrder { id, .. }
+            | SheetOp::ClearRow { id, .. } => id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub seq: u64,
+    pub op: SheetOp,
+    /// Failed deliveries so far
+    pub attempts: u32,
+    /// Unix seconds, the entry is not retried before then
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default, PartialEq, Eq)]
+pub struct FlushReport {
+    pub delivered: usize,
+    pub retried: usize,
+    pub dead_lettered: usize,
+}
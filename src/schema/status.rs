@@ -0,0 +1,311 @@
+use std::{ fmt, str::FromStr };
+
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+/// Lifecycle of a return: received -> matched -> confirmed -> refunded -> restocked.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Received,
+    Matched,
+    Confirmed,
+    Refunded,
+    Restocked,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchType {
+    FullMatch,
+    NoMatch,
+    /// Matched with too little confidence to accept without review
+    NeedsReview,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ManualConfirmation {
+    Pending,
+    Confirmed,
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MainUpdated {
+    Pending,
+    Updated,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown {kind} value: {value:?}")]
+pub struct ParseStatusError {
+    pub kind: &'static str,
+    pub value: String,
+}
+
+/// Rejected status change, surfaced as 409 by the routes.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("order {order_id} cannot move from {from} to {to}")]
+pub struct TransitionError {
+    pub order_id: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Lowercase and collapse separators so "Full Match", "full_match" and "FULL-MATCH" compare equal.
+fn normalize_label(value: &str) -> String {
+    value
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c == '_' || c == '-' { ' ' } else { c })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl OrderStatus {
+    pub const ALL: [OrderStatus; 5] = [
+        OrderStatus::Received,
+        OrderStatus::Matched,
+        OrderStatus::Confirmed,
+        OrderStatus::Refunded,
+        OrderStatus::Restocked,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Received => "received",
+            OrderStatus::Matched => "matched",
+            OrderStatus::Confirmed => "confirmed",
+            OrderStatus::Refunded => "refunded",
+            OrderStatus::Restocked => "restocked",
+        }
+    }
+
+    /// Text written to the sheet.
+    pub fn label(&self) -> &'static str {
+        match self {
+            OrderStatus::Received => "Received",
+            OrderStatus::Matched => "Matched",
+            OrderStatus::Confirmed => "Confirmed",
+            OrderStatus::Refunded => "Refunded",
+            OrderStatus::Restocked => "Restocked",
+        }
+    }
+
+    pub fn next(&self) -> Option<OrderStatus> {
+        match self {
+            OrderStatus::Received => Some(OrderStatus::Matched),
+            OrderStatus::Matched => Some(OrderStatus::Confirmed),
+            OrderStatus::Confirmed => Some(OrderStatus::Refunded),
+            OrderStatus::Refunded => Some(OrderStatus::Restocked),
+            OrderStatus::Restocked => None,
+        }
+    }
+
+    /// A status may stay where it is or move one step forward.
+    pub fn can_transition_to(&self, to: OrderStatus) -> bool {
+        *self == to || self.next() == Some(to)
+    }
+}
+
+/// Check a status change between two versions of the same order. Any initial
+/// status is accepted, clearing or skipping a step is not.
+pub fn validate_transition(
+    order_id: &str,
+    from: Option<OrderStatus>,
+    to: Option<OrderStatus>
+) -> Result<(), TransitionError> {
+    let allowed = match (from, to) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(from), Some(to)) => from.can_transition_to(to),
+    };
+    if allowed {
+        Ok(())
+    } else {
+        let describe = |s: Option<OrderStatus>| s.map(|s| s.as_str()).unwrap_or("none").to_string();
+        Err(TransitionError { order_id: order_id.to_string(), from: describe(from), to: describe(to) })
+    }
+}
+
+impl MatchType {
+    pub const ALL: [MatchType; 3] = [MatchType::FullMatch, MatchType::NoMatch, MatchType::NeedsReview];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchType::FullMatch => "full_match",
+            MatchType::NoMatch => "no_match",
+            MatchType::NeedsReview => "needs_review",
+        }
+    }
+
+    /// Text written to the sheet, kept identical to what the reconcile used to write.
+    pub fn label(&self) -> &'static str {
+        match self {
+            MatchType::FullMatch => "Full Match",
+            MatchType::NoMatch => "None",
+            MatchType::NeedsReview => "Needs Review",
+        }
+    }
+}
+
+impl ManualConfirmation {
+    pub const ALL: [ManualConfirmation; 3] = [
+        ManualConfirmation::Pending,
+        ManualConfirmation::Confirmed,
+        ManualConfirmation::Rejected,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManualConfirmation::Pending => "pending",
+            ManualConfirmation::Confirmed => "confirmed",
+            ManualConfirmation::Rejected => "rejected",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ManualConfirmation::Pending => "Pending",
+            ManualConfirmation::Confirmed => "Confirmed",
+            ManualConfirmation::Rejected => "Rejected",
+        }
+    }
+}
+
+impl MainUpdated {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MainUpdated::Pending => "FALSE",
+            MainUpdated::Updated => "TRUE",
+        }
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = ParseStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = normalize_label(s);
+        OrderStatus::ALL.into_iter()
+            .find(|v| normalize_label(v.as_str()) == value)
+            .ok_or(ParseStatusError { kind: "status", value: s.to_string() })
+    }
+}
+
+impl FromStr for MatchType {
+    type Err = ParseStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = normalize_label(s);
+        MatchType::ALL.into_iter()
+            .find(|v| normalize_label(v.as_str()) == value || normalize_label(v.label()) == value)
+            .ok_or(ParseStatusError { kind: "match_type", value: s.to_string() })
+    }
+}
+
+impl FromStr for ManualConfirmation {
+    type Err = ParseStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = normalize_label(s);
+        ManualConfirmation::ALL.into_iter()
+            .find(|v| normalize_label(v.as_str()) == value)
+            .ok_or(ParseStatusError { kind: "manual_confirmation", value: s.to_string() })
+    }
+}
+
+impl FromStr for MainUpdated {
+    type Err = ParseStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match normalize_label(s).as_str() {
+            "pending" | "false" | "no" | "n" => Ok(MainUpdated::Pending),
+            "updated" | "true" | "yes" | "y" => Ok(MainUpdated::Updated),
+            _ => Err(ParseStatusError { kind: "main_updated", value: s.to_string() }),
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl fmt::Display for MatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl fmt::Display for ManualConfirmation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl fmt::Display for MainUpdated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_stay_put_or_move_one_step_forward() {
+        use OrderStatus::*;
+        assert_eq!(validate_transition("1", Some(Matched), Some(Matched)), Ok(()));
+        assert_eq!(validate_transition("1", Some(Received), Some(Matched)), Ok(()));
+        assert_eq!(validate_transition("1", Some(Refunded), Some(Restocked)), Ok(()));
+        // a new order or one without a status may start anywhere
+        assert_eq!(validate_transition("1", None, Some(Refunded)), Ok(()));
+        assert_eq!(validate_transition("1", None, None), Ok(()));
+    }
+
+    #[test]
+    fn skipping_going_back_and_clearing_are_rejected() {
+        use OrderStatus::*;
+        let rejected = |from, to, described: (&str, &str)| {
+            assert_eq!(validate_transition("104522", from, to), Err(TransitionError {
+                order_id: "104522".to_string(),
+                from: described.0.to_string(),
+                to: described.1.to_string(),
+            }));
+        };
+        rejected(Some(Received), Some(Confirmed), ("received", "confirmed"));
+        rejected(Some(Confirmed), Some(Matched), ("confirmed", "matched"));
+        rejected(Some(Restocked), Some(Received), ("restocked", "received"));
+        rejected(Some(Matched), None, ("matched", "none"));
+    }
+
+    #[test]
+    fn sheet_labels_parse_in_any_spelling() {
+        for text in ["Full Match", "full_match", "FULL-MATCH", "  full   match "] {
+            assert_eq!(text.parse(), Ok(MatchType::FullMatch), "{:?}", text);
+        }
+        assert_eq!("None".parse(), Ok(MatchType::NoMatch));
+        assert_eq!("no_match".parse(), Ok(MatchType::NoMatch));
+        assert_eq!("Matched".parse(), Ok(OrderStatus::Matched));
+        assert_eq!("FALSE".parse(), Ok(MainUpdated::Pending));
+        assert_eq!("yes".parse(), Ok(MainUpdated::Updated));
+    }
+
+    #[test]
+    fn unknown_text_is_a_parse_error() {
+        assert_eq!("no idea".parse::<ManualConfirmation>(), Err(ParseStatusError {
+            kind: "manual_confirmation",
+            value: "no idea".to_string(),
+        }));
+        assert_eq!("Partial Match".parse::<MatchType>().unwrap_err().kind, "match_type");
+        assert_eq!("".parse::<OrderStatus>().unwrap_err().kind, "status");
+    }
+}
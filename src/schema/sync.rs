@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+use crate::schema::{ history::FieldChange, order::{ Order, RowError }, status::MatchType };
+
+/// Values of the owned sheet columns as they were at the last successful sync, by field name.
+pub type SyncBaseline = BTreeMap<String, String>;
+
+/// Fingerprint of a sheet row as of the last sync, rows where neither it nor the order
+/// changed are skipped.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RowState {
+    /// sha256 of the row's position and cells
+    pub hash: String,
+    /// `Order.updated_at` once the row was merged, so DB-side edits are noticed too
+    pub order_updated_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncSide {
+    Sheet,
+    Db,
+}
+
+/// A field edited on both the sheet and the DB since the last sync, to differing values.
+/// Neither side is touched for that field until the conflict is resolved.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub id: String,
+    pub order_id: String,
+    #[schema(example = "returned_sku")]
+    pub field: String,
+    /// Row in the tab the field lives in
+    pub row_number: Option<usize>,
+    /// Value at the last sync, `None` if the order had never been synced
+    pub base: Option<String>,
+    pub sheet: String,
+    pub db: String,
+    pub detected_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone)]
+pub struct ResolveConflict {
+    /// Side whose value is written to the other one
+    pub keep: SyncSide,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Orders created from rows the DB didn't know
+    pub added: usize,
+    /// Rows merged because they or their order changed since the last sync
+    pub changed: usize,
+    /// Rows skipped because neither they nor their order changed
+    pub unchanged: usize,
+    /// Rows seen by the last sync that are gone from the sheet
+    pub removed: usize,
+    /// Fields copied from the sheet into the DB
+    pub pulled: usize,
+    /// Fields copied from the DB into the sheet
+    pub pushed: usize,
+    /// Reconciliation rows added for orders that had none
+    pub appended: usize,
+    /// Fields left alone because both sides changed them
+    pub conflicts: usize,
+    /// Sheet writes that failed, retried on the next sync
+    pub failed_rows: usize,
+    /// Rows that could not be read as an order, left out of the sync
+    pub rejected: Vec<RejectedRow>,
+}
+
+/// A sheet row left out of a sync, with every reason it was rejected.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct RejectedRow {
+    #[schema(example = "Sheet1")]
+    pub tab: String,
+    /// 1-based, as shown in the sheet
+    pub row_number: usize,
+    /// `None` when the row has none
+    pub order_id: Option<String>,
+    pub errors: Vec<RowError>,
+}
+
+/// Fields of one order a sync or reconcile would change in the DB.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct OrderDiff {
+    pub id: String,
+    pub order_id: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Cells of one sheet row a sync would write, by field name.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct SheetRowDiff {
+    #[schema(example = "Sheet1")]
+    pub tab: String,
+    /// `None` for a row that would be appended
+    pub row_number: Option<usize>,
+    pub order_id: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// What a sync would do, worked out without writing to the DB or the sheet.
+/// Orders it would create get their reconciliation row on the sync after.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Default, PartialEq, Eq)]
+pub struct SyncPreview {
+    /// Orders that would be created from rows the DB doesn't know
+    pub created: Vec<Order>,
+    pub updated: Vec<OrderDiff>,
+    pub sheet: Vec<SheetRowDiff>,
+    pub conflicts: Vec<SyncConflict>,
+    /// Rows skipped because neither they nor their order changed
+    pub unchanged: usize,
+    pub rejected: Vec<RejectedRow>,
+}
+
+/// What a Linnworks lookup would change on one order.
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Eq)]
+pub struct MatchPreview {
+    pub order_id: String,
+    /// `false` when the order isn't in the DB or no marketplace recognised it
+    pub recognised: bool,
+    /// Match the order would end up with
+    pub match_type: Option<MatchType>,
+    /// 1-based line of the Linnworks order the returned SKU was found on
+    pub matched_line: Option<usize>,
+    pub changes: Vec<FieldChange>,
+}
@@ -0,0 +1,275 @@
+use std::{ collections::HashMap, error::Error, fmt, fs, sync::{ Arc, Mutex } };
+
+use jsonwebtoken::{ Algorithm, EncodingKey, Header };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use crate::scripts::http::{ Api, HttpClient };
+
+pub const SHEETS_SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+
+/// Tokens are replaced this long before they expire.
+const REFRESH_MARGIN_SECS: i64 = 5 * 60;
+/// Lifetime assumed when a token response has no `expires_in`.
+const DEFAULT_EXPIRES_IN_SECS: i64 = 3600;
+/// Whole service account key as JSON, read before `GOOGLE_CLIENT_EMAIL`/`GOOGLE_PRIVATE_KEY`.
+const SERVICE_ACCOUNT_JSON_ENV: &str = "GOOGLE_SERVICE_ACCOUNT_JSON";
+const CLIENT_EMAIL_ENV: &str = "GOOGLE_CLIENT_EMAIL";
+const PRIVATE_KEY_ENV: &str = "GOOGLE_PRIVATE_KEY";
+
+#[derive(Clone, Deserialize)]
+pub struct ServiceAccount {
+    pub client_email: String,
+    pub private_key: String,
+}
+
+impl fmt::Debug for ServiceAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceAccount")
+            .field("client_email", &self.client_email)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServiceAccount {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file_content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&file_content)?)
+    }
+
+    /// Key from the environment, `None` when none of the variables is set.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        if let Ok(json) = std::env::var(SERVICE_ACCOUNT_JSON_ENV) {
+            return Ok(Some(serde_json::from_str(&json)?));
+        }
+        match (std::env::var(CLIENT_EMAIL_ENV), std::env::var(PRIVATE_KEY_ENV)) {
+            (Ok(client_email), Ok(private_key)) =>
+                Ok(
+                    Some(ServiceAccount {
+                        client_email,
+                        // keys pasted into .env files usually carry escaped newlines
+                        private_key: private_key.replace("\\n", "\n"),
+                    })
+                ),
+            (Err(_), Err(_)) => Ok(None),
+            _ => Err(format!("set both {} and {}", CLIENT_EMAIL_ENV, PRIVATE_KEY_ENV).into()),
+        }
+    }
+
+    /// The environment wins over the key file at `path`.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        match ServiceAccount::from_env()? {
+            Some(account) => Ok(account),
+            None =>
+                ServiceAccount::from_file(path).map_err(|e| {
+                    format!("cannot load {}: {}", path, e).into()
+                }),
+        }
+    }
+
+    /// Whether the key comes from the environment rather than a file.
+    pub fn in_env() -> bool {
+        [SERVICE_ACCOUNT_JSON_ENV, CLIENT_EMAIL_ENV]
+            .iter()
+            .any(|name| std::env::var_os(name).is_some())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    iss: String,
+    scope: String,
+    aud: String,
+    exp: i64,
+    iat: i64,
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    token: String,
+    /// Unix seconds
+    expires_at: i64,
+}
+
+/// One token per (account, scope). The async lock makes concurrent callers wait for a
+/// single refresh instead of each fetching their own.
+type TokenSlot = Arc<tokio::sync::Mutex<Option<CachedToken>>>;
+
+/// OAuth access tokens for service accounts, shared by every client using the same token URL.
+#[derive(Debug, Clone)]
+pub struct TokenProvider {
+    http: HttpClient,
+    token_url: String,
+    slots: Arc<Mutex<HashMap<(String, String), TokenSlot>>>,
+}
+
+impl TokenProvider {
+    pub fn new(http: HttpClient, token_url: &str) -> Self {
+        TokenProvider { http, token_url: token_url.to_string(), slots: Arc::default() }
+    }
+
+    fn slot(&self, account: &ServiceAccount, scope: &str) -> TokenSlot {
+        let mut slots = self.slots.lock().unwrap();
+        slots.entry((account.client_email.clone(), scope.to_string())).or_default().clone()
+    }
+
+    /// A token valid for at least a few more minutes. A failed refresh falls back to the
+    /// cached token while it has not expired yet.
+    pub async fn token(
+        &self,
+        account: &ServiceAccount,
+        scope: &str
+    ) -> Result<String, Box<dyn Error>> {
+        let slot = self.slot(account, scope);
+        let mut cached = slot.lock().await;
+        let now = chrono::Utc::now().timestamp();
+        if let Some(token) = cached.as_ref() && token.expires_at - now > REFRESH_MARGIN_SECS {
+            return Ok(token.token.clone());
+        }
+        match self.fetch(account, scope, now).await {
+            Ok(fresh) => {
+                let token = fresh.token.clone();
+                *cached = Some(fresh);
+                Ok(token)
+            }
+            Err(e) =>
+                match cached.as_ref() {
+                    Some(token) if token.expires_at > now => {
+                        println!(
+                            "⚠️ Token refresh for {} failed, using the current one: {}",
+                            account.client_email,
+                            e
+                        );
+                        Ok(token.token.clone())
+                    }
+                    _ => Err(e),
+                }
+        }
+    }
+
+    async fn fetch(
+        &self,
+        account: &ServiceAccount,
+        scope: &str,
+        now: i64
+    ) -> Result<CachedToken, Box<dyn Error>> {
+        let claims = Claims {
+            iss: account.client_email.clone(),
+            scope: scope.to_string(),
+            aud: self.token_url.clone(),
+            exp: now + DEFAULT_EXPIRES_IN_SECS,
+            iat: now,
+        };
+        let jwt = jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &EncodingKey::from_rsa_pem(account.private_key.as_bytes())?
+        )?;
+
+        let form = [
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", jwt.as_str()),
+        ];
+        let response = self.http
+            .send(Api::GoogleOAuth, |c| c.post(&self.token_url).form(&form)).await?
+            .json::<Value>().await?;
+
+        let token = response["access_token"]
+            .as_str()
+            .ok_or("token response has no access_token")?
+            .to_string();
+        let expires_in = response["expires_in"].as_i64().unwrap_or(DEFAULT_EXPIRES_IN_SECS);
+        Ok(CachedToken { token, expires_at: now + expires_in })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::settings::HttpConfig,
+        scripts::fake_sheets::{ tests::test_account, FakeSheets },
+    };
+
+    fn provider(fake: &FakeSheets) -> TokenProvider {
+        let base_url = fake.start().unwrap();
+        let http = HttpClient::new(&(HttpConfig { max_retries: 0, ..HttpConfig::default() })).unwrap();
+        TokenProvider::new(http, &format!("{}/token", base_url))
+    }
+
+    #[actix_web::test]
+    async fn tokens_are_cached_per_account_and_scope() {
+        let fake = FakeSheets::default();
+        let tokens = provider(&fake);
+        let account = test_account();
+        let other = ServiceAccount {
+            client_email: "other@orders-test.iam.gserviceaccount.com".to_string(),
+            ..test_account()
+        };
+
+        let first = tokens.token(&account, SHEETS_SCOPE).await.unwrap();
+        assert_eq!(tokens.token(&account, SHEETS_SCOPE).await.unwrap(), first);
+        assert_eq!(fake.tokens_issued(), 1);
+        assert_ne!(tokens.token(&account, "https://www.googleapis.com/auth/drive").await.unwrap(), first);
+        assert_ne!(tokens.token(&other, SHEETS_SCOPE).await.unwrap(), first);
+        assert_eq!(fake.tokens_issued(), 3);
+    }
+
+    #[actix_web::test]
+    async fn tokens_are_refreshed_within_the_margin() {
+        let fake = FakeSheets::default();
+        let tokens = provider(&fake);
+        let account = test_account();
+
+        fake.set_token_expires_in(Some(REFRESH_MARGIN_SECS - 10));
+        let first = tokens.token(&account, SHEETS_SCOPE).await.unwrap();
+        let second = tokens.token(&account, SHEETS_SCOPE).await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fake.tokens_issued(), 2);
+
+        // no `expires_in`, the default lifetime is well outside the margin
+        fake.set_token_expires_in(None);
+        let third = tokens.token(&account, SHEETS_SCOPE).await.unwrap();
+        assert_eq!(tokens.token(&account, SHEETS_SCOPE).await.unwrap(), third);
+        assert_eq!(fake.tokens_issued(), 3);
+    }
+
+    #[actix_web::test]
+    async fn a_failed_refresh_falls_back_to_a_token_not_expired_yet() {
+        let fake = FakeSheets::default();
+        let tokens = provider(&fake);
+        let account = test_account();
+
+        fake.set_token_expires_in(Some(REFRESH_MARGIN_SECS - 10));
+        let current = tokens.token(&account, SHEETS_SCOPE).await.unwrap();
+        fake.set_token_failing(true);
+        assert_eq!(tokens.token(&account, SHEETS_SCOPE).await.unwrap(), current);
+
+        // an expired token is no fallback
+        fake.set_token_failing(false);
+        fake.set_token_expires_in(Some(0));
+        tokens.token(&account, SHEETS_SCOPE).await.unwrap();
+        fake.set_token_failing(true);
+        assert!(tokens.token(&account, SHEETS_SCOPE).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn concurrent_callers_share_one_refresh() {
+        let fake = FakeSheets::default();
+        let tokens = provider(&fake);
+        let account = test_account();
+
+        let calls: Vec<_> = (0..8)
+            .map(|_| {
+                let (tokens, account) = (tokens.clone(), account.clone());
+                actix_web::rt::spawn(async move {
+                    tokens.token(&account, SHEETS_SCOPE).await.map_err(|e| e.to_string())
+                })
+            })
+            .collect();
+        for call in calls {
+            assert_eq!(call.await.unwrap().unwrap(), "fake-token-1");
+        }
+        assert_eq!(fake.tokens_issued(), 1);
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+/// Sheet columns the service knows about, independent of where they sit in the tab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    Marketplace,
+    ReturnedSku,
+    OrderId,
+    RefundRequested,
+    Date,
+    Refunded,
+    MatchType,
+    /// Hidden column holding `Order.id`, so a row can be found wherever it has moved to
+    RowId,
+    ShopifyId,
+    OfferSku,
+    MatchedSku,
+    ManualConfirmation,
+    Status,
+    Qty,
+    MainUpdated,
+}
+
+impl Column {
+    pub fn from_name(name: &str) -> Option<Column> {
+        SHEET1_COLUMNS.iter()
+            .chain(SHEET2_COLUMNS)
+            .map(|spec| spec.column)
+            .find(|column| column.name() == name)
+    }
+
+    /// `Order` field the column holds, also used as the key of sync baselines and conflicts.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Marketplace => "marketplace",
+            Column::ReturnedSku => "returned_sku",
+            Column::OrderId => "order_id",
+            Column::RefundRequested => "refund_requested",
+            Column::Date => "date",
+            Column::Refunded => "refunded",
+            Column::MatchType => "match_type",
+            Column::RowId => "id",
+            Column::ShopifyId => "shopify_id",
+            Column::OfferSku => "offer_sku",
+            Column::MatchedSku => "matched_sku",
+            Column::ManualConfirmation => "manual_confirmation",
+            Column::Status => "status",
+            Column::Qty => "qty",
+            Column::MainUpdated => "main_updated",
+        }
+    }
+}
+
+/// Who writes a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    /// Filled in when the service appends a row, left to people afterwards
+    OnAppend,
+    /// Kept in step with the order on every write
+    Owned,
+    /// Written from the order but never read back, the field is edited in another tab
+    Mirrored,
+}
+
+pub struct ColumnSpec {
+    pub column: Column,
+    /// Accepted header texts, preferred first. Compared case- and whitespace-insensitively.
+    pub headers: &'static [&'static str],
+    pub ownership: Ownership,
+    pub required: bool,
+}
+
+/// Orders tab (`Sheet1`). Columns not listed here (BIN RACK, RETURN REASON, ...) are
+/// never written by the service.
+pub const SHEET1_COLUMNS: &[ColumnSpec] = &[
+    ColumnSpec {
+        column: Column::Marketplace,
+        headers: &["MARKETPLACE", "CHANNEL VLOOKUP", "CHANNEL"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::ReturnedSku,
+        headers: &["RETURNED SKU", "RETURN SKU"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::OrderId,
+        headers: &["ORDER ID", "ORDER NUMBER"],
+        ownership: Ownership::OnAppend,
+        required: true,
+    },
+    ColumnSpec {
+        column: Column::RefundRequested,
+        headers: &["REFUND YES", "REFUND"],
+        ownership: Ownership::OnAppend,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::Date,
+        headers: &["DATE", "RETURN DATE"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::Refunded,
+        headers: &["REFUNDED"],
+        ownership: Ownership::OnAppend,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::MatchType,
+        headers: &["MATCH TYPE"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::RowId,
+        headers: &[ROW_ID_HEADER],
+        ownership: Ownership::OnAppend,
+        required: false,
+    },
+];
+
+/// Reconciliation tab (`Sheet2`), one row per order joined to Sheet1 by order id.
+/// Every field here is owned by this tab except the match type, which is edited on Sheet1.
+pub const SHEET2_COLUMNS: &[ColumnSpec] = &[
+    ColumnSpec {
+        column: Column::OrderId,
+        headers: &["ORDER ID", "ORDER NUMBER"],
+        ownership: Ownership::OnAppend,
+        required: true,
+    },
+    ColumnSpec {
+        column: Column::ShopifyId,
+        headers: &["SHOPIFY ID"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::OfferSku,
+        headers: &["OFFER SKU"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::MatchedSku,
+        headers: &["MATCHED SKU"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::MatchType,
+        headers: &["MATCH TYPE"],
+        ownership: Ownership::Mirrored,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::ManualConfirmation,
+        headers: &["MANUAL CONFIRMATION"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::Status,
+        headers: &["STATUS"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::Qty,
+        headers: &["QTY", "QUANTITY"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+    ColumnSpec {
+        column: Column::MainUpdated,
+        headers: &["MAIN UPDATED"],
+        ownership: Ownership::Owned,
+        required: false,
+    },
+];
+
+/// Columns a tab merges with the DB, the ones its sync baselines and conflicts are about.
+pub fn merged_columns(specs: &[ColumnSpec]) -> Vec<Column> {
+    specs
+        .iter()
+        .filter(|spec| spec.ownership == Ownership::Owned)
+        .map(|spec| spec.column)
+        .collect()
+}
+
+/// Header of the hidden id column, added by the first sync of a sheet that lacks it.
+pub const ROW_ID_HEADER: &str = "ROW ID";
+
+/// Reads and writes go through `A:Z`, the id column has to fit in it.
+pub const MAX_COLUMNS: usize = 26;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ColumnError {
+    #[error("sheet has no header row")]
+    NoHeader,
+    #[error("sheet header has no {column:?} column, expected one of {headers:?}")]
+    Missing {
+        column: Column,
+        headers: &'static [&'static str],
+    },
+}
+
+/// Positions of the known columns in one tab, resolved from its header row.
+#[derive(Debug, Clone)]
+pub struct HeaderMap {
+    positions: HashMap<Column, usize>,
+    ownership: HashMap<Column, Ownership>,
+    width: usize,
+}
+
+fn normalize_header(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_uppercase()
+}
+
+impl HeaderMap {
+    pub fn resolve(specs: &[ColumnSpec], header: &[String]) -> Result<Self, ColumnError> {
+        if header.iter().all(|h| h.trim().is_empty()) {
+            return Err(ColumnError::NoHeader);
+        }
+        let normalized: Vec<String> = header
+            .iter()
+            .map(|h| normalize_header(h))
+            .collect();
+        let mut positions = HashMap::new();
+        let mut ownership = HashMap::new();
+        for spec in specs {
+            let position = spec.headers
+                .iter()
+                .find_map(|name| normalized.iter().position(|h| *h == normalize_header(name)));
+            match position {
+                Some(position) => {
+                    positions.insert(spec.column, position);
+                    ownership.insert(spec.column, spec.ownership);
+                }
+                None if spec.required => {
+                    return Err(ColumnError::Missing { column: spec.column, headers: spec.headers });
+                }
+                None => println!("⚠️ Sheet header has no {:?} column, it will be skipped", spec.column),
+            }
+        }
+        Ok(HeaderMap { positions, ownership, width: header.len() })
+    }
+
+    pub fn position(&self, column: Column) -> Option<usize> {
+        self.positions.get(&column).copied()
+    }
+
+    /// Trimmed cell value, `None` when the column is unmapped or the row is short.
+    pub fn cell<'a>(&self, row: &'a [String], column: Column) -> Option<&'a str> {
+        self.position(column)
+            .and_then(|i| row.get(i))
+            .map(|v| v.trim())
+    }
+
+    /// Index of the row holding order `id`. Rows are matched by their id cell, and only
+    /// rows that have no id yet fall back to `order_id`, so a row that was moved, or a
+    /// different return reusing the same order id, is never mistaken for another.
+    pub fn find_row(&self, rows: &[Vec<String>], id: &str, order_id: &str) -> Option<usize> {
+        let data = || rows.iter().enumerate().skip(1);
+        data()
+            .find(|(_, row)| self.cell(row, Column::RowId) == Some(id))
+            .or_else(|| {
+                data().find(|(_, row)| {
+                    self.cell(row, Column::RowId).unwrap_or_default().is_empty() &&
+                        self.cell(row, Column::OrderId) == Some(order_id)
+                })
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// Index of the first row for `order_id`, for tabs joined by order id.
+    pub fn find_order_row(&self, rows: &[Vec<String>], order_id: &str) -> Option<usize> {
+        rows.iter()
+            .enumerate()
+            .skip(1)
+            .find(|(_, row)| self.cell(row, Column::OrderId) == Some(order_id))
+            .map(|(i, _)| i)
+    }
+
+    /// A full row for appending: columns the service writes are filled from `value`,
+    /// everything else is left blank.
+    pub fn append_row(&self, value: impl Fn(Column) -> String) -> Vec<String> {
+        let mut row = vec![String::new(); self.width];
+        for (column, position) in &self.positions {
+            row[*position] = value(*column);
+        }
+        row
+    }
+
+    /// A row for updating in place with just `cells` set.
+    pub fn partial_row(&self, cells: &[(Column, String)]) -> Vec<Option<String>> {
+        let mut row = vec![None; self.width];
+        for (column, value) in cells {
+            if let Some(position) = self.position(*column) {
+                row[position] = Some(value.clone());
+            }
+        }
+        row
+    }
+
+    /// Mapped columns the service keeps in step with the order, in a stable order.
+    pub fn owned_columns(&self) -> Vec<Column> {
+        self.columns_with(Ownership::Owned)
+    }
+
+    /// Mapped columns only ever written from the order.
+    pub fn mirrored_columns(&self) -> Vec<Column> {
+        self.columns_with(Ownership::Mirrored)
+    }
+
+    fn columns_with(&self, wanted: Ownership) -> Vec<Column> {
+        let mut columns: Vec<Column> = self.ownership
+            .iter()
+            .filter(|(_, ownership)| **ownership == wanted)
+            .map(|(column, _)| *column)
+            .collect();
+        columns.sort_by_key(|column| self.positions[column]);
+        columns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn label(column: Column) -> String {
+        format!("{:?}", column)
+    }
+
+    #[test]
+    fn columns_are_found_wherever_they_sit() {
+        let map = HeaderMap::resolve(
+            SHEET1_COLUMNS,
+            &header(&["BIN RACK", " order  id ", "Returned SKU", "DATE", "CHANNEL VLOOKUP"])
+        ).unwrap();
+        assert_eq!(map.position(Column::OrderId), Some(1));
+        assert_eq!(map.position(Column::ReturnedSku), Some(2));
+        assert_eq!(map.position(Column::Date), Some(3));
+        // an alternative header text
+        assert_eq!(map.position(Column::Marketplace), Some(4));
+
+        let row = header(&["B-12", "7001", " ABC-1 "]);
+        assert_eq!(map.cell(&row, Column::ReturnedSku), Some("ABC-1"));
+        // the row stops before the DATE column
+        assert_eq!(map.cell(&row, Column::Date), None);
+    }
+
+    #[test]
+    fn missing_optional_columns_are_skipped() {
+        let map = HeaderMap::resolve(SHEET1_COLUMNS, &header(&["ORDER ID", "NOTES"])).unwrap();
+        assert_eq!(map.position(Column::OrderId), Some(0));
+        for column in [Column::Marketplace, Column::ReturnedSku, Column::Refunded, Column::MatchType] {
+            assert_eq!(map.position(column), None, "{:?}", column);
+            assert_eq!(map.cell(&header(&["7001", "x"]), column), None);
+        }
+    }
+
+    #[test]
+    fn required_columns_and_the_header_row_must_exist() {
+        assert_eq!(
+            HeaderMap::resolve(SHEET1_COLUMNS, &header(&["RETURNED SKU", "DATE"])).unwrap_err(),
+            ColumnError::Missing { column: Column::OrderId, headers: &["ORDER ID", "ORDER NUMBER"] }
+        );
+        assert_eq!(HeaderMap::resolve(SHEET1_COLUMNS, &header(&["", " "])).unwrap_err(), ColumnError::NoHeader);
+        assert_eq!(HeaderMap::resolve(SHEET1_COLUMNS, &[]).unwrap_err(), ColumnError::NoHeader);
+    }
+
+    #[test]
+    fn writes_leave_unknown_columns_alone() {
+        let map = HeaderMap::resolve(
+            SHEET1_COLUMNS,
+            &header(&["ORDER ID", "BIN RACK", "RETURNED SKU", "RETURN REASON", "REFUNDED", "MATCH TYPE"])
+        ).unwrap();
+
+        assert_eq!(map.append_row(label), header(&["OrderId", "", "ReturnedSku", "", "Refunded", "MatchType"]));
+        // OnAppend columns (ORDER ID, REFUNDED) are people's to edit once the row exists
+        assert_eq!(map.owned_columns(), [Column::ReturnedSku, Column::MatchType]);
+        let cells: Vec<(Column, String)> = map.owned_columns()
+            .into_iter()
+            .map(|column| (column, label(column)))
+            .collect();
+        assert_eq!(map.partial_row(&cells), [
+            None,
+            None,
+            Some("ReturnedSku".to_string()),
+            None,
+            None,
+            Some("MatchType".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn rows_are_found_by_id_before_order_id() {
+        let map = HeaderMap::resolve(SHEET1_COLUMNS, &header(&["ORDER ID", ROW_ID_HEADER])).unwrap();
+        let rows = vec![
+            header(&["ORDER ID", ROW_ID_HEADER]),
+            // the same order id reused by a later return
+            header(&["7001", "id-b"]),
+            header(&["7001"]),
+            header(&["7002", "id-a"]),
+        ];
+        assert_eq!(map.find_row(&rows, "id-a", "7002"), Some(3));
+        assert_eq!(map.find_row(&rows, "id-b", "7001"), Some(1));
+        // rows carrying another id are never matched by order id
+        assert_eq!(map.find_row(&rows, "id-c", "7001"), Some(2));
+        assert_eq!(map.find_row(&rows, "id-c", "7002"), None);
+    }
+}
@@ -0,0 +1,408 @@
+use std::{ collections::HashMap, net::TcpListener, sync::{ Arc, Mutex } };
+
+use actix_web::{ web, App, HttpResponse, HttpServer, Responder };
+use serde_json::{ json, Value };
+
+/// In-memory stand-in for the Sheets `values` API and the OAuth token endpoint,
+/// so the import/update flows can run without network access.
+#[derive(Debug, Clone, Default)]
+pub struct FakeSheets {
+    /// `"{spreadsheet_id}/{tab}"` -> rows
+    tabs: Arc<Mutex<HashMap<String, Vec<Vec<String>>>>>,
+    /// `"{spreadsheet_id}/{sheet_id}"` -> hidden column indexes
+    hidden: Arc<Mutex<HashMap<String, Vec<usize>>>>,
+    token: Arc<Mutex<FakeToken>>,
+}
+
+/// What the token endpoint answers.
+#[derive(Debug)]
+struct FakeToken {
+    /// `None` leaves `expires_in` out of the response
+    expires_in: Option<i64>,
+    /// Answer 500 instead of a token
+    failing: bool,
+    /// Tokens handed out so far, numbered `fake-token-1`, `fake-token-2`, ...
+    issued: usize,
+}
+
+impl Default for FakeToken {
+    fn default() -> Self {
+        FakeToken { expires_in: Some(3600), failing: false, issued: 0 }
+    }
+}
+
+/// A parsed A1 range such as `Sheet1`, `Sheet1!A:Z` or `Sheet1!A5:Z5`.
+#[derive(Debug, PartialEq, Eq)]
+struct A1Range {
+    tab: String,
+    /// 0-based, inclusive
+    first_row: Option<usize>,
+    last_row: Option<usize>,
+    first_col: usize,
+}
+
+fn column_index(letters: &str) -> usize {
+    letters
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .fold(0, |acc, c| acc * 26 + ((c.to_ascii_uppercase() as usize) - ('A' as usize) + 1))
+        .saturating_sub(1)
+}
+
+fn split_cell(cell: &str) -> (usize, Option<usize>) {
+    let letters: String = cell
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect();
+    let row = cell[letters.len()..].parse::<usize>().ok().map(|r| r.saturating_sub(1));
+    (column_index(&letters), row)
+}
+
+fn parse_range(range: &str) -> A1Range {
+    let (tab, cells) = match range.split_once('!') {
+        Some((tab, cells)) => (tab, Some(cells)),
+        None => (range, None),
+    };
+    let tab = tab.trim_matches('\'').to_string();
+    let Some(cells) = cells else {
+        return A1Range { tab, first_row: None, last_row: None, first_col: 0 };
+    };
+    let (start, end) = cells.split_once(':').unwrap_or((cells, cells));
+    let (first_col, first_row) = split_cell(start);
+    let (_, last_row) = split_cell(end);
+    A1Range { tab, first_row, last_row, first_col }
+}
+
+impl FakeSheets {
+    fn key(spreadsheet_id: &str, tab: &str) -> String {
+        format!("{}/{}", spreadsheet_id, tab)
+    }
+
+    /// Replace the content of a tab, header row included.
+    pub fn seed(&self, spreadsheet_id: &str, tab: &str, rows: Vec<Vec<String>>) {
+        self.tabs.lock().unwrap().insert(Self::key(spreadsheet_id, tab), rows);
+    }
+
+    /// Lifetime of the tokens handed out from now on, `None` to leave it out of the response.
+    #[cfg(test)]
+    pub fn set_token_expires_in(&self, expires_in: Option<i64>) {
+        self.token.lock().unwrap().expires_in = expires_in;
+    }
+
+    /// Make the token endpoint fail, or work again.
+    #[cfg(test)]
+    pub fn set_token_failing(&self, failing: bool) {
+        self.token.lock().unwrap().failing = failing;
+    }
+
+    #[cfg(test)]
+    pub fn tokens_issued(&self) -> usize {
+        self.token.lock().unwrap().issued
+    }
+
+    /// Load `{ "<spreadsheet_id>": { "<tab>": [[cell, ...], ...] } }` from a JSON file.
+    pub fn seed_from_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)?;
+        let seed: HashMap<String, HashMap<String, Vec<Vec<String>>>> = serde_json::from_str(
+            &content
+        )?;
+        for (spreadsheet_id, tabs) in seed {
+            for (tab, rows) in tabs {
+                self.seed(&spreadsheet_id, &tab, rows);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rows(&self, spreadsheet_id: &str, tab: &str) -> Vec<Vec<String>> {
+        self.tabs.lock().unwrap().get(&Self::key(spreadsheet_id, tab)).cloned().unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    pub fn hidden_columns(&self, spreadsheet_id: &str, sheet_id: usize) -> Vec<usize> {
+        let key = format!("{}/{}", spreadsheet_id, sheet_id);
+        self.hidden.lock().unwrap().get(&key).cloned().unwrap_or_default()
+    }
+
+    /// Tabs of a spreadsheet in a stable order, their position doubles as the sheet id.
+    fn tab_titles(&self, spreadsheet_id: &str) -> Vec<String> {
+        let prefix = format!("{}/", spreadsheet_id);
+        let mut titles: Vec<String> = self.tabs
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|k| k.strip_prefix(&prefix).map(|t| t.to_string()))
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    fn get_values(&self, spreadsheet_id: &str, range: &A1Range) -> Vec<Vec<String>> {
+        let rows = self.rows(spreadsheet_id, &range.tab);
+        let first = range.first_row.unwrap_or(0);
+        let last = range.last_row.unwrap_or(usize::MAX);
+        rows.into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i >= first && *i <= last)
+            .map(|(_, row)| row.into_iter().skip(range.first_col).collect())
+            .collect()
+    }
+
+    fn append_values(&self, spreadsheet_id: &str, range: &A1Range, values: Vec<Vec<String>>) {
+        let mut tabs = self.tabs.lock().unwrap();
+        let rows = tabs.entry(Self::key(spreadsheet_id, &range.tab)).or_default();
+        // Sheets appends after the last row that has any content
+        while rows.last().is_some_and(|row| row.iter().all(|c| c.is_empty())) {
+            rows.pop();
+        }
+        rows.extend(values);
+    }
+
+    /// `None` cells keep their current value, like `null` in the real API.
+    fn put_values(&self, spreadsheet_id: &str, range: &A1Range, values: Vec<Vec<Option<String>>>) {
+        let mut tabs = self.tabs.lock().unwrap();
+        let rows = tabs.entry(Self::key(spreadsheet_id, &range.tab)).or_default();
+        let first = range.first_row.unwrap_or(0);
+        for (offset, new_row) in values.into_iter().enumerate() {
+            let index = first + offset;
+            if rows.len() <= index {
+                rows.resize(index + 1, Vec::new());
+            }
+            let row = &mut rows[index];
+            if row.len() < range.first_col + new_row.len() {
+                row.resize(range.first_col + new_row.len(), String::new());
+            }
+            for (col, cell) in new_row.into_iter().enumerate() {
+                if let Some(cell) = cell {
+                    row[range.first_col + col] = cell;
+                }
+            }
+        }
+    }
+
+    fn clear_values(&self, spreadsheet_id: &str, range: &A1Range) {
+        let mut tabs = self.tabs.lock().unwrap();
+        if let Some(rows) = tabs.get_mut(&Self::key(spreadsheet_id, &range.tab)) {
+            let first = range.first_row.unwrap_or(0);
+            let last = range.last_row.unwrap_or(usize::MAX);
+            for (i, row) in rows.iter_mut().enumerate() {
+                if i >= first && i <= last {
+                    for cell in row.iter_mut().skip(range.first_col) {
+                        cell.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bind to a random local port and serve in the background. Returns the base URL
+    /// to hand to `GoogleSheetsClient`; the token endpoint lives at `{base}/token`.
+    pub fn start(&self) -> std::io::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let state = self.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .route("/token", web::post().to(token))
+                .route("/v4/spreadsheets/{id}", web::get().to(get_spreadsheet))
+                .route("/v4/spreadsheets/{id}", web::post().to(batch_update))
+                .route("/v4/spreadsheets/{id}/values/{range:.*}", web::get().to(get_values))
+                .route("/v4/spreadsheets/{id}/values/{range:.*}", web::put().to(put_values))
+                .route("/v4/spreadsheets/{id}/values/{range:.*}", web::post().to(post_values))
+        })
+            .workers(1)
+            .listen(listener)?
+            .run();
+        actix_web::rt::spawn(server);
+        Ok(base_url)
+    }
+}
+
+fn body_values(body: &Value) -> Vec<Vec<String>> {
+    crate::scripts::sheets::rows_from_value(body)
+}
+
+async fn token(sheets: web::Data<FakeSheets>) -> impl Responder {
+    let mut token = sheets.token.lock().unwrap();
+    if token.failing {
+        return HttpResponse::InternalServerError().body("token endpoint unavailable");
+    }
+    token.issued += 1;
+    let mut body = json!({
+        "access_token": format!("fake-token-{}", token.issued),
+        "token_type": "Bearer"
+    });
+    if let Some(expires_in) = token.expires_in {
+        body["expires_in"] = json!(expires_in);
+    }
+    HttpResponse::Ok().json(body)
+}
+
+async fn get_spreadsheet(sheets: web::Data<FakeSheets>, path: web::Path<String>) -> impl Responder {
+    let id = path.into_inner();
+    let tabs: Vec<Value> = sheets
+        .tab_titles(&id)
+        .into_iter()
+        .enumerate()
+        .map(|(i, title)| json!({ "properties": { "sheetId": i, "title": title } }))
+        .collect();
+    HttpResponse::Ok().json(json!({ "spreadsheetId": id, "sheets": tabs }))
+}
+
+/// Only `updateDimensionProperties` hiding columns is understood.
+async fn batch_update(
+    sheets: web::Data<FakeSheets>,
+    path: web::Path<String>,
+    body: web::Json<Value>
+) -> impl Responder {
+    let path = path.into_inner();
+    let Some(id) = path.strip_suffix(":batchUpdate") else {
+        return HttpResponse::NotFound().body(format!("unsupported spreadsheet operation: {}", path));
+    };
+    let requests = body["requests"].as_array().cloned().unwrap_or_default();
+    for request in requests {
+        let update = &request["updateDimensionProperties"];
+        if update["properties"]["hiddenByUser"] != json!(true) {
+            continue;
+        }
+        let range = &update["range"];
+        let key = format!("{}/{}", id, range["sheetId"]);
+        let start = range["startIndex"].as_u64().unwrap_or(0) as usize;
+        let end = range["endIndex"].as_u64().unwrap_or(start as u64 + 1) as usize;
+        sheets.hidden.lock().unwrap().entry(key).or_default().extend(start..end);
+    }
+    HttpResponse::Ok().json(json!({ "spreadsheetId": id, "replies": [] }))
+}
+
+async fn get_values(
+    sheets: web::Data<FakeSheets>,
+    path: web::Path<(String, String)>
+) -> impl Responder {
+    let (id, range) = path.into_inner();
+    let values = sheets.get_values(&id, &parse_range(&range));
+    HttpResponse::Ok().json(json!({ "range": range, "majorDimension": "ROWS", "values": values }))
+}
+
+async fn put_values(
+    sheets: web::Data<FakeSheets>,
+    path: web::Path<(String, String)>,
+    body: web::Json<Value>
+) -> impl Responder {
+    let (id, range) = path.into_inner();
+    let values = body["values"]
+        .as_array()
+        .map(|rows| {
+            rows.iter()
+                .map(|row| {
+                    row.as_array()
+                        .map(|cells| {
+                            cells
+                                .iter()
+                                .map(|c| c.as_str().map(|s| s.to_string()))
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    sheets.put_values(&id, &parse_range(&range), values);
+    HttpResponse::Ok().json(json!({ "spreadsheetId": id, "updatedRange": range }))
+}
+
+/// `:append` and `:clear` are suffixes on the range segment.
+async fn post_values(
+    sheets: web::Data<FakeSheets>,
+    path: web::Path<(String, String)>,
+    body: Option<web::Json<Value>>
+) -> impl Responder {
+    let (id, range) = path.into_inner();
+    if let Some(range) = range.strip_suffix(":append") {
+        let values = body.map(|b| body_values(&b)).unwrap_or_default();
+        sheets.append_values(&id, &parse_range(range), values);
+        HttpResponse::Ok().json(json!({ "spreadsheetId": id }))
+    } else if let Some(range) = range.strip_suffix(":clear") {
+        sheets.clear_values(&id, &parse_range(range));
+        HttpResponse::Ok().json(json!({ "spreadsheetId": id, "clearedRange": range }))
+    } else {
+        HttpResponse::NotFound().body(format!("unsupported values operation: {}", range))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        config::settings::HttpConfig,
+        scripts::{
+            auth::{ ServiceAccount, TokenProvider },
+            http::HttpClient,
+            sheets::{ rows_from_value, GoogleSheetsClient, SheetsClient },
+        },
+    };
+
+    /// Service account with a throwaway key, accepted by the fake's token endpoint.
+    pub(crate) fn test_account() -> ServiceAccount {
+        ServiceAccount::from_file(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/test_service_account.json")
+        ).unwrap()
+    }
+
+    /// A client for the fake started at `base_url`, signing in through its token endpoint.
+    pub(crate) fn client(base_url: &str) -> GoogleSheetsClient {
+        let http = HttpClient::new(&HttpConfig::default()).unwrap();
+        let tokens = TokenProvider::new(http.clone(), &format!("{}/token", base_url));
+        GoogleSheetsClient::new(http, base_url, tokens, Some(test_account()))
+    }
+
+    const SPREADSHEET: &str = "test-spreadsheet";
+
+    fn row(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn a1_ranges_parse_to_zero_based_rows() {
+        assert_eq!(parse_range("Sheet1"), A1Range {
+            tab: "Sheet1".to_string(),
+            first_row: None,
+            last_row: None,
+            first_col: 0,
+        });
+        assert_eq!(parse_range("'Sheet 2'!B5:Z7"), A1Range {
+            tab: "Sheet 2".to_string(),
+            first_row: Some(4),
+            last_row: Some(6),
+            first_col: 1,
+        });
+        assert_eq!(parse_range("Sheet1!A:Z").first_row, None);
+        assert_eq!(column_index("AA"), 26);
+    }
+
+    #[actix_web::test]
+    async fn client_round_trips_through_the_fake() {
+        let fake = FakeSheets::default();
+        fake.seed(SPREADSHEET, "Sheet1", vec![row(&["ORDER ID", "Returned SKU", "QTY"]), row(&["7001", "ABC-1", "1"])]);
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+
+        sheets.append(SPREADSHEET, "Sheet1!A:Z", vec![row(&["7002", "XYZ-2", "2"])]).await.unwrap();
+        // `None` leaves the cell as it is
+        sheets.update_row(SPREADSHEET, "Sheet1!A:Z", 1, vec![None, Some("ABC-9".to_string()), Some("3".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(fake.rows(SPREADSHEET, "Sheet1"), [
+            row(&["ORDER ID", "Returned SKU", "QTY"]),
+            row(&["7001", "ABC-9", "3"]),
+            row(&["7002", "XYZ-2", "2"]),
+        ]);
+
+        let fetched = sheets.fetch(SPREADSHEET, "Sheet1!A3:Z3").await.unwrap();
+        assert_eq!(rows_from_value(&fetched), [row(&["7002", "XYZ-2", "2"])]);
+
+        // the row stays, so the rows below keep their numbers
+        sheets.clear_row(SPREADSHEET, "Sheet1!A:Z", 1).await.unwrap();
+        assert_eq!(fake.rows(SPREADSHEET, "Sheet1")[1..], [row(&["", "", ""]), row(&["7002", "XYZ-2", "2"])]);
+    }
+}
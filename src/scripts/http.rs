@@ -0,0 +1,268 @@
+use std::{ collections::HashMap, fmt, sync::{ Arc, Mutex }, time::{ Duration, Instant } };
+
+use reqwest::{ header::RETRY_AFTER, Client, RequestBuilder, Response, StatusCode };
+
+use crate::config::settings::HttpConfig;
+
+/// Outbound APIs, each with its own request budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Api {
+    Sheets,
+    GoogleOAuth,
+    Linnworks,
+}
+
+impl fmt::Display for Api {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Api::Sheets => "Google Sheets",
+            Api::GoogleOAuth => "Google OAuth",
+            Api::Linnworks => "Linnworks",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("{api} request failed after {attempts} attempt(s): {source}")]
+    Transport {
+        api: Api,
+        attempts: u32,
+        source: reqwest::Error,
+    },
+    #[error("{api} returned {status} after {attempts} attempt(s): {body}")]
+    Status {
+        api: Api,
+        status: StatusCode,
+        attempts: u32,
+        body: String,
+    },
+}
+
+impl HttpError {
+    /// The status the API answered with, `None` when no response came back.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            HttpError::Status { status, .. } => Some(*status),
+            HttpError::Transport { .. } => None,
+        }
+    }
+}
+
+/// Token bucket refilled at `per_minute` requests a minute, holding at most that many.
+#[derive(Debug)]
+struct Budget {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// The one HTTP client of the service. Clones share the connection pool and the budgets.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    config: HttpConfig,
+    budgets: Arc<Mutex<HashMap<Api, Budget>>>,
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS ||
+        status == StatusCode::REQUEST_TIMEOUT ||
+        status.is_server_error()
+}
+
+/// `Retry-After` as either delay seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = at.timestamp() - chrono::Utc::now().timestamp();
+    Some(Duration::from_secs(wait.max(0) as u64))
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpConfig) -> Result<Self, reqwest::Error> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .build()?;
+        Ok(HttpClient { client, config: config.clone(), budgets: Arc::default() })
+    }
+
+    fn per_minute(&self, api: Api) -> u32 {
+        match api {
+            Api::Sheets => self.config.sheets_per_minute,
+            Api::GoogleOAuth => self.config.oauth_per_minute,
+            Api::Linnworks => self.config.linnworks_per_minute,
+        }
+    }
+
+    /// Wait until `api` has budget left for one more request and spend it.
+    async fn acquire(&self, api: Api) {
+        let per_minute = self.per_minute(api).max(1) as f64;
+        let rate = per_minute / 60.0;
+        loop {
+            let wait = {
+                let mut budgets = self.budgets.lock().unwrap();
+                let budget = budgets.entry(api).or_insert_with(|| Budget {
+                    tokens: per_minute,
+                    refilled_at: Instant::now(),
+                });
+                let now = Instant::now();
+                let elapsed = now.duration_since(budget.refilled_at).as_secs_f64();
+                budget.tokens = (budget.tokens + elapsed * rate).min(per_minute);
+                budget.refilled_at = now;
+                if budget.tokens >= 1.0 {
+                    budget.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - budget.tokens) / rate)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Exponential backoff with full jitter for retry `attempt` (1-based).
+    fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self.config.max_backoff_ms;
+        let ceiling = self.config.base_backoff_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+            .min(cap);
+        Duration::from_millis(rand::random_range(0..=ceiling))
+    }
+
+    /// Send the request `build` makes, retrying transport errors, 429, 408 and 5xx
+    /// responses. `Retry-After` is honoured over the computed backoff. Only a 2xx
+    /// response is returned, anything else comes back as an `HttpError`.
+    pub async fn send(
+        &self,
+        api: Api,
+        build: impl Fn(&Client) -> RequestBuilder
+    ) -> Result<Response, HttpError> {
+        self.send_with_retries(api, true, build).await
+    }
+
+    /// `send` for requests that must not be applied twice, such as appends. Only failures
+    /// that show the request never reached the API are retried: connection errors and 429.
+    /// A timeout or 5xx may hide a request that went through, so it is returned instead.
+    pub async fn send_non_idempotent(
+        &self,
+        api: Api,
+        build: impl Fn(&Client) -> RequestBuilder
+    ) -> Result<Response, HttpError> {
+        self.send_with_retries(api, false, build).await
+    }
+
+    async fn send_with_retries(
+        &self,
+        api: Api,
+        idempotent: bool,
+        build: impl Fn(&Client) -> RequestBuilder
+    ) -> Result<Response, HttpError> {
+        let max_attempts = self.config.max_retries + 1;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.acquire(api).await;
+            let (error, delay) = match build(&self.client).send().await {
+                Ok(response) if response.status().is_success() => {
+                    return Ok(response);
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let delay = retry_after(&response);
+                    let body = response.text().await.unwrap_or_default();
+                    let error = HttpError::Status { api, status, attempts: attempt, body };
+                    let retryable = if idempotent {
+                        is_retryable(status)
+                    } else {
+                        status == StatusCode::TOO_MANY_REQUESTS
+                    };
+                    if !retryable {
+                        return Err(error);
+                    }
+                    (error, delay)
+                }
+                Err(source) => {
+                    let never_sent = source.is_connect();
+                    let error = HttpError::Transport { api, attempts: attempt, source };
+                    if !idempotent && !never_sent {
+                        return Err(error);
+                    }
+                    (error, None)
+                }
+            };
+            if attempt >= max_attempts {
+                return Err(error);
+            }
+            let delay = delay
+                .map(|d| d.min(Duration::from_secs(self.config.max_retry_after_secs)))
+                .unwrap_or_else(|| self.backoff(attempt));
+            println!("⏳ {}, retrying in {:?}", error, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ net::TcpListener, sync::atomic::{ AtomicUsize, Ordering } };
+
+    use actix_web::{ web, App, HttpResponse, HttpServer };
+
+    use super::*;
+
+    /// A server answering every request with `status`, and the number of requests it got.
+    fn failing_server(status: u16) -> (String, Arc<AtomicUsize>) {
+        let status = actix_web::http::StatusCode::from_u16(status).unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let counter = hits.clone();
+        let server = HttpServer::new(move || {
+            let counter = counter.clone();
+            App::new().default_service(
+                web::to(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    async move { HttpResponse::build(status).finish() }
+                })
+            )
+        })
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run();
+        actix_web::rt::spawn(server);
+        (base_url, hits)
+    }
+
+    fn client() -> HttpClient {
+        HttpClient::new(
+            &(HttpConfig { max_retries: 2, base_backoff_ms: 1, max_backoff_ms: 1, ..HttpConfig::default() })
+        ).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn server_errors_are_retried_only_when_repeating_is_safe() {
+        let (base_url, hits) = failing_server(503);
+        let http = client();
+
+        let err = http.send(Api::Sheets, |c| c.post(&base_url)).await.unwrap_err();
+        assert!(matches!(err, HttpError::Status { status: StatusCode::SERVICE_UNAVAILABLE, .. }));
+        assert_eq!(hits.swap(0, Ordering::SeqCst), 3);
+
+        http.send_non_idempotent(Api::Sheets, |c| c.post(&base_url)).await.unwrap_err();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn rate_limited_appends_are_retried() {
+        let (base_url, hits) = failing_server(429);
+
+        let err = client().send_non_idempotent(Api::Sheets, |c| c.post(&base_url)).await.unwrap_err();
+        assert!(matches!(err, HttpError::Status { status: StatusCode::TOO_MANY_REQUESTS, .. }));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+}
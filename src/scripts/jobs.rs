@@ -0,0 +1,274 @@
+use std::{ collections::HashMap, error::Error, sync::{ Arc, Mutex }, time::Duration };
+
+use chrono::{ DateTime, Utc };
+
+use crate::{
+    config::settings::AppConfig,
+    lmdb::{ jobs::DBJobs, sync::DBSync, utils::DB },
+    schema::jobs::{ JobKind, JobOutcome, JobRun, JobStatus, JobTrigger },
+    scripts::{
+        linnworks::LinnworksSession,
+        marketplaces::MatcherRegistry,
+        outbox::flush_outbox,
+        sheets::SheetsClient,
+        update_fixed::reconcile_pending,
+    },
+};
+
+/// Wait before a scheduled run skipped because of an overlapping job is tried again.
+const BUSY_RETRY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct JobSlot {
+    running: bool,
+    /// Unix seconds
+    next_run_at: Option<i64>,
+}
+
+/// Runs the background jobs on their intervals and on demand, never two runs of
+/// the same job, or of jobs that exclude each other, at once.
+#[derive(Clone)]
+pub struct JobScheduler<S> {
+    db: DB,
+    sheets: S,
+    linnworks: LinnworksSession,
+    matchers: MatcherRegistry,
+    config: AppConfig,
+    slots: Arc<Mutex<HashMap<JobKind, JobSlot>>>,
+}
+
+/// Marks a job as running until dropped.
+pub struct JobGuard {
+    job: JobKind,
+    slots: Arc<Mutex<HashMap<JobKind, JobSlot>>>,
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        if let Ok(mut slots) = self.slots.lock() {
+            slots.entry(self.job).or_default().running = false;
+        }
+    }
+}
+
+impl<S: SheetsClient + Clone + 'static> JobScheduler<S> {
+    pub fn new(
+        db: DB,
+        sheets: S,
+        linnworks: LinnworksSession,
+        matchers: MatcherRegistry,
+        config: AppConfig
+    ) -> Self {
+        JobScheduler { db, sheets, linnworks, matchers, config, slots: Arc::default() }
+    }
+
+    /// Seconds between scheduled runs, 0 when the job only runs on demand.
+    pub fn interval(&self, job: JobKind) -> u64 {
+        match job {
+            JobKind::SheetImport => self.config.jobs.sheet_import_interval_secs,
+            JobKind::OutboxFlush => self.config.outbox.poll_interval_secs,
+            JobKind::LinnworksReconcile => self.config.jobs.linnworks_reconcile_interval_secs,
+        }
+    }
+
+    /// Mark `job` as running, `None` if it or a job it excludes already is.
+    pub fn try_claim(&self, job: JobKind) -> Option<JobGuard> {
+        let mut slots = self.slots.lock().ok()?;
+        let busy = std::iter
+            ::once(&job)
+            .chain(job.excludes())
+            .any(|other| slots.get(other).is_some_and(|slot| slot.running));
+        if busy {
+            return None;
+        }
+        slots.entry(job).or_default().running = true;
+        Some(JobGuard { job, slots: self.slots.clone() })
+    }
+
+    /// Run `job` once and record the outcome. `None` if it could not start because of an overlap.
+    pub async fn run(
+        &self,
+        job: JobKind,
+        trigger: JobTrigger
+    ) -> Result<Option<JobRun>, Box<dyn Error>> {
+        let Some(_guard) = self.try_claim(job) else {
+            return Ok(None);
+        };
+        let started_at = Utc::now().to_rfc3339();
+        let result = self.execute(job).await;
+        let (outcome, detail) = match result {
+            Ok(detail) => (JobOutcome::Succeeded, detail),
+            Err(e) => {
+                println!("❌ Job {} failed: {}", job.as_str(), e);
+                (JobOutcome::Failed, e.to_string())
+            }
+        };
+        let run = JobRun {
+            seq: 0,
+            job,
+            trigger,
+            started_at,
+            finished_at: Utc::now().to_rfc3339(),
+            outcome,
+            detail,
+        };
+        Ok(Some(self.db.record_job_run(run, self.config.jobs.history_limit)?))
+    }
+
+    /// The report of the run as JSON.
+    async fn execute(&self, job: JobKind) -> Result<String, Box<dyn Error>> {
+        let detail = match job {
+            JobKind::SheetImport => {
+                let report = self.db.sync_sheet(
+                    &self.sheets,
+                    &self.config.sheets,
+                    &self.matchers,
+                    None
+                ).await?;
+                serde_json::to_string(&report)?
+            }
+            JobKind::OutboxFlush => {
+                let report = flush_outbox(&self.db, &self.sheets, &self.matchers, &self.config).await?;
+                serde_json::to_string(&report)?
+            }
+            JobKind::LinnworksReconcile => {
+                let report = reconcile_pending(
+                    &self.db,
+                    &self.linnworks,
+                    &self.matchers,
+                    &self.config.matching,
+                    self.config.jobs.reconcile_batch_size
+                ).await?;
+                serde_json::to_string(&report)?
+            }
+        };
+        Ok(detail)
+    }
+
+    pub fn status(&self) -> Result<Vec<JobStatus>, Box<dyn Error>> {
+        let slots = self.slots.lock().map_err(|e| e.to_string())?;
+        let mut jobs = Vec::new();
+        for job in JobKind::ALL {
+            let slot = slots.get(&job);
+            jobs.push(JobStatus {
+                job,
+                interval_secs: self.interval(job),
+                running: slot.is_some_and(|s| s.running),
+                last_run: self.db.last_job_run(job)?,
+                next_run_at: slot
+                    .and_then(|s| s.next_run_at)
+                    .and_then(|at| DateTime::from_timestamp(at, 0))
+                    .map(|at| at.to_rfc3339()),
+            });
+        }
+        Ok(jobs)
+    }
+
+    /// Spawn a loop for every job with a non-zero interval. Each job runs once at startup.
+    pub fn start(&self) {
+        for job in JobKind::ALL {
+            let interval = self.interval(job);
+            if interval == 0 {
+                println!("⏸️ Job {} is not scheduled", job.as_str());
+                continue;
+            }
+            let scheduler = self.clone();
+            actix_web::rt::spawn(async move { scheduler.run_every(job, interval).await });
+        }
+    }
+
+    async fn run_every(&self, job: JobKind, interval: u64) {
+        loop {
+            match self.run(job, JobTrigger::Schedule).await {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    tokio::time::sleep(BUSY_RETRY).await;
+                    continue;
+                }
+                Err(e) => println!("❌ Job {} could not be recorded: {}", job.as_str(), e),
+            }
+            let next = Utc::now().timestamp() + (interval as i64);
+            if let Ok(mut slots) = self.slots.lock() {
+                slots.entry(job).or_default().next_run_at = Some(next);
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::utils::tests::temp_db,
+        scripts::{ fake_sheets::{ tests::client, FakeSheets }, http::HttpClient, sheets::GoogleSheetsClient },
+    };
+
+    async fn scheduler(dir: &tempfile::TempDir, base_url: &str) -> JobScheduler<GoogleSheetsClient> {
+        let mut config = AppConfig::default();
+        config.sheets.spreadsheet_id = "sheet".to_string();
+        let http = HttpClient::new(&config.http).unwrap();
+        let linnworks = LinnworksSession::new(http, config.linnworks.clone());
+        JobScheduler::new(temp_db(dir).await, client(base_url), linnworks, MatcherRegistry::with_builtin(), config)
+    }
+
+    #[tokio::test]
+    async fn sheet_writers_exclude_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = scheduler(&dir, "http://127.0.0.1:9").await;
+        for held in JobKind::ALL {
+            let _guard = scheduler.try_claim(held).unwrap();
+            for job in JobKind::ALL {
+                let overlaps = job == held || held.excludes().contains(&job);
+                assert_eq!(
+                    scheduler.try_claim(job).is_none(),
+                    overlaps,
+                    "{} while {} runs",
+                    job.as_str(),
+                    held.as_str()
+                );
+            }
+        }
+        assert!(!held_jobs(&scheduler));
+        let import = scheduler.try_claim(JobKind::SheetImport);
+        assert!(scheduler.try_claim(JobKind::OutboxFlush).is_none());
+        assert!(scheduler.try_claim(JobKind::LinnworksReconcile).is_some());
+        drop(import);
+        assert!(scheduler.try_claim(JobKind::OutboxFlush).is_some());
+    }
+
+    fn held_jobs(scheduler: &JobScheduler<GoogleSheetsClient>) -> bool {
+        scheduler.status().unwrap().iter().any(|status| status.running)
+    }
+
+    #[tokio::test]
+    async fn an_overlapping_run_is_skipped_and_not_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = scheduler(&dir, "http://127.0.0.1:9").await;
+        let _guard = scheduler.try_claim(JobKind::OutboxFlush).unwrap();
+        assert_eq!(scheduler.run(JobKind::SheetImport, JobTrigger::Manual).await.unwrap(), None);
+        assert_eq!(scheduler.db.last_job_run(JobKind::SheetImport).unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn a_busy_scheduled_run_is_retried_once_the_overlap_ends() {
+        let fake = FakeSheets::default();
+        fake.seed("sheet", "Sheet1", vec![vec!["ORDER ID".to_string(), "ROW ID".to_string()]]);
+        let base_url = fake.start().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = scheduler(&dir, &base_url).await;
+        let guard = scheduler.try_claim(JobKind::OutboxFlush).unwrap();
+
+        let looping = scheduler.clone();
+        actix_web::rt::spawn(async move { looping.run_every(JobKind::SheetImport, 3600).await });
+        tokio::time::sleep(BUSY_RETRY / 2).await;
+        assert_eq!(scheduler.db.last_job_run(JobKind::SheetImport).unwrap(), None);
+
+        drop(guard);
+        tokio::time::sleep(BUSY_RETRY * 2).await;
+        let run = scheduler.db.last_job_run(JobKind::SheetImport).unwrap().unwrap();
+        assert_eq!((run.trigger, run.outcome), (JobTrigger::Schedule, JobOutcome::Succeeded));
+        let status = scheduler.status().unwrap();
+        assert!(status[0].next_run_at.is_some() && !status[0].running);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{ error::Error, sync::Arc };
+
+use reqwest::{ Response, StatusCode };
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{ config::settings::LinnworksConfig, scripts::http::{ Api, HttpClient } };
+
+/// Sessions are renewed this long before the TTL Linnworks gave them runs out.
+const REFRESH_MARGIN_SECS: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+struct AuthResponse {
+    #[serde(rename = "Token")]
+    token: String,
+    /// Base URL every further call of the session goes to
+    #[serde(rename = "Server")]
+    server: String,
+    /// Seconds the session stays valid
+    #[serde(rename = "TTL")]
+    ttl: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    token: String,
+    server: String,
+    /// Unix seconds
+    expires_at: i64,
+}
+
+/// An authorized Linnworks session, shared by every caller. Clones share the session.
+#[derive(Debug, Clone)]
+pub struct LinnworksSession {
+    http: HttpClient,
+    config: LinnworksConfig,
+    session: Arc<tokio::sync::Mutex<Option<Session>>>,
+}
+
+impl LinnworksSession {
+    pub fn new(http: HttpClient, config: LinnworksConfig) -> Self {
+        LinnworksSession { http, config, session: Arc::default() }
+    }
+
+    /// The cached session, authorizing first when there is none or it is about to expire.
+    /// Concurrent callers wait for a single authorization.
+    async fn session(&self) -> Result<Session, Box<dyn Error>> {
+        let mut session = self.session.lock().await;
+        let now = chrono::Utc::now().timestamp();
+        if let Some(current) = session.as_ref() && current.expires_at - now > REFRESH_MARGIN_SECS {
+            return Ok(current.clone());
+        }
+        let fresh = self.authorize(now).await?;
+        *session = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    async fn authorize(&self, now: i64) -> Result<Session, Box<dyn Error>> {
+        if !self.config.is_configured() {
+            return Err("Linnworks credentials are not configured".into());
+        }
+        let body =
+            json!({
+            "ApplicationId": self.config.app_id,
+            "ApplicationSecret": self.config.app_secret,
+            "Token": self.config.api_token,
+        });
+        let auth = self.http
+            .send(Api::Linnworks, |c| c.post(&self.config.auth_url).json(&body)).await?
+            .json::<AuthResponse>().await?;
+        println!("🔑 Linnworks session opened on {}", auth.server);
+        Ok(Session {
+            token: auth.token,
+            server: auth.server.trim_end_matches('/').to_string(),
+            expires_at: now + auth.ttl.unwrap_or(self.config.session_ttl_secs),
+        })
+    }
+
+    /// Drop the session if it is still `token`, so the next call authorizes again.
+    async fn invalidate(&self, token: &str) {
+        let mut session = self.session.lock().await;
+        if session.as_ref().is_some_and(|s| s.token == token) {
+            *session = None;
+        }
+    }
+
+    /// GET `path` (e.g. `/api/Orders/...`) on the session's server. A 401 means the session
+    /// was revoked or timed out early; it is renewed and the call made once more.
+    pub async fn get(&self, path: &str) -> Result<Response, Box<dyn Error>> {
+        let session = self.session().await?;
+        match self.get_with(&session, path).await {
+            Err(e) if e.status() == Some(StatusCode::UNAUTHORIZED) => {
+                println!("🔑 Linnworks session rejected, authorizing again");
+                self.invalidate(&session.token).await;
+                let session = self.session().await?;
+                Ok(self.get_with(&session, path).await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn get_with(
+        &self,
+        session: &Session,
+        path: &str
+    ) -> Result<Response, crate::scripts::http::HttpError> {
+        let url = format!("{}{}", session.server, path);
+        self.http.send(Api::Linnworks, |c| c.get(&url).header("Authorization", &session.token)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ net::TcpListener, sync::Mutex };
+
+    use actix_web::{ web, App, HttpRequest, HttpResponse, HttpServer, Responder };
+
+    use super::*;
+    use crate::config::settings::HttpConfig;
+
+    /// Stand-in for Linnworks: hands out numbered sessions on a server of its own and
+    /// answers 401 for sessions it has revoked.
+    #[derive(Debug, Default)]
+    struct FakeLinnworks {
+        ttl: Option<i64>,
+        issued: Mutex<usize>,
+        revoked: Mutex<Vec<String>>,
+        /// (path, Authorization header) of every call made to the session server
+        calls: Mutex<Vec<(String, String)>>,
+    }
+
+    async fn authorize(fake: web::Data<FakeLinnworks>, req: HttpRequest) -> impl Responder {
+        let mut issued = fake.issued.lock().unwrap();
+        *issued += 1;
+        let server = format!("http://{}/eu-server/", req.connection_info().host());
+        HttpResponse::Ok().json(json!({ "Token": format!("session-{}", issued), "Server": server, "TTL": fake.ttl }))
+    }
+
+    async fn api(fake: web::Data<FakeLinnworks>, req: HttpRequest) -> impl Responder {
+        let token = req.headers().get("Authorization").and_then(|v| v.to_str().ok()).unwrap_or_default();
+        fake.calls.lock().unwrap().push((req.path().to_string(), token.to_string()));
+        if fake.revoked.lock().unwrap().iter().any(|t| t == token) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        HttpResponse::Ok().json(json!([]))
+    }
+
+    fn start(fake: FakeLinnworks) -> (web::Data<FakeLinnworks>, LinnworksSession) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let fake = web::Data::new(fake);
+        let state = fake.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(state.clone())
+                .route("/auth", web::post().to(authorize))
+                .route("/eu-server/{tail:.*}", web::get().to(api))
+        })
+            .listen(listener)
+            .unwrap()
+            .workers(1)
+            .run();
+        actix_web::rt::spawn(server);
+        let config = LinnworksConfig {
+            app_id: "app".to_string(),
+            app_secret: "secret".to_string(),
+            api_token: "install-token".to_string(),
+            auth_url: format!("{}/auth", base_url),
+            ..LinnworksConfig::default()
+        };
+        (fake, LinnworksSession::new(HttpClient::new(&HttpConfig::default()).unwrap(), config))
+    }
+
+    #[actix_web::test]
+    async fn calls_go_to_the_server_named_at_authorization() {
+        let (fake, linnworks) = start(FakeLinnworks::default());
+        linnworks.get("/api/Orders/GetOpenOrders").await.unwrap();
+        assert_eq!(*fake.calls.lock().unwrap(), [
+            ("/eu-server/api/Orders/GetOpenOrders".to_string(), "session-1".to_string()),
+        ]);
+    }
+
+    #[actix_web::test]
+    async fn the_session_is_reused_until_its_ttl_less_the_margin() {
+        let (fake, linnworks) = start(FakeLinnworks { ttl: Some(REFRESH_MARGIN_SECS + 30), ..Default::default() });
+        linnworks.get("/api/a").await.unwrap();
+        linnworks.get("/api/b").await.unwrap();
+        assert_eq!(*fake.issued.lock().unwrap(), 1);
+
+        // a session inside the margin is as good as expired
+        let (fake, linnworks) = start(FakeLinnworks { ttl: Some(REFRESH_MARGIN_SECS), ..Default::default() });
+        linnworks.get("/api/a").await.unwrap();
+        linnworks.get("/api/b").await.unwrap();
+        assert_eq!(*fake.issued.lock().unwrap(), 2);
+    }
+
+    #[actix_web::test]
+    async fn a_rejected_session_is_renewed_once() {
+        let (fake, linnworks) = start(FakeLinnworks::default());
+        linnworks.get("/api/a").await.unwrap();
+        fake.revoked.lock().unwrap().push("session-1".to_string());
+
+        linnworks.get("/api/b").await.unwrap();
+        assert_eq!(*fake.issued.lock().unwrap(), 2);
+        let tokens: Vec<_> = fake.calls.lock().unwrap().iter().map(|(_, token)| token.clone()).collect();
+        assert_eq!(tokens, ["session-1", "session-1", "session-2"]);
+
+        // a renewed session that is refused as well is not retried again
+        fake.revoked.lock().unwrap().push("session-2".to_string());
+        fake.revoked.lock().unwrap().push("session-3".to_string());
+        let err = linnworks.get("/api/c").await.unwrap_err();
+        assert!(err.to_string().contains("401"), "{}", err);
+        assert_eq!(*fake.issued.lock().unwrap(), 3);
+        assert_eq!(fake.calls.lock().unwrap().len(), 5);
+    }
+}
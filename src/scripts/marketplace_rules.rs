@@ -0,0 +1,395 @@
+use std::{ path::Path, sync::Arc, time::{ Duration, SystemTime } };
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    schema::order_api::Orders,
+    scripts::marketplaces::{ MarketplaceData, MarketplaceMatcher, MatcherRegistry },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RulesError {
+    #[error("cannot read marketplace rules {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("cannot parse marketplace rules {path}: {source}")]
+    Parse {
+        path: String,
+        source: serde_json::Error,
+    },
+    #[error("invalid marketplace rules: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+}
+
+/// The rules file, see `marketplace_rules.example.json`.
+#[derive(Debug, Deserialize)]
+struct RulesFile {
+    rules: Vec<RuleDef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDef {
+    marketplace: String,
+    #[serde(default)]
+    priority: i32,
+    /// Every condition given must hold
+    #[serde(rename = "match")]
+    conditions: Conditions,
+    marketplace_id: FieldDef,
+    /// `default_shopify_id` when unset or when the field is empty
+    shopify_id: Option<FieldDef>,
+    #[serde(default = "default_shopify_id")]
+    default_shopify_id: String,
+}
+
+fn default_shopify_id() -> String {
+    "000".to_string()
+}
+
+/// Regexes tested against the Linnworks order.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Conditions {
+    source: Option<String>,
+    sub_source: Option<String>,
+    /// Any note matching is enough
+    notes: Option<String>,
+    /// Any property whose name and value both match is enough
+    extended_property: Option<PropertyDef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PropertyDef {
+    name: String,
+    #[serde(default)]
+    value: Option<String>,
+}
+
+/// Where a value is read from. With a `pattern` the first capture group, or the whole
+/// match when it has none, is taken from the first value it matches.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldDef {
+    from: Field,
+    /// Property name, for `extended_property`
+    name: Option<String>,
+    pattern: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    Source,
+    SubSource,
+    ReferenceNum,
+    SecondaryReference,
+    ExternalReferenceNum,
+    Notes,
+    ExtendedProperty,
+}
+
+struct PropertyMatch {
+    name: Regex,
+    value: Option<Regex>,
+}
+
+struct FieldRef {
+    from: Field,
+    name: Option<String>,
+    pattern: Option<Regex>,
+}
+
+impl FieldRef {
+    /// The candidate values of the field on `order`, notes and properties can have several.
+    fn values<'a>(&self, order: &'a Orders) -> Vec<&'a str> {
+        let info = &order.general_info;
+        match self.from {
+            Field::Source => vec![info.source.as_str()],
+            Field::SubSource => vec![info.sub_source.as_str()],
+            Field::ReferenceNum => vec![info.reference_num.as_str()],
+            Field::SecondaryReference => vec![info.secondary_reference.as_str()],
+            Field::ExternalReferenceNum => vec![info.external_reference_num.as_str()],
+            Field::Notes => order.notes.iter().map(|n| n.note.as_str()).collect(),
+            Field::ExtendedProperty => order.extended_properties
+                .iter()
+                .filter(|p| Some(&p.name) == self.name.as_ref())
+                .map(|p| p.value.as_str())
+                .collect(),
+        }
+    }
+
+    /// The trimmed value, `None` when the field is missing, empty or the pattern never matches.
+    fn extract(&self, order: &Orders) -> Option<String> {
+        self.values(order)
+            .into_iter()
+            .find_map(|value| {
+                let Some(pattern) = &self.pattern else {
+                    return Some(value);
+                };
+                let captures = pattern.captures(value)?;
+                captures.get(1).or(captures.get(0)).map(|m| m.as_str())
+            })
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+/// A marketplace detected by a rule from the rules file.
+pub struct RuleMatcher {
+    marketplace: String,
+    priority: i32,
+    source: Option<Regex>,
+    sub_source: Option<Regex>,
+    notes: Option<Regex>,
+    extended_property: Option<PropertyMatch>,
+    marketplace_id: FieldRef,
+    shopify_id: Option<FieldRef>,
+    default_shopify_id: String,
+}
+
+impl MarketplaceMatcher for RuleMatcher {
+    fn name(&self) -> &str {
+        &self.marketplace
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn extract(&self, order: &Orders) -> Option<MarketplaceData> {
+        let info = &order.general_info;
+        let matches =
+            self.source.as_ref().is_none_or(|re| re.is_match(&info.source)) &&
+            self.sub_source.as_ref().is_none_or(|re| re.is_match(&info.sub_source)) &&
+            self.notes.as_ref().is_none_or(|re| order.notes.iter().any(|n| re.is_match(&n.note))) &&
+            self.extended_property.as_ref().is_none_or(|property| {
+                order.extended_properties.iter().any(|p| {
+                    property.name.is_match(&p.name) &&
+                        property.value.as_ref().is_none_or(|re| re.is_match(&p.value))
+                })
+            });
+        if !matches {
+            return None;
+        }
+        let marketplace_id = self.marketplace_id.extract(order)?;
+        let shopify_id = self.shopify_id
+            .as_ref()
+            .and_then(|field| field.extract(order))
+            .unwrap_or_else(|| self.default_shopify_id.clone());
+        Some(MarketplaceData::new(order, &self.marketplace, marketplace_id, shopify_id))
+    }
+}
+
+/// Compiles the rule's regexes, pushing anything wrong onto `problems`.
+struct Compiler<'a> {
+    label: String,
+    problems: &'a mut Vec<String>,
+}
+
+impl Compiler<'_> {
+    fn regex(&mut self, what: &str, pattern: Option<&String>) -> Option<Regex> {
+        let pattern = pattern?;
+        match Regex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                // syntax errors point at the pattern over several lines
+                let reason = e.to_string().split_whitespace().collect::<Vec<_>>().join(" ");
+                self.problems.push(format!("{}: {} is not a valid regex: {}", self.label, what, reason));
+                None
+            }
+        }
+    }
+
+    fn field(&mut self, what: &str, def: &FieldDef) -> FieldRef {
+        if (def.from == Field::ExtendedProperty) != def.name.is_some() {
+            self.problems.push(
+                format!(
+                    "{}: {}.name is required with extended_property and only allowed there",
+                    self.label,
+                    what
+                )
+            );
+        }
+        FieldRef {
+            from: def.from,
+            name: def.name.clone(),
+            pattern: self.regex(&format!("{}.pattern", what), def.pattern.as_ref()),
+        }
+    }
+}
+
+impl RuleDef {
+    fn compile(&self, index: usize, problems: &mut Vec<String>) -> RuleMatcher {
+        let mut compiler = Compiler {
+            label: format!("rule {} ({})", index + 1, self.marketplace),
+            problems,
+        };
+        if self.marketplace.trim().is_empty() {
+            compiler.problems.push(format!("{}: marketplace is empty", compiler.label));
+        }
+        let conditions = &self.conditions;
+        if
+            conditions.source.is_none() &&
+            conditions.sub_source.is_none() &&
+            conditions.notes.is_none() &&
+            conditions.extended_property.is_none()
+        {
+            compiler.problems.push(format!("{}: match needs at least one condition", compiler.label));
+        }
+        let extended_property = conditions.extended_property.as_ref().and_then(|p| {
+            Some(PropertyMatch {
+                name: compiler.regex("match.extended_property.name", Some(&p.name))?,
+                value: compiler.regex("match.extended_property.value", p.value.as_ref()),
+            })
+        });
+        RuleMatcher {
+            marketplace: self.marketplace.trim().to_string(),
+            priority: self.priority,
+            source: compiler.regex("match.source", conditions.source.as_ref()),
+            sub_source: compiler.regex("match.sub_source", conditions.sub_source.as_ref()),
+            notes: compiler.regex("match.notes", conditions.notes.as_ref()),
+            extended_property,
+            marketplace_id: compiler.field("marketplace_id", &self.marketplace_id),
+            shopify_id: self.shopify_id.as_ref().map(|def| compiler.field("shopify_id", def)),
+            default_shopify_id: self.default_shopify_id.clone(),
+        }
+    }
+}
+
+/// Parse and validate the rules at `path`, reporting every problem at once.
+pub fn load_rules(path: &str) -> Result<Vec<RuleMatcher>, RulesError> {
+    let content = std::fs::read_to_string(path).map_err(|source| RulesError::Read {
+        path: path.to_string(),
+        source,
+    })?;
+    let file: RulesFile = serde_json::from_str(&content).map_err(|source| RulesError::Parse {
+        path: path.to_string(),
+        source,
+    })?;
+    let mut problems = Vec::new();
+    if file.rules.is_empty() {
+        problems.push("no rules defined".to_string());
+    }
+    let rules: Vec<RuleMatcher> = file.rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| rule.compile(index, &mut problems))
+        .collect();
+    if problems.is_empty() { Ok(rules) } else { Err(RulesError::Invalid(problems)) }
+}
+
+/// A registry holding the rules at `path`.
+pub fn registry_from_rules(path: &str) -> Result<MatcherRegistry, RulesError> {
+    let registry = MatcherRegistry::default();
+    registry.replace(into_matchers(load_rules(path)?));
+    Ok(registry)
+}
+
+fn into_matchers(rules: Vec<RuleMatcher>) -> Vec<Arc<dyn MarketplaceMatcher>> {
+    rules
+        .into_iter()
+        .map(|rule| Arc::new(rule) as Arc<dyn MarketplaceMatcher>)
+        .collect()
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(Path::new(path)).ok()?.modified().ok()
+}
+
+/// Reload the rules into `registry` whenever the file at `path` changes. Rules that fail
+/// validation are reported and the ones in use are kept.
+pub fn watch_rules(registry: MatcherRegistry, path: String, interval_secs: u64) {
+    // taken now, a change made before the task first runs is still picked up
+    let mut loaded = modified(&path);
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+            let current = modified(&path);
+            if current == loaded {
+                continue;
+            }
+            loaded = current;
+            match load_rules(&path) {
+                Ok(rules) => {
+                    println!("🔄 Reloaded {} marketplace rule(s) from {}", rules.len(), path);
+                    registry.replace(into_matchers(rules));
+                }
+                Err(e) => println!("❌ Keeping the current marketplace rules: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripts::marketplaces::tests::{ debenhams, matalan, secret_sales, unmatched };
+
+    #[test]
+    fn example_rules_detect_like_the_builtin_matchers() {
+        let rules = registry_from_rules(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/marketplace_rules.example.json")
+        ).unwrap();
+        let builtin = MatcherRegistry::with_builtin();
+        for order in [debenhams(), secret_sales(), matalan()] {
+            let expected = builtin.detect(&order).unwrap().unwrap();
+            let data = rules.detect(&order).unwrap().unwrap();
+            assert_eq!(
+                (&data.marketplace, &data.marketplace_id, &data.shopify_id),
+                (&expected.marketplace, &expected.marketplace_id, &expected.shopify_id)
+            );
+        }
+        assert!(rules.detect(&unmatched()).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_rules_report_every_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.json");
+        std::fs::write(
+            &path,
+            r#"{ "rules": [
+                { "marketplace": "", "match": {}, "marketplace_id": { "from": "notes", "pattern": "(" } },
+                { "marketplace": "Next", "match": { "source": "NEXT" },
+                  "marketplace_id": { "from": "extended_property" } }
+            ] }"#
+        ).unwrap();
+        let Err(RulesError::Invalid(problems)) = load_rules(path.to_str().unwrap()) else {
+            panic!("expected the rules to be rejected");
+        };
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[actix_web::test]
+    async fn a_changed_file_is_reloaded_and_a_broken_one_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.json");
+        let write = |marketplace: &str, age_secs: u64| {
+            let rule = format!(
+                r#"{{ "rules": [{{ "marketplace": "{}", "match": {{ "sub_source": "(?i)matalan" }},
+                    "marketplace_id": {{ "from": "reference_num" }} }}] }}"#,
+                marketplace
+            );
+            std::fs::write(&path, rule).unwrap();
+            // writes within the same second would otherwise look unchanged
+            let mtime = SystemTime::now() - Duration::from_secs(age_secs);
+            std::fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        };
+        write("Matalan", 60);
+        let registry = registry_from_rules(path.to_str().unwrap()).unwrap();
+        watch_rules(registry.clone(), path.to_str().unwrap().to_string(), 1);
+
+        write("Matalan Outlet", 30);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(registry.names(), ["Matalan Outlet"]);
+
+        write("", 0);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(registry.names(), ["Matalan Outlet"]);
+    }
+}
@@ -0,0 +1,299 @@
+use std::sync::{ Arc, RwLock };
+
+use serde::Serialize;
+
+use crate::schema::order_api::Orders;
+
+/// Note prefix Linnworks channel integrations put before the marketplace's own order id.
+const MARKETPLACE_ORDER_ID_NOTE: &str = "Marketplace Order ID -";
+
+/// What a marketplace knows about a Linnworks order.
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketplaceData {
+    pub linnwork_id: String,
+    pub marketplace: String,
+    pub marketplace_id: String,
+    pub shopify_id: String,
+    pub items: Vec<MarketplaceItem>,
+}
+
+/// One line of the Linnworks order, SKU as Linnworks has it.
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketplaceItem {
+    pub sku: String,
+    pub quantity: i32,
+}
+
+impl MarketplaceData {
+    pub fn new(order: &Orders, marketplace: &str, marketplace_id: String, shopify_id: String) -> Self {
+        MarketplaceData {
+            linnwork_id: order.num_order_id.to_string(),
+            marketplace: marketplace.to_string(),
+            marketplace_id,
+            shopify_id,
+            items: order.items
+                .iter()
+                .map(|item| MarketplaceItem { sku: item.sku.clone(), quantity: item.quantity })
+                .collect(),
+        }
+    }
+}
+
+/// Recognises the orders of one marketplace.
+pub trait MarketplaceMatcher: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// When several matchers recognise an order the highest priority wins. Specific
+    /// matchers should rank above generic ones they overlap with.
+    fn priority(&self) -> i32;
+
+    /// The marketplace details, `None` when the order isn't from this marketplace.
+    fn extract(&self, order: &Orders) -> Option<MarketplaceData>;
+}
+
+/// More than one matcher of the same priority recognised the order, surfaced as 409 by the routes.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("Linnworks order {order_id} matches several marketplaces: {}", .marketplaces.join(", "))]
+pub struct AmbiguousMarketplace {
+    pub order_id: String,
+    pub marketplaces: Vec<String>,
+}
+
+/// Marketplace order id written in a note after `Marketplace Order ID -`.
+fn note_order_id(order: &Orders, note_marker: &str) -> Option<String> {
+    let note = order.notes.iter().find(|note| note.note.contains(note_marker))?;
+    Some(note.note.split(MARKETPLACE_ORDER_ID_NOTE).nth(1)?.trim().to_string())
+}
+
+/// `000` stands in for orders without a Shopify reference.
+fn shopify_reference(order: &Orders) -> String {
+    let reference = order.general_info.reference_num.trim();
+    if reference.is_empty() { "000".to_string() } else { reference.to_string() }
+}
+
+/// Debenhams orders carry a note mentioning DUX, which also holds the generic
+/// marketplace order id note, so this outranks `SecretSales`.
+pub struct Debenhams;
+
+impl MarketplaceMatcher for Debenhams {
+    fn name(&self) -> &str {
+        "Debenhams"
+    }
+
+    fn priority(&self) -> i32 {
+        20
+    }
+
+    fn extract(&self, order: &Orders) -> Option<MarketplaceData> {
+        let marketplace_id = note_order_id(order, "DUX")?;
+        Some(MarketplaceData::new(order, self.name(), marketplace_id, shopify_reference(order)))
+    }
+}
+
+/// Any order with a marketplace order id note not claimed by a more specific matcher.
+pub struct SecretSales;
+
+impl MarketplaceMatcher for SecretSales {
+    fn name(&self) -> &str {
+        "Secret Sales"
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+
+    fn extract(&self, order: &Orders) -> Option<MarketplaceData> {
+        let marketplace_id = note_order_id(order, MARKETPLACE_ORDER_ID_NOTE)?;
+        Some(MarketplaceData::new(order, self.name(), marketplace_id, shopify_reference(order)))
+    }
+}
+
+/// Matalan orders come through Mirakl with the Matalan order id as reference.
+pub struct Matalan;
+
+impl MarketplaceMatcher for Matalan {
+    fn name(&self) -> &str {
+        "Matalan"
+    }
+
+    fn priority(&self) -> i32 {
+        20
+    }
+
+    fn extract(&self, order: &Orders) -> Option<MarketplaceData> {
+        if order.general_info.sub_source.trim().to_lowercase() != "mirakl matalan" {
+            return None;
+        }
+        let marketplace_id = order.general_info.reference_num.clone();
+        Some(MarketplaceData::new(order, self.name(), marketplace_id, "000".to_string()))
+    }
+}
+
+/// The marketplaces Linnworks orders are matched against, highest priority first.
+/// Clones share the matchers, so a reload is seen everywhere.
+#[derive(Clone, Default)]
+pub struct MatcherRegistry {
+    matchers: Arc<RwLock<Vec<Arc<dyn MarketplaceMatcher>>>>,
+}
+
+impl MatcherRegistry {
+    /// Debenhams, Secret Sales and Matalan.
+    pub fn with_builtin() -> Self {
+        let registry = MatcherRegistry::default();
+        registry.register(Debenhams);
+        registry.register(SecretSales);
+        registry.register(Matalan);
+        registry
+    }
+
+    pub fn register(&self, matcher: impl MarketplaceMatcher + 'static) {
+        let mut matchers = self.matchers.write().unwrap();
+        matchers.push(Arc::new(matcher));
+        matchers.sort_by_key(|m| std::cmp::Reverse(m.priority()));
+    }
+
+    /// Swap in a whole new set of matchers.
+    pub fn replace(&self, mut matchers: Vec<Arc<dyn MarketplaceMatcher>>) {
+        matchers.sort_by_key(|m| std::cmp::Reverse(m.priority()));
+        *self.matchers.write().unwrap() = matchers;
+    }
+
+    /// Marketplace names the matchers recognise, highest priority first.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for matcher in self.matchers.read().unwrap().iter() {
+            if !names.iter().any(|n| n == matcher.name()) {
+                names.push(matcher.name().to_string());
+            }
+        }
+        names
+    }
+
+    /// The marketplace of `order`, `None` when no matcher recognises it. Lower priority
+    /// matches are dropped, a tie at the top between different marketplaces is an error.
+    pub fn detect(&self, order: &Orders) -> Result<Option<MarketplaceData>, AmbiguousMarketplace> {
+        let matchers = self.matchers.read().unwrap().clone();
+        let mut matches = matchers
+            .iter()
+            .filter_map(|m| m.extract(order).map(|data| (m.priority(), data)));
+        let Some((top, best)) = matches.next() else {
+            return Ok(None);
+        };
+        let rest: Vec<(i32, MarketplaceData)> = matches.collect();
+        let tied: Vec<String> = rest
+            .iter()
+            .filter(|(priority, data)| *priority == top && data.marketplace != best.marketplace)
+            .map(|(_, data)| data.marketplace.clone())
+            .collect();
+        if !tied.is_empty() {
+            return Err(AmbiguousMarketplace {
+                order_id: best.linnwork_id.clone(),
+                marketplaces: std::iter::once(best.marketplace).chain(tied).collect(),
+            });
+        }
+        for (_, data) in &rest {
+            println!(
+                "ℹ️ {} takes precedence over {} for order {}",
+                best.marketplace,
+                data.marketplace,
+                best.linnwork_id
+            );
+        }
+        Ok(Some(best))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The single order of a `GetOrdersById` response saved under `tests/fixtures/linnworks`.
+    fn fixture(json: &str) -> Orders {
+        let mut orders: Vec<Orders> = serde_json::from_str(json).unwrap();
+        assert_eq!(orders.len(), 1);
+        orders.remove(0)
+    }
+
+    pub(crate) fn debenhams() -> Orders {
+        fixture(include_str!("../../tests/fixtures/linnworks/debenhams.json"))
+    }
+
+    pub(crate) fn secret_sales() -> Orders {
+        fixture(include_str!("../../tests/fixtures/linnworks/secret_sales.json"))
+    }
+
+    pub(crate) fn matalan() -> Orders {
+        fixture(include_str!("../../tests/fixtures/linnworks/matalan.json"))
+    }
+
+    pub(crate) fn unmatched() -> Orders {
+        fixture(include_str!("../../tests/fixtures/linnworks/unmatched.json"))
+    }
+
+    /// Claims every Mirakl order at the same priority as `Matalan`.
+    struct AnyMirakl;
+
+    impl MarketplaceMatcher for AnyMirakl {
+        fn name(&self) -> &str {
+            "Mirakl"
+        }
+
+        fn priority(&self) -> i32 {
+            20
+        }
+
+        fn extract(&self, order: &Orders) -> Option<MarketplaceData> {
+            (order.general_info.source == "MIRAKL").then(|| {
+                MarketplaceData::new(order, self.name(), order.general_info.reference_num.clone(), "000".to_string())
+            })
+        }
+    }
+
+    #[test]
+    fn debenhams_note_outranks_the_generic_secret_sales_note() {
+        let order = debenhams();
+        assert!(SecretSales.extract(&order).is_some(), "the fixture should overlap with Secret Sales");
+
+        let data = MatcherRegistry::with_builtin().detect(&order).unwrap().unwrap();
+        assert_eq!(data.marketplace, "Debenhams");
+        assert_eq!(data.marketplace_id, "DB-7781234");
+        assert_eq!(data.shopify_id, "#SH10452");
+        assert_eq!(data.linnwork_id, "104522");
+        let skus: Vec<(&str, i32)> = data.items.iter().map(|i| (i.sku.as_str(), i.quantity)).collect();
+        assert_eq!(skus, [("TSHIRT-RED-M", 1), ("SOCKS-3PK", 2)]);
+    }
+
+    #[test]
+    fn secret_sales_from_the_marketplace_order_id_note() {
+        let data = MatcherRegistry::with_builtin().detect(&secret_sales()).unwrap().unwrap();
+        assert_eq!(data.marketplace, "Secret Sales");
+        assert_eq!(data.marketplace_id, "SS-556677");
+        assert_eq!(data.shopify_id, "000");
+    }
+
+    #[test]
+    fn matalan_from_the_sub_source() {
+        let data = MatcherRegistry::with_builtin().detect(&matalan()).unwrap().unwrap();
+        assert_eq!(data.marketplace, "Matalan");
+        assert_eq!(data.marketplace_id, "MAT-2024-88120");
+        assert_eq!(data.shopify_id, "000");
+    }
+
+    #[test]
+    fn priority_tie_is_ambiguous() {
+        let registry = MatcherRegistry::with_builtin();
+        registry.register(AnyMirakl);
+
+        let err = registry.detect(&matalan()).unwrap_err();
+        assert_eq!(err.order_id, "104541");
+        let mut marketplaces = err.marketplaces.clone();
+        marketplaces.sort();
+        assert_eq!(marketplaces, ["Matalan", "Mirakl"]);
+    }
+
+    #[test]
+    fn unrecognised_order_is_none() {
+        assert!(MatcherRegistry::with_builtin().detect(&unmatched()).unwrap().is_none());
+        assert!(MatcherRegistry::default().detect(&debenhams()).unwrap().is_none());
+    }
+}
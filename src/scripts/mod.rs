@@ -1,3 +1,14 @@
 pub mod order;
 pub mod utils;
-pub mod update_fixed;
\ No newline at end of file
+pub mod update_fixed;
+pub mod sheets;
+pub mod fake_sheets;
+pub mod columns;
+pub mod outbox;
+pub mod jobs;
+pub mod http;
+pub mod auth;
+pub mod linnworks;
+pub mod marketplaces;
+pub mod marketplace_rules;
+pub mod sku_match;
@@ -1,126 +1,139 @@
 use std::error::Error;
-use reqwest::Client;
 use serde_json::{ json, Value };
 
+use crate::scripts::http::{ Api, HttpClient };
+
 pub async fn append_to_google_sheets(
+    client: &HttpClient,
+    base_url: &str,
     access_token: String,
     spreadsheet_id: &str,
     range: &str,
-    values: Vec<String>
+    values: Vec<Vec<String>>
 ) -> Result<(), Box<dyn Error>> {
     let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}:append?valueInputOption=USER_ENTERED",
+        "{}/v4/spreadsheets/{}/values/{}:append?valueInputOption=USER_ENTERED",
+        base_url,
         spreadsheet_id,
         range
     );
 
     let body = json!({
-        "values": [values]
+        "values": values
     });
 
-    let client = Client::new();
-    let res = client.post(&url).bearer_auth(access_token).json(&body).send().await?;
-
-    if res.status().is_success() {
-        println!("✅ Rows appended successfully");
-    } else {
-        println!("❌ Error: {}", res.text().await?);
-    }
+    // a repeated append would add the rows twice
+    client
+        .send_non_idempotent(Api::Sheets, |c| c.post(&url).bearer_auth(&access_token).json(&body))
+        .await?;
 
+    println!("✅ Rows appended successfully");
     Ok(())
 }
 
 pub async fn update_order_in_sheets(
+    client: &HttpClient,
+    base_url: &str,
     access_token: String,
     sheet_id: &str,
-    _sheet1_range: &str,
+    tab_range: &str,
     row_number: usize,
-    // sheet2_range: &str,
-    sheet1_values: Vec<Vec<String>>
-    // sheet2_values: Vec<Vec<String>>,
+    values: Vec<Option<String>>
 ) -> Result<(), Box<dyn Error>> {
-    let client = Client::new();
     let row_number = row_number + 1; // Google Sheets 1-based index
-    let range = format!("Sheet1!A{}:Z{}", row_number, row_number);
-    // Sheet1 update
+    // only the tab part of the range matters, the row is always rewritten as A:Z
+    let tab = tab_range.split('!').next().unwrap_or(tab_range);
+    let range = format!("{}!A{}:Z{}", tab, row_number, row_number);
     let url1 = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}?valueInputOption=USER_ENTERED",
+        "{}/v4/spreadsheets/{}/values/{}?valueInputOption=USER_ENTERED",
+        base_url,
         sheet_id,
         range
     );
-    println!("Updating Sheet1 at range: {}", range);
-    client
-        .put(&url1)
-        .bearer_auth(access_token)
-        .json(&json!({ "values": sheet1_values }))
-        .send().await?
-        .error_for_status()?; // Agar error aaya to throw karega
-
-    // Sheet2 update
-    // let url2 = format!(
-    //     "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}?valueInputOption=USER_ENTERED",
-    //     sheet_id, sheet2_range
-    // );
-
-    // client
-    //     .put(&url2)
-    //     .bearer_auth(access_token)
-    //     .json(&json!({ "values": sheet2_values }))
-    //     .send()
-    //     .await?
-    //     .error_for_status()?;
+    println!("Updating sheet at range: {}", range);
+    // null cells are skipped by the API, so unowned columns keep their content
+    let body = json!({ "values": [values] });
+    client.send(Api::Sheets, |c| c.put(&url1).bearer_auth(&access_token).json(&body)).await?;
 
-    println!("✅ Order updated successfully in both sheets");
+    println!("✅ Sheet row updated");
     Ok(())
 }
 
-pub async fn _delete_order_in_sheets(
+pub async fn clear_sheet_row(
+    client: &HttpClient,
+    base_url: &str,
     access_token: &str,
     sheet_id: &str,
     sheet1_range: &str,
-    sheet2_range: &str
+    row_number: usize
 ) -> Result<(), Box<dyn Error>> {
-    let client = Client::new();
-
-    // Sheet1 delete (clear)
-    let url1 = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}:clear",
-        sheet_id,
-        sheet1_range
-    );
-
-    client.post(&url1).bearer_auth(access_token).send().await?.error_for_status()?;
-
-    // Sheet2 delete (clear)
-    let url2 = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}:clear",
-        sheet_id,
-        sheet2_range
-    );
+    let row_number = row_number + 1; // Google Sheets 1-based index
+    let tab = sheet1_range.split('!').next().unwrap_or(sheet1_range);
+    let range = format!("{}!A{}:Z{}", tab, row_number, row_number);
+    let url = format!("{}/v4/spreadsheets/{}/values/{}:clear", base_url, sheet_id, range);
 
-    client.post(&url2).bearer_auth(access_token).send().await?.error_for_status()?;
+    client.send(Api::Sheets, |c| c.post(&url).bearer_auth(access_token)).await?;
 
-    println!("🗑️ Order deleted from both sheets");
+    println!("🗑️ Cleared sheet row {}", range);
     Ok(())
 }
 
 pub async fn fetch_sheet_data(
+    client: &HttpClient,
+    base_url: &str,
     access_token: &str,
     spreadsheet_id: &str,
     sheet_name: &str
 ) -> Result<Value, Box<dyn Error>> {
     let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}",
+        "{}/v4/spreadsheets/{}/values/{}",
+        base_url,
         spreadsheet_id,
         sheet_name
     );
 
-    let response = Client::new().get(&url).bearer_auth(access_token).send().await?;
-
-    if !response.status().is_success() {
-        return Err(format!("API request failed: {}", response.text().await?).into());
-    }
-
+    let response = client.send(Api::Sheets, |c| c.get(&url).bearer_auth(access_token)).await?;
     Ok(response.json::<Value>().await?)
 }
 
+
+/// Hide one column of `tab`. Needs the numeric sheet id, which is looked up by tab title.
+pub async fn hide_sheet_column(
+    client: &HttpClient,
+    base_url: &str,
+    access_token: &str,
+    spreadsheet_id: &str,
+    tab: &str,
+    column: usize
+) -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/v4/spreadsheets/{}?fields=sheets.properties", base_url, spreadsheet_id);
+    let meta = client
+        .send(Api::Sheets, |c| c.get(&url).bearer_auth(access_token)).await?
+        .json::<Value>().await?;
+    let sheet_id = meta["sheets"]
+        .as_array()
+        .and_then(|sheets| {
+            sheets.iter().find(|s| s["properties"]["title"].as_str() == Some(tab))
+        })
+        .and_then(|s| s["properties"]["sheetId"].as_i64())
+        .ok_or_else(|| format!("tab {} not found in spreadsheet {}", tab, spreadsheet_id))?;
+
+    let url = format!("{}/v4/spreadsheets/{}:batchUpdate", base_url, spreadsheet_id);
+    let body =
+        json!({
+        "requests": [{
+            "updateDimensionProperties": {
+                "range": {
+                    "sheetId": sheet_id,
+                    "dimension": "COLUMNS",
+                    "startIndex": column,
+                    "endIndex": column + 1
+                },
+                "properties": { "hiddenByUser": true },
+                "fields": "hiddenByUser"
+            }
+        }]
+    });
+    client.send(Api::Sheets, |c| c.post(&url).bearer_auth(access_token).json(&body)).await?;
+    Ok(())
+}
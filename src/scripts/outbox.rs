@@ -0,0 +1,202 @@
+use std::{ collections::HashSet, error::Error };
+
+use crate::{
+    config::settings::{ AppConfig, OutboxConfig },
+    lmdb::{ order::DBOrder, outbox::DBOutbox, sync::DBSync, utils::DB },
+    schema::outbox::{ FlushReport, OutboxEntry, SheetOp },
+    scripts::{
+        columns::{ HeaderMap, SHEET1_COLUMNS, SHEET2_COLUMNS },
+        marketplaces::MatcherRegistry,
+        sheets::{ rows_from_value, SheetsClient },
+    },
+};
+
+/// Delay before the next attempt after `attempts` failures, `None` once they are used up.
+fn retry_delay(config: &OutboxConfig, attempts: u32) -> Option<u64> {
+    if attempts >= config.max_attempts {
+        return None;
+    }
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    Some(config.base_backoff_secs.saturating_mul(factor).min(config.max_backoff_secs))
+}
+
+async fn deliver<S: SheetsClient>(
+    db: &DB,
+    sheets: &S,
+    matchers: &MatcherRegistry,
+    config: &AppConfig,
+    op: &SheetOp
+) -> Result<(), Box<dyn Error>> {
+    let sheet = &config.sheets;
+    match op {
+        SheetOp::AppendOrder { id } => {
+            // deleted since it was queued
+            let Some(order) = db.get_single(id.clone())? else {
+                return Ok(());
+            };
+            let value = sheets.fetch(&sheet.spreadsheet_id, &sheet.orders_range()).await?;
+            let rows = rows_from_value(&value);
+            let header = HeaderMap::resolve(
+                SHEET1_COLUMNS,
+                rows.first().map(|r| r.as_slice()).unwrap_or_default()
+            )?;
+            // an earlier attempt may have landed without us hearing back
+            if header.find_row(&rows, &order.id, &order.order_id).is_none() {
+                let row = order.to_sheet1_row(&header);
+                sheets.append(&sheet.spreadsheet_id, &sheet.orders_range(), vec![row]).await?;
+            }
+            // records the baseline and adds the reconciliation row
+            sync_order(db, sheets, matchers, config, &order.order_id).await
+        }
+        SheetOp::ClearRow { id, order_id } => {
+            let value = sheets.fetch(&sheet.spreadsheet_id, &sheet.orders_range()).await?;
+            let rows = rows_from_value(&value);
+            let header = HeaderMap::resolve(
+                SHEET1_COLUMNS,
+                rows.first().map(|r| r.as_slice()).unwrap_or_default()
+            )?;
+            // never written, or cleared by an earlier attempt
+            if let Some(row_number) = header.find_row(&rows, id, order_id) {
+                sheets.clear_row(&sheet.spreadsheet_id, &sheet.orders_range(), row_number).await?;
+            }
+            // the reconciliation row is shared by order id, keep it while the id is in use
+            if db.get_single(order_id.clone())?.is_some() {
+                return Ok(());
+            }
+            let spreadsheet_id = sheet.reconciliation_spreadsheet();
+            let value = sheets.fetch(spreadsheet_id, &sheet.reconciliation_range()).await?;
+            let rows = rows_from_value(&value);
+            let Ok(header) = HeaderMap::resolve(
+                SHEET2_COLUMNS,
+                rows.first().map(|r| r.as_slice()).unwrap_or_default()
+            ) else {
+                return Ok(());
+            };
+            if let Some(row_number) = header.find_order_row(&rows, order_id) {
+                sheets.clear_row(spreadsheet_id, &sheet.reconciliation_range(), row_number).await?;
+            }
+            Ok(())
+        }
+        SheetOp::SyncOrder { order_id, .. } => {
+            sync_order(db, sheets, matchers, config, order_id).await
+        }
+    }
+}
+
+async fn sync_order<S: SheetsClient>(
+    db: &DB,
+    sheets: &S,
+    matchers: &MatcherRegistry,
+    config: &AppConfig,
+    order_id: &str
+) -> Result<(), Box<dyn Error>> {
+    let report = db.sync_sheet(sheets, &config.sheets, matchers, Some(order_id)).await?;
+    if report.failed_rows > 0 {
+        return Err(format!("sheet rows of order {} could not be written", order_id).into());
+    }
+    // the row is left alone until someone fixes it in the sheet
+    if let Some(rejected) = report.rejected.first() {
+        let reasons: Vec<String> = rejected.errors.iter().map(|e| e.to_string()).collect();
+        let message = format!(
+            "sheet row {} of order {} is invalid: {}",
+            rejected.row_number,
+            order_id,
+            reasons.join(", ")
+        );
+        return Err(message.into());
+    }
+    Ok(())
+}
+
+/// Deliver the entries that are due. Entries are handled in queue order and a failure
+/// holds back later entries for the same order, so an update never overtakes its append.
+pub async fn flush_outbox<S: SheetsClient>(
+    db: &DB,
+    sheets: &S,
+    matchers: &MatcherRegistry,
+    config: &AppConfig
+) -> Result<FlushReport, Box<dyn Error>> {
+    let now = chrono::Utc::now().timestamp();
+    let entries: Vec<OutboxEntry> = db.due_entries(now, config.outbox.batch_size)?;
+    let mut report = FlushReport::default();
+    let mut blocked = HashSet::new();
+    for entry in entries {
+        let key = entry.op.order_key().to_string();
+        if blocked.contains(&key) {
+            continue;
+        }
+        match deliver(db, sheets, matchers, config, &entry.op).await {
+            Ok(_) => {
+                db.complete_entry(entry.seq)?;
+                report.delivered += 1;
+            }
+            Err(e) => {
+                blocked.insert(key);
+                let retry_at = retry_delay(&config.outbox, entry.attempts + 1).map(
+                    |delay| now + (delay as i64)
+                );
+                println!("❌ Sheet write {} failed (attempt {}): {}", entry.seq, entry.attempts + 1, e);
+                if db.fail_entry(entry.seq, e.to_string(), retry_at)? {
+                    println!("☠️ Sheet write {} moved to dead letters", entry.seq);
+                    report.dead_lettered += 1;
+                } else {
+                    report.retried += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::{ utils::tests::temp_db, versioned::tests::expected_v1 },
+        schema::{ history::{ ChangeContext, ChangeSource }, order::Order },
+        scripts::{ columns::ROW_ID_HEADER, fake_sheets::{ tests::client, FakeSheets } },
+    };
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        let config = OutboxConfig {
+            max_attempts: 6,
+            base_backoff_secs: 5,
+            max_backoff_secs: 30,
+            ..OutboxConfig::default()
+        };
+        let delays: Vec<Option<u64>> = (1..=6).map(|attempts| retry_delay(&config, attempts)).collect();
+        assert_eq!(delays, [Some(5), Some(10), Some(20), Some(30), Some(30), None]);
+    }
+
+    #[actix_web::test]
+    async fn queued_writes_reach_the_sheet_in_order() {
+        let fake = FakeSheets::default();
+        let cells = |cells: &[&str]| cells.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        fake.seed("sheet", "Sheet1", vec![cells(&["ORDER ID", "RETURNED SKU", "DATE", "MARKETPLACE", ROW_ID_HEADER])]);
+        let base_url = fake.start().unwrap();
+        let sheets = client(&base_url);
+        let mut config = AppConfig::default();
+        config.sheets.spreadsheet_id = "sheet".to_string();
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        let ctx = ChangeContext { claimed_actor: "test".to_string(), source: ChangeSource::Http };
+
+        let order = Order { row_number: None, ..expected_v1() };
+        db.insert(order.clone(), &ctx).unwrap();
+        db.put(Order { returned_sku: Some("TSHIRT-RED-L".to_string()), ..order.clone() }, &ctx).unwrap();
+        assert_eq!(db.pending().unwrap().len(), 2);
+
+        let report = flush_outbox(&db, &sheets, &MatcherRegistry::with_builtin(), &config).await.unwrap();
+        assert_eq!(report, FlushReport { delivered: 2, retried: 0, dead_lettered: 0 });
+        assert!(db.pending().unwrap().is_empty());
+        assert_eq!(fake.rows("sheet", "Sheet1")[1], cells(&["104522", "TSHIRT-RED-L", &order.date, &order.marketplace, &order.id]));
+
+        // a replayed append finds the row already there
+        let mut txn = db.env.write_txn().unwrap();
+        db.enqueue_sheet_op(&mut txn, SheetOp::AppendOrder { id: order.id.clone() }).unwrap();
+        txn.commit().unwrap();
+        flush_outbox(&db, &sheets, &MatcherRegistry::with_builtin(), &config).await.unwrap();
+        assert_eq!(fake.rows("sheet", "Sheet1").len(), 2);
+    }
+}
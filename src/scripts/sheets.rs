@@ -0,0 +1,166 @@
+use std::error::Error;
+
+use serde_json::Value;
+
+use crate::scripts::{
+    auth::{ ServiceAccount, TokenProvider, SHEETS_SCOPE },
+    http::HttpClient,
+    order::{
+        append_to_google_sheets,
+        clear_sheet_row,
+        fetch_sheet_data,
+        hide_sheet_column,
+        update_order_in_sheets,
+    },
+};
+
+pub const GOOGLE_SHEETS_BASE_URL: &str = "https://sheets.googleapis.com";
+pub const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+/// Everything the service does with a spreadsheet, so the Google API can be
+/// swapped for the in-process fake in `fake_sheets`.
+#[allow(dead_code)]
+pub trait SheetsClient {
+    /// Raw `values` response for a range, e.g. `Sheet1!A:Z`.
+    async fn fetch(&self, spreadsheet_id: &str, range: &str) -> Result<Value, Box<dyn Error>>;
+    /// Add `rows` after the last row with content.
+    async fn append(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        rows: Vec<Vec<String>>
+    ) -> Result<(), Box<dyn Error>>;
+    /// Write sheet row `row_number` (0-based, header is row 0). `None` cells are left as they are.
+    async fn update_row(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        row_number: usize,
+        values: Vec<Option<String>>
+    ) -> Result<(), Box<dyn Error>>;
+    /// Empty every cell of row `row_number` (0-based). The row itself stays, so the
+    /// rows below keep their numbers.
+    async fn clear_row(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        row_number: usize
+    ) -> Result<(), Box<dyn Error>>;
+    /// Hide column `column` (0-based) of `tab` from people using the sheet.
+    async fn hide_column(
+        &self,
+        spreadsheet_id: &str,
+        tab: &str,
+        column: usize
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Google Sheets over the shared `HttpClient`.
+#[derive(Debug, Clone)]
+pub struct GoogleSheetsClient {
+    http: HttpClient,
+    base_url: String,
+    tokens: TokenProvider,
+    /// `None` skips OAuth, only useful against the fake server
+    credentials: Option<ServiceAccount>,
+}
+
+impl GoogleSheetsClient {
+    pub fn new(
+        http: HttpClient,
+        base_url: &str,
+        tokens: TokenProvider,
+        credentials: Option<ServiceAccount>
+    ) -> Self {
+        GoogleSheetsClient {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            tokens,
+            credentials,
+        }
+    }
+
+    async fn access_token(&self) -> Result<String, Box<dyn Error>> {
+        match &self.credentials {
+            Some(account) => self.tokens.token(account, SHEETS_SCOPE).await,
+            None => Ok("offline".to_string()),
+        }
+    }
+}
+
+impl SheetsClient for GoogleSheetsClient {
+    async fn fetch(&self, spreadsheet_id: &str, range: &str) -> Result<Value, Box<dyn Error>> {
+        let token = self.access_token().await?;
+        fetch_sheet_data(&self.http, &self.base_url, &token, spreadsheet_id, range).await
+    }
+
+    async fn append(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        rows: Vec<Vec<String>>
+    ) -> Result<(), Box<dyn Error>> {
+        let token = self.access_token().await?;
+        append_to_google_sheets(&self.http, &self.base_url, token, spreadsheet_id, range, rows).await
+    }
+
+    async fn update_row(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        row_number: usize,
+        values: Vec<Option<String>>
+    ) -> Result<(), Box<dyn Error>> {
+        let token = self.access_token().await?;
+        update_order_in_sheets(
+            &self.http,
+            &self.base_url,
+            token,
+            spreadsheet_id,
+            range,
+            row_number,
+            values
+        ).await
+    }
+
+    async fn clear_row(
+        &self,
+        spreadsheet_id: &str,
+        range: &str,
+        row_number: usize
+    ) -> Result<(), Box<dyn Error>> {
+        let token = self.access_token().await?;
+        clear_sheet_row(&self.http, &self.base_url, &token, spreadsheet_id, range, row_number).await
+    }
+
+    async fn hide_column(
+        &self,
+        spreadsheet_id: &str,
+        tab: &str,
+        column: usize
+    ) -> Result<(), Box<dyn Error>> {
+        let token = self.access_token().await?;
+        hide_sheet_column(&self.http, &self.base_url, &token, spreadsheet_id, tab, column).await
+    }
+}
+
+/// Converts a `values` response into rows of strings.
+pub fn rows_from_value(value: &Value) -> Vec<Vec<String>> {
+    value["values"]
+        .as_array()
+        .map(|rows| {
+            rows.iter()
+                .map(|row| {
+                    row.as_array()
+                        .map(|cells| {
+                            cells
+                                .iter()
+                                .map(|v| v.as_str().unwrap_or_default().to_string())
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::settings::MatchingConfig,
+    schema::catalog::{ compact_sku as compact, normalize_sku, SKU_SEPARATORS as SEPARATORS },
+    scripts::marketplaces::MarketplaceItem,
+};
+
+/// Confidence given to each way a returned SKU can resemble a line's SKU.
+const EXACT: u8 = 100;
+const NORMALIZED: u8 = 95;
+const SEPARATORS_IGNORED: u8 = 90;
+const ALIAS: u8 = 90;
+const SIZE_SUFFIX: u8 = 75;
+/// Confidence at one edit, lowered by `EDIT_PENALTY` for every further one.
+const EDIT_DISTANCE: u8 = 70;
+const EDIT_PENALTY: u8 = 10;
+/// Characters of the shorter SKU per edit allowed, so short SKUs like `S1` and `M2`,
+/// which differ in every character, are never taken for one another.
+const CHARS_PER_EDIT: usize = 4;
+
+const SIZES: [&str; 13] = ["xxs", "xs", "s", "m", "l", "xl", "xxl", "xxxl", "2xl", "3xl", "4xl", "os", "onesize"];
+
+/// The line a returned SKU was matched to and how sure the match is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkuMatch {
+    /// Index of the matched line, `None` when no line resembles the SKU closely enough
+    pub line: Option<usize>,
+    /// 0-100
+    pub confidence: u8,
+    pub explanation: String,
+}
+
+/// SKUs known to be the same product, each mapped to the SKU the product goes by.
+#[derive(Debug, Default)]
+pub struct AliasTable {
+    canonical: HashMap<String, String>,
+}
+
+impl AliasTable {
+    /// The `matching.sku_aliases` of the config.
+    pub fn from_config(config: &MatchingConfig) -> Self {
+        let mut table = AliasTable::default();
+        for (alias, sku) in &config.sku_aliases {
+            table.add(alias, sku);
+            table.add(sku, sku);
+        }
+        table
+    }
+
+    pub fn add(&mut self, sku: &str, canonical: &str) {
+        self.canonical.insert(compact(sku), canonical.trim().to_string());
+    }
+
+    fn canonical(&self, sku: &str) -> Option<&str> {
+        self.canonical.get(&compact(sku)).map(String::as_str)
+    }
+}
+
+/// `sku` without a trailing size like `-XL`, `_10` or ` UK8`, `None` when it has none.
+fn without_size(sku: &str) -> Option<&str> {
+    let (base, suffix) = sku.trim().rsplit_once(SEPARATORS)?;
+    let suffix = suffix.to_lowercase();
+    let numeric = |s: &str| (1..=3).contains(&s.len()) && s.chars().all(|c| c.is_ascii_digit());
+    let is_size =
+        SIZES.contains(&suffix.as_str()) ||
+        numeric(&suffix) ||
+        suffix.strip_prefix("uk").is_some_and(numeric);
+    (is_size && !base.trim().is_empty()).then_some(base)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// How sure it is that `line_sku` is the returned SKU, and why. `None` when they aren't alike.
+fn score(
+    returned: &str,
+    line_sku: &str,
+    aliases: &AliasTable,
+    config: &MatchingConfig
+) -> Option<(u8, String)> {
+    if returned.trim() == line_sku.trim() {
+        return Some((EXACT, "same SKU".to_string()));
+    }
+    if normalize_sku(returned) == normalize_sku(line_sku) {
+        return Some((NORMALIZED, "same SKU ignoring case and spaces".to_string()));
+    }
+    let (returned_compact, line_compact) = (compact(returned), compact(line_sku));
+    if returned_compact == line_compact {
+        return Some((SEPARATORS_IGNORED, "same SKU ignoring separators".to_string()));
+    }
+    if
+        let (Some(returned_sku), Some(sku)) = (aliases.canonical(returned), aliases.canonical(line_sku)) &&
+        compact(returned_sku) == compact(sku)
+    {
+        return Some((ALIAS, format!("both are known as {:?}", sku)));
+    }
+    // a trailing number may be part of the SKU itself, so try the bases against both forms
+    let returned_forms = [Some(returned), without_size(returned)];
+    let line_forms = [Some(line_sku), without_size(line_sku)];
+    let same_base = returned_forms
+        .iter()
+        .flatten()
+        .any(|r| line_forms.iter().flatten().any(|l| compact(r) == compact(l)));
+    if same_base {
+        return Some((SIZE_SUFFIX, "same SKU with a different size suffix".to_string()));
+    }
+    let shorter = returned_compact.chars().count().min(line_compact.chars().count());
+    let max_distance = config.max_edit_distance.min(shorter / CHARS_PER_EDIT);
+    let distance = edit_distance(&returned_compact, &line_compact);
+    if distance <= max_distance {
+        let penalty = EDIT_PENALTY.saturating_mul(u8::try_from(distance - 1).unwrap_or(u8::MAX));
+        return Some((
+            EDIT_DISTANCE.saturating_sub(penalty),
+            format!("{} character(s) apart", distance),
+        ));
+    }
+    None
+}
+
+/// The line of `items` that best matches `returned_sku`, among the lines whose SKU is held
+/// at least `qty` times across the order, as an order may list one SKU on several lines.
+/// The earliest line wins a tie.
+pub fn best_line(
+    returned_sku: &str,
+    qty: u32,
+    items: &[MarketplaceItem],
+    aliases: &AliasTable,
+    config: &MatchingConfig
+) -> SkuMatch {
+    let no_match = |explanation: String| SkuMatch { line: None, confidence: 0, explanation };
+    if returned_sku.trim().is_empty() {
+        return no_match("the order has no returned SKU".to_string());
+    }
+    if items.is_empty() {
+        return no_match("the Linnworks order has no lines".to_string());
+    }
+
+    // units of each line's SKU over every line of the order
+    let held: Vec<i64> = items
+        .iter()
+        .map(|item| {
+            items
+                .iter()
+                .filter(|other| compact(&other.sku) == compact(&item.sku))
+                .map(|other| i64::from(other.quantity))
+                .sum()
+        })
+        .collect();
+
+    let mut best: Option<(usize, u8, String)> = None;
+    let mut short: Option<(usize, u8)> = None;
+    for (i, item) in items.iter().enumerate() {
+        let Some((confidence, why)) = score(returned_sku, &item.sku, aliases, config) else {
+            continue;
+        };
+        if held[i] < i64::from(qty) {
+            if short.is_none_or(|(_, c)| confidence > c) {
+                short = Some((i, confidence));
+            }
+            continue;
+        }
+        if best.as_ref().is_none_or(|(_, c, _)| confidence > *c) {
+            best = Some((i, confidence, why));
+        }
+    }
+
+    match (best, short) {
+        (Some((line, confidence, why)), _) =>
+            SkuMatch {
+                line: Some(line),
+                confidence,
+                explanation: format!(
+                    "line {} of {} ({:?}): {}",
+                    line + 1,
+                    items.len(),
+                    items[line].sku,
+                    why
+                ),
+            },
+        (None, Some((line, _))) =>
+            no_match(
+                format!(
+                    "line {} ({:?}) resembles {:?} but the order has {} of the {} returned",
+                    line + 1,
+                    items[line].sku,
+                    returned_sku.trim(),
+                    held[line],
+                    qty
+                )
+            ),
+        (None, None) =>
+            no_match(
+                format!("no line of the Linnworks order resembles {:?}", returned_sku.trim())
+            ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(sku: &str, quantity: i32) -> MarketplaceItem {
+        MarketplaceItem { sku: sku.to_string(), quantity }
+    }
+
+    fn find(returned_sku: &str, qty: u32, items: &[MarketplaceItem]) -> SkuMatch {
+        best_line(returned_sku, qty, items, &AliasTable::default(), &MatchingConfig::default())
+    }
+
+    /// Confidence of `returned` against a single line holding `line_sku`, `None` for no match.
+    fn confidence(returned: &str, line_sku: &str) -> Option<u8> {
+        let found = find(returned, 1, &[line(line_sku, 1)]);
+        found.line.map(|_| found.confidence)
+    }
+
+    #[test]
+    fn exact() {
+        assert_eq!(confidence("TSHIRT-RED-M", " TSHIRT-RED-M"), Some(EXACT));
+    }
+
+    #[test]
+    fn normalized() {
+        assert_eq!(confidence("TSHIRT-RED-M", "tshirt-red-m"), Some(NORMALIZED));
+    }
+
+    #[test]
+    fn separators_ignored() {
+        assert_eq!(confidence("TSHIRT-RED-M", "tshirt red_m"), Some(SEPARATORS_IGNORED));
+    }
+
+    #[test]
+    fn alias() {
+        let config = MatchingConfig {
+            sku_aliases: [("RED TEE M".to_string(), "TSHIRT-RED-M".to_string())].into(),
+            ..MatchingConfig::default()
+        };
+        let aliases = AliasTable::from_config(&config);
+        let found = best_line("red-tee-m", 1, &[line("TSHIRT-RED-M", 1)], &aliases, &config);
+        assert_eq!((found.line, found.confidence), (Some(0), ALIAS));
+        assert!(found.explanation.ends_with("both are known as \"TSHIRT-RED-M\""), "{}", found.explanation);
+        assert_eq!(confidence("red-tee-m", "TSHIRT-RED-M"), None);
+    }
+
+    #[test]
+    fn size_suffix() {
+        assert_eq!(confidence("TSHIRT-RED-M", "TSHIRT-RED-XL"), Some(SIZE_SUFFIX));
+        assert_eq!(confidence("DRESS-GRN-10", "DRESS-GRN-UK12"), Some(SIZE_SUFFIX));
+    }
+
+    #[test]
+    fn edit_distance_scales_with_the_sku_length() {
+        assert_eq!(confidence("JACKET-BLK-LONG", "JACKET-BLK-LANG"), Some(EDIT_DISTANCE));
+        assert_eq!(confidence("JACKET-BLK-LONG", "JACKET-BLK-LXNX"), Some(EDIT_DISTANCE - EDIT_PENALTY));
+        assert_eq!(confidence("JACKET-BLK-LONG", "JACKET-BLK-XXXX"), None);
+        // one character in two is nothing alike
+        assert_eq!(confidence("S1", "M2"), None);
+        assert_eq!(confidence("ABC", "ABD"), None);
+    }
+
+    #[test]
+    fn picks_the_closest_line() {
+        let items = [line("SOCKS-3PK", 1), line("tshirt red m", 1), line("TSHIRT-RED-M", 1)];
+        let found = find("TSHIRT-RED-M", 1, &items);
+        assert_eq!(found.line, Some(2));
+        assert_eq!(found.confidence, EXACT);
+        assert_eq!(found.explanation, "line 3 of 3 (\"TSHIRT-RED-M\"): same SKU");
+    }
+
+    #[test]
+    fn quantities_add_up_across_lines_of_the_same_sku() {
+        let items = [line("SOCKS-3PK", 3), line("TSHIRT-RED-M", 1), line("TSHIRT-RED-M", 1)];
+        let found = find("TSHIRT-RED-M", 2, &items);
+        assert_eq!(found.line, Some(1));
+        assert_eq!(found.confidence, EXACT);
+    }
+
+    #[test]
+    fn too_few_units_is_no_match() {
+        let items = [line("TSHIRT-RED-M", 1), line("TSHIRT-RED-L", 1)];
+        let found = find("TSHIRT-RED-M", 2, &items);
+        assert_eq!(found.line, None);
+        assert!(found.explanation.contains("the order has 1 of the 2 returned"), "{}", found.explanation);
+    }
+}
@@ -1,71 +1,45 @@
 use actix_web::web;
-use reqwest;
 use serde::{ Deserialize, Serialize };
-use std::fs;
 use chrono::{ FixedOffset, TimeZone };
-use dotenv::dotenv;
-use std::env;
-use serde_json::{ json };
-use log;
 
-use crate::{lmdb::{order::DBOrder, utils::DB}, schema::order_api::{ Orders}};
-
-const BASE_URL: &str = "https://eu-ext.linnworks.net";
+use crate::{
+    config::settings::MatchingConfig,
+    lmdb::{ catalog::DBCatalog, jobs::DBJobs, order::DBOrder, query::DBOrderQuery, utils::DB },
+    schema::{
+        catalog::CatalogEntry,
+        history::{ diff_orders, ChangeContext },
+        jobs::{ JobKind, ReconcileReport },
+        order::Order,
+        order_api::Orders,
+        status::{ ManualConfirmation, MatchType, OrderStatus },
+        sync::MatchPreview,
+    },
+    scripts::{
+        linnworks::LinnworksSession,
+        marketplaces::{ MarketplaceData, MatcherRegistry },
+        sku_match::{ best_line, AliasTable },
+    },
+};
 
 #[derive(Debug, Serialize, Deserialize)]
-struct AuthResponse {
-    Token: String,
-    Server: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-
+#[allow(dead_code)]
 struct OrderItem {
-    SKU: String,
-    Quantity: i32,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct GeneralInfo {
-    ReferenceNum: String,
-    SubSource: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct MarketplaceData {
-    linnwork_id: String,
-    marketplace: String,
-    marketplace_id: String,
-    shopify_id: String,
-    items: Vec<MarketplaceItem>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct MarketplaceItem {
+    #[serde(rename = "SKU")]
     sku: String,
+    #[serde(rename = "Quantity")]
     quantity: i32,
 }
 
-async fn authorize() -> Result<AuthResponse, Box<dyn std::error::Error>> {
-    // dotenv().ok();
-
-    let client = reqwest::Client::new();
-    let res = client
-        .post("https://api.linnworks.net/api/Auth/AuthorizeByApplication")
-        .json(
-            &json!({
-            // "ApplicationId":"f4fba1a1-36d5-472b-b903-7a3c1f1fe1e0",
-            // "ApplicationSecret":"4e55d5ce-9cc5-45d5-a563-e8a9800c781f",
-            // "Token":"4f5b1e4f127d1bacde9468078589abe9",
-        })
-        )
-        .send().await?;
-
-    let auth_response: AuthResponse = res.json().await?;
-    Ok(auth_response)
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(dead_code)]
+struct GeneralInfo {
+    #[serde(rename = "ReferenceNum")]
+    reference_num: String,
+    #[serde(rename = "SubSource")]
+    sub_source: String,
 }
 
+#[allow(dead_code)]
 fn get_utc_date_time(dt: &str) -> String {
     let parts: Vec<&str> = dt.split(' ').collect();
     if parts.len() != 2 {
@@ -79,18 +53,17 @@ fn get_utc_date_time(dt: &str) -> String {
         ::parse_from_str(&format!("{} {}", date_str, time_str), "%Y-%m-%d %H:%M")
         .unwrap();
 
-    let london_offset = FixedOffset::east(0); // GMT in winter
+    let london_offset = FixedOffset::east_opt(0).unwrap(); // GMT in winter
     let london_time = london_offset.from_local_datetime(&naive_datetime).unwrap();
 
     london_time.to_rfc3339()
 }
 
-async fn get_num_order(id: &str, token: &str) -> Result<Orders, Box<dyn std::error::Error>> {
+async fn get_num_order(linnworks: &LinnworksSession, id: &str) -> Result<Orders, Box<dyn std::error::Error>> {
     println!("Fetching order details for ID: {}", id);
-    let client = reqwest::Client::new();
-    let url = format!("{}/api/Orders/GetOrderDetailsByNumOrderId?OrderId={}", BASE_URL, id);
+    let path = format!("/api/Orders/GetOrderDetailsByNumOrderId?OrderId={}", id);
 
-    let res = client.get(&url).header("Authorization", token).send().await?;
+    let res = linnworks.get(&path).await?;
     println!("Response status: {}", res.status());
     
     // Add debugging to see the raw response
@@ -102,162 +75,295 @@ async fn get_num_order(id: &str, token: &str) -> Result<Orders, Box<dyn std::err
     Ok(order)
 }
 
-fn get_debenhams_data(order: &Orders) -> Option<MarketplaceData> {
-    let mut is_debenham = false;
-    let mut deb_id = String::new();
-
-    for note in &order.notes {
-        if note.note.contains("DUX") {
-            deb_id = note.note.split("Marketplace Order ID -").nth(1)?.trim().to_string();
-            is_debenham = true;
-            break;
+/// Copy the marketplace details onto `db_order` when one of the order lines resembles the
+/// SKU returned. Confident matches are accepted, weaker ones are left for manual
+/// confirmation. Nothing of an earlier match is kept. Returns the index of the matched line.
+fn apply_match(
+    db_order: &mut Order,
+    data: &MarketplaceData,
+    catalog: &LineCatalog,
+    matching: &MatchingConfig
+) -> Option<usize> {
+    let returned_sku = db_order.returned_sku.clone().unwrap_or_default();
+    let found = best_line(&returned_sku, db_order.qty.unwrap_or(1), &data.items, &catalog.aliases, matching);
+    println!("SKU match for order {}: {} ({}%)", db_order.order_id, found.explanation, found.confidence);
+    db_order.match_explanation = Some(found.explanation);
+    db_order.matched_line = found.line.map(|i| i + 1);
+    let Some(line) = found.line else {
+        db_order.match_type = Some(MatchType::NoMatch);
+        db_order.match_confidence = None;
+        db_order.matched_sku = None;
+        db_order.offer_sku = None;
+        db_order.manual_confirmation = None;
+        return None;
+    };
+    db_order.marketplace = data.marketplace.clone();
+    db_order.market_place_code = Some(data.marketplace_id.clone());
+    db_order.shopify_id = Some(data.shopify_id.clone());
+    match &catalog.entries[line] {
+        Some(entry) => {
+            db_order.matched_sku = Some(entry.sku.clone());
+            db_order.offer_sku = entry.offer_sku(&data.marketplace).map(str::to_string);
+        }
+        None => {
+            db_order.matched_sku = Some(data.items[line].sku.clone());
+            db_order.offer_sku = None;
         }
     }
-
-    if !is_debenham {
-        return None;
+    db_order.match_confidence = Some(found.confidence);
+    if found.confidence >= matching.auto_accept_confidence {
+        db_order.match_type = Some(MatchType::FullMatch);
+        db_order.manual_confirmation = None;
+        if db_order.status.is_none_or(|s| s == OrderStatus::Received) {
+            db_order.status = Some(OrderStatus::Matched);
+        }
+    } else {
+        println!("🔎 Order {} needs manual confirmation", db_order.order_id);
+        db_order.match_type = Some(MatchType::NeedsReview);
+        db_order.manual_confirmation = Some(ManualConfirmation::Pending);
     }
+    Some(line)
+}
 
-    let items: Vec<MarketplaceItem> = order.items
-        .iter()
-        .map(|item| MarketplaceItem {
-            sku: item.sku.to_lowercase(),
-            quantity: item.quantity,
-        })
-        .collect();
+/// What the SKU catalog knows about the lines of a Linnworks order.
+struct LineCatalog {
+    /// The catalog entry of each line
+    entries: Vec<Option<CatalogEntry>>,
+    /// The config aliases, plus the catalog SKUs of the lines and of the returned SKU
+    aliases: AliasTable,
+}
 
-    let shopify_id = if order.general_info.reference_num.trim().is_empty() {
-        "000".to_string()
-    } else {
-        order.general_info.reference_num.trim().to_string()
+impl LineCatalog {
+    fn lookup(
+        db: &DB,
+        data: &MarketplaceData,
+        returned_sku: &str,
+        matching: &MatchingConfig
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut aliases = AliasTable::from_config(matching);
+        if let Some(entry) = db.resolve_sku(&data.marketplace, returned_sku)? {
+            aliases.add(returned_sku, &entry.sku);
+        }
+        let mut entries = Vec::with_capacity(data.items.len());
+        for item in &data.items {
+            let entry = db.resolve_sku(&data.marketplace, &item.sku)?;
+            if let Some(entry) = &entry {
+                aliases.add(&item.sku, &entry.sku);
+            }
+            entries.push(entry);
+        }
+        Ok(LineCatalog { entries, aliases })
+    }
+}
+
+/// The order before and after matching it against its Linnworks order, and the index of
+/// the matched line. `None` when it isn't in the DB or no marketplace recognises the
+/// Linnworks order. Nothing is written.
+async fn plan_update(db: &DB, linnworks: &LinnworksSession, matchers: &MatcherRegistry, matching: &MatchingConfig, order_id: &str, row_number: &str) -> Result<Option<(Order, Order, Option<usize>)>, Box<dyn std::error::Error>> {
+    println!("Updating order: {}", order_id);
+    let order = get_num_order(linnworks, order_id).await?;
+    println!("Fetched order: {:?}", order);
+    let Some(data) = matchers.detect(&order)? else {
+        return Ok(None);
+    };
+    let Some(before) = db.get_single(order_id.to_string())? else {
+        println!("Order not found in database: {}", row_number);
+        return Ok(None);
     };
+    println!("Found order in database: {}", row_number);
+    let mut after = before.clone();
+    let returned_sku = before.returned_sku.clone().unwrap_or_default();
+    let catalog = LineCatalog::lookup(db, &data, &returned_sku, matching)?;
+    let line = apply_match(&mut after, &data, &catalog, matching);
+    Ok(Some((before, after, line)))
+}
 
-    Some(MarketplaceData {
-        linnwork_id: order.num_order_id.clone().to_string(),
-        marketplace: "Debenhams".to_string(),
-        marketplace_id: deb_id,
-        shopify_id,
-        items,
-    })
+pub async fn update(db:web::Data<DB>, linnworks: &LinnworksSession, matchers: &MatcherRegistry, matching: &MatchingConfig, order_id: &str, row_number: String) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some((_, db_order, _)) = plan_update(&db, linnworks, matchers, matching, order_id, &row_number).await? {
+        println!("Order details: {:?}", db_order);
+        db.put(db_order, &ChangeContext::linnworks_reconcile())?;
+        println!("Successfully updated order in database: {}", row_number);
+    }
+    Ok(())
 }
 
-fn get_secret_sales_data(order: &Orders) -> Option<MarketplaceData> {
-    let mut is_secret_sales = false;
-    let mut ss_id = String::new();
+/// What `update` would change, without writing anything.
+pub async fn preview_update(db: &DB, linnworks: &LinnworksSession, matchers: &MatcherRegistry, matching: &MatchingConfig, order_id: &str, row_number: &str) -> Result<MatchPreview, Box<dyn std::error::Error>> {
+    let preview = match plan_update(db, linnworks, matchers, matching, order_id, row_number).await? {
+        Some((before, after, line)) => MatchPreview {
+            order_id: order_id.to_string(),
+            recognised: true,
+            match_type: after.match_type,
+            matched_line: line.map(|i| i + 1),
+            changes: diff_orders(Some(&before), Some(&after)),
+        },
+        None => MatchPreview {
+            order_id: order_id.to_string(),
+            recognised: false,
+            match_type: None,
+            matched_line: None,
+            changes: Vec::new(),
+        },
+    };
+    Ok(preview)
+}
 
-    for note in &order.notes {
-        if note.note.contains("Marketplace Order ID -") {
-            ss_id = note.note.split("Marketplace Order ID -").nth(1)?.trim().to_string();
-            is_secret_sales = true;
-            break;
-        }
+/// Look up the next `batch_size` unmatched orders in Linnworks, continuing where the
+/// previous run stopped and starting over once the end is reached.
+pub async fn reconcile_pending(db: &DB, linnworks: &LinnworksSession, matchers: &MatcherRegistry, matching: &MatchingConfig, batch_size: usize) -> Result<ReconcileReport, Box<dyn std::error::Error>> {
+    let cursor = db.job_cursor(JobKind::LinnworksReconcile)?;
+    let mut orders = db.unmatched_orders(cursor.as_deref(), batch_size)?;
+    if orders.is_empty() && cursor.is_some() {
+        orders = db.unmatched_orders(None, batch_size)?;
     }
 
-    if !is_secret_sales {
-        return None;
+    let mut report = ReconcileReport::default();
+    let data = web::Data::new(db.clone());
+    for order in &orders {
+        report.checked += 1;
+        let row_number = order.row_number.map(|n| n.to_string()).unwrap_or_default();
+        if let Err(e) = update(data.clone(), linnworks, matchers, matching, &order.order_id, row_number).await {
+            println!("❌ Linnworks lookup for {} failed: {}", order.order_id, e);
+            report.failed += 1;
+            continue;
+        }
+        match db.get_single(order.id.clone())?.and_then(|o| o.match_type) {
+            Some(MatchType::FullMatch) => report.matched += 1,
+            Some(MatchType::NeedsReview) => report.needs_review += 1,
+            _ => {}
+        }
     }
+    db.set_job_cursor(JobKind::LinnworksReconcile, orders.last().map(|o| o.id.as_str()))?;
 
-    let items: Vec<MarketplaceItem> = order.items
-        .iter()
-        .map(|item| MarketplaceItem {
-            sku: item.sku.to_lowercase(),
-            quantity: item.quantity,
-        })
-        .collect();
-
-    let shopify_id = if order.general_info.reference_num.trim().is_empty() {
-        "000".to_string()
-    } else {
-        order.general_info.reference_num.trim().to_string()
-    };
-
-    Some(MarketplaceData {
-        linnwork_id: order.num_order_id.clone().to_string(),
-        marketplace: "Secret Sales".to_string(),
-        marketplace_id: ss_id,
-        shopify_id,
-        items,
-    })
+    if report.checked > 0 && report.failed == report.checked {
+        return Err(format!("all {} Linnworks lookups failed", report.failed).into());
+    }
+    Ok(report)
 }
 
-fn get_matalan_data(order: &Orders) -> Option<MarketplaceData> {
-    let is_matalan = order.general_info.sub_source.trim().to_lowercase() == "mirakl matalan";
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lmdb::{ utils::tests::temp_db, versioned::tests::{ expected_v2, expected_v3 } },
+        schema::catalog::SkuAlias,
+        scripts::marketplaces::MarketplaceItem,
+    };
 
-    if !is_matalan {
-        return None;
+    /// No catalog entries for the lines of `data`, only the config aliases.
+    fn no_catalog(data: &MarketplaceData) -> LineCatalog {
+        LineCatalog { entries: vec![None; data.items.len()], aliases: AliasTable::default() }
     }
 
-    let mat_id = order.general_info.reference_num.clone();
-
-    let items: Vec<MarketplaceItem> = order.items
-        .iter()
-        .map(|item| MarketplaceItem {
-            sku: item.sku.to_lowercase(),
-            quantity: item.quantity,
-        })
-        .collect();
-
-    Some(MarketplaceData {
-        linnwork_id: order.num_order_id.clone().to_string(),
-        marketplace: "Matalan".to_string(),
-        marketplace_id: mat_id,
-        shopify_id: "000".to_string(),
-        items,
-    })
-}
+    fn linnworks_order(lines: &[(&str, i32)]) -> MarketplaceData {
+        MarketplaceData {
+            linnwork_id: "104541".to_string(),
+            marketplace: "Matalan".to_string(),
+            marketplace_id: "MAT-2024-88120".to_string(),
+            shopify_id: "000".to_string(),
+            items: lines
+                .iter()
+                .map(|(sku, quantity)| MarketplaceItem { sku: sku.to_string(), quantity: *quantity })
+                .collect(),
+        }
+    }
 
+    /// `expected_v2` returned `qty` units of DRESS-GRN-10.
+    fn returned(qty: u32) -> Order {
+        Order { qty: Some(qty), ..expected_v2() }
+    }
 
-pub async fn update(db:web::Data<DB>,order_id: &str, row_number: String) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Updating order: {}", order_id);
-    let auth: AuthResponse = authorize().await?;
-    println!("Authorization successful, token: {}", auth.Token);
-    let order = get_num_order(order_id, &auth.Token).await?;
-    println!("Fetched order: {:?}", order);
-    fn update_if_match(
-        db: web::Data<DB>,
-        row_number: &str,
-        data: Option<MarketplaceData>,
-        order_id:String
-    ) -> Result<bool, Box<dyn std::error::Error>> {
-        let mut breaks: bool = false;
-        if let Some(data) = data {
-            if let Some(mut db_order) = db.get_single(order_id.to_string())? {
-                println!("Found order in database: {}", row_number);
-                println!("returned_sku: {:?} and sku: {:?}", db_order.returned_sku, data.items[0].sku);
-                if db_order.returned_sku == Some(data.items[0].sku.clone()) {
-                    println!("Order matched in database with same SKU: {}", data.items[0].sku);
-                    db_order.marketplace = data.marketplace.clone();
-                    db_order.market_place_code = Some(data.marketplace_id.clone());
-                    db_order.shopify_id = Some(data.shopify_id.clone());
-                    db_order.returned_sku = Some(data.items[0].sku.clone());
-                    db_order.match_type = Some("Full Match".to_string());
-                    println!("Successfully updated order in database: {}", row_number);
-                    println!("Order details: {:?}", db_order);
-                    db.put(db_order.clone())?;
-                    breaks = true;
-                } else {
-                    db_order.match_type = Some("None".to_string());
-                }
-                db.put(db_order)?;
-            } else {
-                println!("Order not found in database: {}", row_number);
-                breaks = true;
-            }
+    /// `expected_v3`, left for review by an earlier run with the offer SKU of that match.
+    fn in_review() -> Order {
+        Order {
+            offer_sku: Some("SS-JB-32".to_string()),
+            match_type: Some(MatchType::NeedsReview),
+            match_confidence: Some(75),
+            manual_confirmation: Some(ManualConfirmation::Pending),
+            status: Some(OrderStatus::Received),
+            ..expected_v3()
         }
-        Ok(breaks)
     }
-    let ans = update_if_match(db.clone(), &row_number, get_debenhams_data(&order),order_id.to_string())?;
-    if ans {
-        return Ok(());
+
+    #[test]
+    fn the_closest_line_is_matched() {
+        let data = linnworks_order(&[("SOCKS-3PK", 1), (" dress-grn-10 ", 1), ("DRESS-GRN-10", 2)]);
+        let mut order = returned(2);
+        assert_eq!(apply_match(&mut order, &data, &no_catalog(&data), &MatchingConfig::default()), Some(2));
+        assert_eq!(order.matched_line, Some(3));
+        assert_eq!(order.matched_sku.as_deref(), Some("DRESS-GRN-10"));
+        assert_eq!(order.match_confidence, Some(100));
+        assert_eq!(order.match_type, Some(MatchType::FullMatch));
+        assert_eq!(order.status, Some(OrderStatus::Matched));
+    }
+
+    #[test]
+    fn matches_below_auto_accept_confidence_wait_for_review() {
+        let data = linnworks_order(&[("DRESS-GRN-12", 2)]);
+        let mut order = returned(2);
+        assert_eq!(apply_match(&mut order, &data, &no_catalog(&data), &MatchingConfig::default()), Some(0));
+        assert_eq!(order.match_type, Some(MatchType::NeedsReview));
+        assert_eq!(order.manual_confirmation, Some(ManualConfirmation::Pending));
+        assert_eq!(order.status, Some(OrderStatus::Received));
+
+        // the same match is accepted once the bar is low enough
+        let matching = MatchingConfig { auto_accept_confidence: 75, ..MatchingConfig::default() };
+        let mut order = returned(2);
+        apply_match(&mut order, &data, &no_catalog(&data), &matching);
+        assert_eq!(order.match_type, Some(MatchType::FullMatch));
+        assert_eq!(order.status, Some(OrderStatus::Matched));
     }
-    let ans = update_if_match(db.clone(), &row_number, get_secret_sales_data(&order),order_id.to_string())?;
-    if ans {
-        return Ok(());
+
+    #[test]
+    fn full_match_clears_an_earlier_review() {
+        let mut order = in_review();
+        let data = linnworks_order(&[("SOCKS-3PK", 1), ("JEANS BLUE 32", 1)]);
+
+        assert_eq!(apply_match(&mut order, &data, &no_catalog(&data), &MatchingConfig::default()), Some(1));
+        assert_eq!(order.match_type, Some(MatchType::FullMatch));
+        assert_eq!(order.manual_confirmation, None);
+        assert_eq!(order.matched_sku.as_deref(), Some("JEANS BLUE 32"));
+        assert_eq!(order.offer_sku, None);
+        assert_eq!(order.matched_line, Some(2));
+        assert_eq!(order.status, Some(OrderStatus::Matched));
     }
-    let ans = update_if_match(db, &row_number, get_matalan_data(&order),order_id.to_string())?;
-    if ans {
-        return Ok(());
+
+    #[test]
+    fn no_match_clears_the_earlier_match() {
+        let mut order = in_review();
+        let data = linnworks_order(&[("SOCKS-3PK", 1)]);
+
+        assert_eq!(apply_match(&mut order, &data, &no_catalog(&data), &MatchingConfig::default()), None);
+        assert_eq!(order.match_type, Some(MatchType::NoMatch));
+        assert_eq!(order.match_confidence, None);
+        assert_eq!(order.manual_confirmation, None);
+        assert_eq!(order.matched_sku, None);
+        assert_eq!(order.offer_sku, None);
+        assert_eq!(order.matched_line, None);
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn catalog_skus_fill_the_matched_and_offer_sku() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+        db.insert_catalog_entry(CatalogEntry {
+            sku: "DRESS-GREEN".to_string(),
+            title: None,
+            aliases: vec![
+                SkuAlias { marketplace: None, sku: "LW-DRESS-GRN".to_string() },
+                SkuAlias { marketplace: Some("Matalan".to_string()), sku: "MAT-DG-10".to_string() }
+            ],
+            updated_at: String::new(),
+        }).unwrap();
+        // returned under our SKU, sold under the Linnworks one
+        let mut order = Order { returned_sku: Some("dress green".to_string()), ..returned(1) };
+        let data = linnworks_order(&[("SOCKS-3PK", 1), ("LW-DRESS-GRN", 1)]);
+        let matching = MatchingConfig::default();
+        let catalog = LineCatalog::lookup(&db, &data, "dress green", &matching).unwrap();
+
+        assert_eq!(apply_match(&mut order, &data, &catalog, &matching), Some(1));
+        assert_eq!(order.match_type, Some(MatchType::FullMatch));
+        assert_eq!(order.matched_sku.as_deref(), Some("DRESS-GREEN"));
+        assert_eq!(order.offer_sku.as_deref(), Some("MAT-DG-10"));
+    }
 }
@@ -1,180 +1,225 @@
-use jsonwebtoken::{ Algorithm, EncodingKey, Header };
-use reqwest::Client;
-use serde::{ Deserialize, Serialize };
-use serde_json::{ Value };
-use std::error::Error;
-use std::time::{ SystemTime, UNIX_EPOCH };
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    iss: String,
-    scope: String,
-    aud: String,
-    exp: usize,
-    iat: usize,
-}
-use std::sync::Mutex;
-use once_cell::sync::Lazy;
-
-use crate::schema::order::Order;
-
-static TOKEN_CACHE: Lazy<Mutex<Option<(String, usize)>>> = Lazy::new(|| Mutex::new(None));
-
-pub async fn generate_token(
-    client_email: &str,
-    private_key: &str
-) -> Result<String, Box<dyn Error>> {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as usize;
-
-    let claims = Claims {
-        iss: client_email.to_string(),
-        scope: "https://www.googleapis.com/auth/spreadsheets".to_string(),
-        aud: "https://oauth2.googleapis.com/token".to_string(),
-        exp: now + 3600,
-        iat: now,
-    };
-
-    let jwt = jsonwebtoken::encode(
-        &Header::new(Algorithm::RS256),
-        &claims,
-        &EncodingKey::from_rsa_pem(private_key.as_bytes())?
-    )?;
-
-    let response = Client::new()
-        .post("https://oauth2.googleapis.com/token")
-        .form(
-            &[
-                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
-                ("assertion", &jwt),
-            ]
-        )
-        .send().await?
-        .json::<Value>().await?;
-
-    response["access_token"]
-        .as_str()
-        .map(|s| s.to_string())
-        .ok_or_else(|| "Failed to get access token".into())
-}
+use crate::{
+    schema::{ order::{ normalize_date, Order, RowError }, status::OrderStatus },
+    scripts::columns::{ Column, HeaderMap },
+};
 
-pub async fn get_or_generate_token(
-    client_email: &str,
-    private_key: &str
-) -> Result<String, Box<dyn std::error::Error>> {
-    let now = std::time::SystemTime
-        ::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs() as usize;
-
-    {
-        let cache = TOKEN_CACHE.lock().unwrap();
-        if let Some((token, exp)) = &*cache {
-            if *exp > now + 60 {
-                // 60 sec buffer before expiry
-                return Ok(token.clone());
-            }
+impl Order {
+    /// Sheet text for one column of this order.
+    pub fn sheet_value(&self, column: Column) -> String {
+        match column {
+            Column::Marketplace => self.marketplace.clone(),
+            Column::ReturnedSku => self.returned_sku.clone().unwrap_or_default(),
+            Column::OrderId => self.order_id.clone(),
+            Column::RefundRequested => (if self.boolean { "Y" } else { "N" }).to_string(),
+            Column::Date => self.date.clone(),
+            Column::Refunded => "FALSE".to_string(),
+            Column::MatchType => self.match_type.map(|m| m.label().to_string()).unwrap_or_default(),
+            Column::RowId => self.id.clone(),
+            Column::ShopifyId => self.shopify_id.clone().unwrap_or_default(),
+            Column::OfferSku => self.offer_sku.clone().unwrap_or_default(),
+            Column::MatchedSku => self.matched_sku.clone().unwrap_or_default(),
+            Column::ManualConfirmation =>
+                self.manual_confirmation.map(|v| v.label().to_string()).unwrap_or_default(),
+            Column::Status => self.status.map(|v| v.label().to_string()).unwrap_or_default(),
+            Column::Qty => self.qty.map(|v| v.to_string()).unwrap_or_default(),
+            Column::MainUpdated => self.main_updated.map(|v| v.label().to_string()).unwrap_or_default(),
         }
     }
 
-    // Generate new token
-    let token = generate_token(client_email, private_key).await?;
-
-    // Save with expiry
-    {
-        let mut cache = TOKEN_CACHE.lock().unwrap();
-        *cache = Some((token.clone(), now + 3600));
+    /// Inverse of `sheet_value` for the columns a sync may pull from the sheet.
+    pub fn set_sheet_value(&mut self, column: Column, value: &str) {
+        let text = || Some(value.to_string()).filter(|v| !v.is_empty());
+        match column {
+            Column::Marketplace => {
+                self.marketplace = value.to_string();
+            }
+            Column::ReturnedSku => {
+                self.returned_sku = Some(value.to_string());
+            }
+            Column::Date => {
+                self.date = value.to_string();
+            }
+            Column::MatchType => {
+                self.match_type = value.parse().ok();
+            }
+            Column::ShopifyId => {
+                self.shopify_id = text();
+            }
+            Column::OfferSku => {
+                self.offer_sku = text();
+            }
+            Column::MatchedSku => {
+                self.matched_sku = text();
+            }
+            Column::ManualConfirmation => {
+                self.manual_confirmation = value.parse().ok();
+            }
+            Column::Status => {
+                self.status = value.parse().ok();
+            }
+            Column::Qty => {
+                self.qty = value.parse().ok();
+            }
+            Column::MainUpdated => {
+                self.main_updated = value.parse().ok();
+            }
+            // identity and refund flags are never pulled one at a time
+            Column::OrderId | Column::RefundRequested | Column::Refunded | Column::RowId => {}
+        }
     }
 
-    Ok(token)
-}
-
-impl Order {
-    pub async fn to_sheet1_row(order: &Order) -> Vec<String> {
-        vec![
-            "none".to_string(), // CHANNEL VLOOKUP
-            order.marketplace.to_string(), // #REF!
-            "none".to_string(), // SKU
-            order.returned_sku.clone().unwrap(), // #REF!
-            "none".to_string(), // BIN RACK
-            order.order_id.clone(),
-            "none".to_string(), // RETURN REASON
-            "Y".to_string(), // REFUND YES
-            order.date.clone(), // DATE
-            "FALSE".to_string(), // REFUNDED checkbox
-            "none".to_string(), // stock added
-            "none".to_string(), // refund date
-            "none".to_string(), // MATCH TYPE
-            "none".to_string() // Fraser Classification
-        ]
+    /// New Sheet1 row laid out according to `header`.
+    pub fn to_sheet1_row(&self, header: &HeaderMap) -> Vec<String> {
+        header.append_row(|column| self.sheet_value(column))
     }
 
-    // pub fn to_sheet2_row(&self) -> Vec<String> {
-    //     vec![
-    //         self.return_order.map(|v| v.to_string()).unwrap_or_default(),
-    //         self.shopify_id.clone().unwrap_or_default(),
-    //         self.marketplace.clone(),
-    //         self.returned_sku.clone().unwrap_or_default(),
-    //         self.offer_sku.clone().unwrap_or_default(),
-    //         self.matched_sku.clone().unwrap_or_default(),
-    //         self.match_type.clone().unwrap_or_default(),
-    //         self.row_number.map(|v| v.to_string()).unwrap_or_default(),
-    //         self.manual_confirmation.clone().unwrap_or_default(),
-    //         self.status.clone().unwrap_or_default(),
-    //         "".to_string(), // MARKETPLACE col duplicate
-    //         self.qty.map(|v| v.to_string()).unwrap_or_default(),
-    //         self.main_updated.clone().unwrap_or_default()
-    //     ]
-    // }
+    /// New reconciliation row laid out according to `header`.
+    pub fn to_sheet2_row(&self, header: &HeaderMap) -> Vec<String> {
+        header.append_row(|column| self.sheet_value(column))
+    }
 }
 
 impl Order {
-    pub async fn from_sheets(
+    /// Build an order from Sheet1 row `i`, reading cells by header name. A row that can't
+    /// be an order comes back with every problem found. `marketplaces` lists the accepted
+    /// marketplace names, any name is accepted when it is empty. The reconciliation fields
+    /// come from Sheet2 in a later pass.
+    pub fn from_sheets(
         i: usize,
+        header: &HeaderMap,
         sheet1_row: &[String],
-        _sheet2_row: Option<&[String]>
-    ) -> Option<Self> {
-        let refund_yes_or_no = sheet1_row.get(7).cloned().unwrap_or_default();
-        let refunded = sheet1_row.get(9).cloned().unwrap_or_default();
+        marketplaces: &[String]
+    ) -> Result<Self, Vec<RowError>> {
+        let cell = |column| header.cell(sheet1_row, column).unwrap_or_default().to_string();
+        let mut errors = Vec::new();
+        let order_id = cell(Column::OrderId);
+        if order_id.trim().is_empty() {
+            errors.push(RowError::MissingOrderId);
+        }
+        let returned_sku = cell(Column::ReturnedSku);
+        if returned_sku.trim().is_empty() {
+            errors.push(RowError::EmptySku);
+        }
+        let date = cell(Column::Date);
+        if normalize_date(&date).is_none() {
+            errors.push(RowError::BadDate { value: date.clone() });
+        }
+        let marketplace = cell(Column::Marketplace);
+        let known = marketplaces.iter().any(|m| m.eq_ignore_ascii_case(marketplace.trim()));
+        if !marketplaces.is_empty() && !known {
+            errors.push(RowError::UnknownMarketplace { value: marketplace.clone() });
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        // rows written by the service carry their id, new ones get one here
+        let id = Some(cell(Column::RowId))
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
         let mut order = Order {
-            id: uuid::Uuid::new_v4().to_string(),
-            marketplace: sheet1_row.get(1).unwrap().to_string(),
-            order_id: sheet1_row.get(5).cloned().unwrap_or_default(),
+            id,
+            marketplace,
+            order_id,
             return_order: None,
             shopify_id: None,
             market_place_code: None,
-            returned_sku: Some(sheet1_row.get(3).cloned().unwrap_or_default()),
+            returned_sku: Some(returned_sku),
             offer_sku: None,
             matched_sku: None,
-            match_type: sheet1_row.get(12).cloned(),
+            match_type: header.cell(sheet1_row, Column::MatchType).and_then(|v| v.parse().ok()),
+            match_confidence: None,
+            match_explanation: None,
+            matched_line: None,
             row_number: Some(i),
             manual_confirmation: None,
-            status: None,
+            status: Some(OrderStatus::Received),
             qty: None,
             main_updated: None,
-            date: sheet1_row.get(8).cloned().unwrap_or_default(),
+            date,
             created_at: chrono::Utc::now().to_rfc3339(),
             updated_at: chrono::Utc::now().to_rfc3339(),
             boolean: false, // not needed
         };
-        if refund_yes_or_no == "Y" && refunded == "FALSE" {
-            order.boolean = true; // Set boolean to false if both conditions are met
+        let refund_requested = cell(Column::RefundRequested).eq_ignore_ascii_case("Y");
+        let refunded = cell(Column::Refunded).eq_ignore_ascii_case("FALSE");
+        if refund_requested && refunded {
+            order.boolean = true; // refund asked for but not yet given
         }
-        // if let Some(row2) = sheet2_row {
-        //     order.marketplace = row2.get(2).cloned().unwrap_or(order.marketplace);
-        //     order.return_order = row2.get(0).and_then(|s| s.parse().ok());
-        //     order.shopify_id = row2.get(1).cloned();
-        //     order.returned_sku = row2.get(3).cloned();
-        //     order.offer_sku = row2.get(4).cloned();
-        //     order.matched_sku = row2.get(5).cloned();
-        //     order.match_type = row2.get(6).cloned().or(order.match_type);
-        //     order.row_number = row2.get(7).and_then(|s| s.parse().ok());
-        //     order.manual_confirmation = row2.get(8).cloned();
-        //     order.status = row2.get(9).cloned();
-        //     order.qty = row2.get(11).and_then(|s| s.parse().ok());
-        //     order.main_updated = row2.get(12).cloned();
-        // }
-
-        Some(order)
+        Ok(order)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripts::columns::SHEET1_COLUMNS;
+
+    fn cells(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn header() -> HeaderMap {
+        let cells = cells(&["MARKETPLACE", "RETURNED SKU", "ORDER ID", "DATE"]);
+        HeaderMap::resolve(SHEET1_COLUMNS, &cells).unwrap()
+    }
+
+    fn marketplaces() -> Vec<String> {
+        cells(&["Debenhams", "Matalan"])
+    }
+
+    fn parse(row: &[&str], marketplaces: &[String]) -> Result<Order, Vec<RowError>> {
+        Order::from_sheets(3, &header(), &cells(row), marketplaces)
+    }
+
+    #[test]
+    fn a_valid_row_becomes_a_received_order() {
+        let order = parse(&["debenhams", "TSHIRT-RED-L", "104522", "01/05/2024"], &marketplaces()).unwrap();
+        assert_eq!(order.order_id, "104522");
+        assert_eq!(order.returned_sku.as_deref(), Some("TSHIRT-RED-L"));
+        assert_eq!(order.row_number, Some(3));
+        assert_eq!(order.status, Some(OrderStatus::Received));
+        assert!(!order.id.is_empty());
+    }
+
+    #[test]
+    fn each_missing_field_is_reported() {
+        let row = |id, sku, date| parse(&["Matalan", sku, id, date], &marketplaces()).unwrap_err();
+        assert_eq!(row("", "SKU-1", "2024-05-01"), vec![RowError::MissingOrderId]);
+        assert_eq!(row("104522", "  ", "2024-05-01"), vec![RowError::EmptySku]);
+        assert_eq!(
+            row("104522", "SKU-1", "May 1st"),
+            vec![RowError::BadDate { value: "May 1st".to_string() }]
+        );
+    }
+
+    #[test]
+    fn unknown_marketplaces_are_rejected_unless_none_are_configured() {
+        let row = ["Woolworths", "SKU-1", "104522", "2024-05-01"];
+        assert_eq!(
+            parse(&row, &marketplaces()).unwrap_err(),
+            vec![RowError::UnknownMarketplace { value: "Woolworths".to_string() }]
+        );
+        assert!(parse(&row, &[]).is_ok());
+    }
+
+    #[test]
+    fn all_errors_of_a_row_are_reported_together() {
+        let errors = parse(&["Woolworths", "", "", "yesterday"], &marketplaces()).unwrap_err();
+        assert_eq!(errors, vec![
+            RowError::MissingOrderId,
+            RowError::EmptySku,
+            RowError::BadDate { value: "yesterday".to_string() },
+            RowError::UnknownMarketplace { value: "Woolworths".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn a_row_shorter_than_the_header_is_rejected_without_panicking() {
+        let errors = parse(&["Debenhams", "SKU-1"], &marketplaces()).unwrap_err();
+        assert_eq!(errors, vec![
+            RowError::MissingOrderId,
+            RowError::BadDate { value: String::new() },
+        ]);
+    }
+}
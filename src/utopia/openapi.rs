@@ -1,6 +1,28 @@
 use utoipa::OpenApi;
 
-use crate::{routes::order::*, schema::order::Order};
+use crate::{
+    routes::{ catalog::*, jobs::*, order::*, outbox::*, sync::* },
+    schema::{
+        catalog::{ CatalogEntry, CatalogImportReport, RejectedCatalogLine, SkuAlias },
+        history::{ ChangeAction, ChangeSource, FieldChange, HistoryEntry },
+        jobs::{ JobKind, JobOutcome, JobRun, JobStatus, JobTrigger, ReconcileReport },
+        order::{ Order, RowError },
+        order_query::{ OrderPage, OrderSort, SortDirection },
+        status::{ MainUpdated, ManualConfirmation, MatchType, OrderStatus },
+        outbox::{ FlushReport, OutboxEntry, SheetOp },
+        sync::{
+            MatchPreview,
+            OrderDiff,
+            RejectedRow,
+            ResolveConflict,
+            SheetRowDiff,
+            SyncConflict,
+            SyncPreview,
+            SyncReport,
+            SyncSide,
+        },
+    },
+};
 
 #[derive(OpenApi)]
 #[openapi(
@@ -10,10 +32,66 @@ use crate::{routes::order::*, schema::order::Order};
         get_order,
         list_orders,
         update_order,
-        delete_order
+        delete_order,
+        list_deleted_orders,
+        restore_order,
+        get_order_history,
+        run_sync,
+        list_conflicts,
+        resolve_conflict,
+        list_outbox,
+        list_dead_letters,
+        retry_dead_letter,
+        discard_dead_letter,
+        list_jobs,
+        run_job,
+        list_job_runs,
+        list_catalog,
+        get_catalog_entry,
+        insert_catalog_entry,
+        replace_catalog_entry,
+        delete_catalog_entry,
+        import_catalog
 
     ),
-    components(schemas(Order))
+    components(
+        schemas(
+            Order,
+            OrderPage,
+            OrderSort,
+            SortDirection,
+            OrderStatus,
+            MatchType,
+            ManualConfirmation,
+            MainUpdated,
+            HistoryEntry,
+            FieldChange,
+            ChangeAction,
+            ChangeSource,
+            SyncConflict,
+            SyncReport,
+            RejectedRow,
+            RowError,
+            SyncSide,
+            SyncPreview,
+            OrderDiff,
+            SheetRowDiff,
+            MatchPreview,
+            ResolveConflict,
+            OutboxEntry,
+            SheetOp,
+            FlushReport,
+            JobKind,
+            JobTrigger,
+            JobOutcome,
+            JobRun,
+            JobStatus,
+            ReconcileReport,
+            CatalogEntry,
+            SkuAlias,
+            CatalogImportReport,
+            RejectedCatalogLine
+        )
+    )
 )]
-
 pub struct ApiDoc;
\ No newline at end of file